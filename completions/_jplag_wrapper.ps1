@@ -25,13 +25,30 @@ Register-ArgumentCompleter -Native -CommandName 'jplag_wrapper' -ScriptBlock {
             [CompletionResult]::new('--log-level', '--log-level', [CompletionResultType]::ParameterName, 'Log Level to use')
             [CompletionResult]::new('-c', '-c', [CompletionResultType]::ParameterName, 'Specify the config toml file to look for if you don''t want to use the default config.toml')
             [CompletionResult]::new('--config', '--config', [CompletionResultType]::ParameterName, 'Specify the config toml file to look for if you don''t want to use the default config.toml')
-            [CompletionResult]::new('-s', '-s', [CompletionResultType]::ParameterName, 'Where the input file can be found')
-            [CompletionResult]::new('--source-zip', '--source-zip', [CompletionResultType]::ParameterName, 'Where the input file can be found')
+            [CompletionResult]::new('-s', '-s', [CompletionResultType]::ParameterName, 'Where the input file can be found, can be repeated')
+            [CompletionResult]::new('--source-zip', '--source-zip', [CompletionResultType]::ParameterName, 'Where the input file can be found, can be repeated')
             [CompletionResult]::new('-t', '-t', [CompletionResultType]::ParameterName, 'Where to put the results')
             [CompletionResult]::new('--target-dir', '--target-dir', [CompletionResultType]::ParameterName, 'Where to put the results')
             [CompletionResult]::new('--tmp-dir', '--tmp-dir', [CompletionResultType]::ParameterName, 'Where to put the temporary files')
             [CompletionResult]::new('-i', '-i', [CompletionResultType]::ParameterName, 'Where to find the ignore-file')
             [CompletionResult]::new('--ignore-file', '--ignore-file', [CompletionResultType]::ParameterName, 'Where to find the ignore-file')
+            [CompletionResult]::new('--base-code', '--base-code', [CompletionResultType]::ParameterName, 'Where to find the base code (the template handed out to the students), either a dir or an archive')
+            [CompletionResult]::new('--max-nesting-depth', '--max-nesting-depth', [CompletionResultType]::ParameterName, 'How deep archives inside of extracted submissions are extracted')
+            [CompletionResult]::new('--jobs', '--jobs', [CompletionResultType]::ParameterName, 'How many submissions are extracted in parallel')
+            [CompletionResult]::new('--max-submission-bytes', '--max-submission-bytes', [CompletionResultType]::ParameterName, 'Max uncompressed bytes a single submission may expand to')
+            [CompletionResult]::new('--max-entries', '--max-entries', [CompletionResultType]::ParameterName, 'Max files and dirs a single submission may contain')
+            [CompletionResult]::new('--max-compression-ratio', '--max-compression-ratio', [CompletionResultType]::ParameterName, 'Max ratio between the uncompressed size of a submission and its archive')
+            [CompletionResult]::new('--max-total-bytes', '--max-total-bytes', [CompletionResultType]::ParameterName, 'Max uncompressed bytes of all submissions together')
+            [CompletionResult]::new('--password', '--password', [CompletionResultType]::ParameterName, 'Password to try for encrypted zip, 7z and rar submissions, can be repeated')
+            [CompletionResult]::new('--cache-dir', '--cache-dir', [CompletionResultType]::ParameterName, 'Where to cache extracted submissions, to restore unchanged ones on the next run')
+            [CompletionResult]::new('--multi-archive-policy', '--multi-archive-policy', [CompletionResultType]::ParameterName, 'What to do with a submission containing more than one archive')
+            [CompletionResult]::new('--duplicate-policy', '--duplicate-policy', [CompletionResultType]::ParameterName, 'What to do, if a submission with the same name is in more than one source')
+            [CompletionResult]::new('--layout', '--layout', [CompletionResultType]::ParameterName, 'How the submissions are laid out in the `{{source_zip}}`')
+            [CompletionResult]::new('--git-deadline', '--git-deadline', [CompletionResultType]::ParameterName, 'Check out the last commit before this deadline of each git repository, in any format git understands (e.g. `2024-05-01 23:59`)')
+            [CompletionResult]::new('--git-ref', '--git-ref', [CompletionResultType]::ParameterName, 'Check out this tag or branch of each git repository (combined with `--git-deadline`, the last commit of it before the deadline)')
+            [CompletionResult]::new('--old-submission', '--old-submission', [CompletionResultType]::ParameterName, 'Where old submissions (e.g. of previous semesters) can be found, can be repeated')
+            [CompletionResult]::new('--deadline', '--deadline', [CompletionResultType]::ParameterName, 'The deadline of the assignment, submissions after it are late (e.g. `2024-05-01T23:59:00+02:00`, or `2024-05-01 23:59`, which is taken as UTC)')
+            [CompletionResult]::new('--timestamps', '--timestamps', [CompletionResultType]::ParameterName, 'A CSV with the submission time of each student, `student,timestamp` per line')
             [CompletionResult]::new('-j', '-j', [CompletionResultType]::ParameterName, 'Where the jplag jar can be found')
             [CompletionResult]::new('--jplag-jar', '--jplag-jar', [CompletionResultType]::ParameterName, 'Where the jplag jar can be found')
             [CompletionResult]::new('--init', '--init', [CompletionResultType]::ParameterName, 'Initialize the config, will create (or override!) `config.toml` with all values and fill it with the defaults')
@@ -39,11 +56,20 @@ Register-ArgumentCompleter -Native -CommandName 'jplag_wrapper' -ScriptBlock {
             [CompletionResult]::new('-p', '-p', [CompletionResultType]::ParameterName, 'Set to not remove `{{tmp_dir}}` when the program finishes')
             [CompletionResult]::new('--preserve-tmp-dir', '--preserve-tmp-dir', [CompletionResultType]::ParameterName, 'Set to not remove `{{tmp_dir}}` when the program finishes')
             [CompletionResult]::new('--ignore-output', '--ignore-output', [CompletionResultType]::ParameterName, 'Set to ignore the output of jplag')
+            [CompletionResult]::new('--stream', '--stream', [CompletionResultType]::ParameterName, 'Set to extract the archives of the submissions directly from the `{{source_zip}}`, instead of unzipping them to `{{tmp_dir}}` first')
+            [CompletionResult]::new('--keep-loose-files', '--keep-loose-files', [CompletionResultType]::ParameterName, 'Set to keep files, which are no archives, instead of removing them')
+            [CompletionResult]::new('--keep-non-ascii', '--keep-non-ascii', [CompletionResultType]::ParameterName, 'Set to keep non-ASCII characters (e.g. umlauts) in file names')
+            [CompletionResult]::new('--flatten-wrapper-dirs', '--flatten-wrapper-dirs', [CompletionResultType]::ParameterName, 'Set to collapse directories, which only contain a single directory, e.g. `alice/Assignment3/Assignment3/src` becomes `alice/src`')
+            [CompletionResult]::new('--salvage-corrupt', '--salvage-corrupt', [CompletionResultType]::ParameterName, 'Set to keep the readable entries of corrupt or truncated zips and tars, instead of dropping the submission')
+            [CompletionResult]::new('--anonymize', '--anonymize', [CompletionResultType]::ParameterName, 'Set to rename every submission to a pseudonym (e.g. `student_3f9a1c2b7d4e8a60`), before jplag runs, so the results contain no names')
+            [CompletionResult]::new('--only-late', '--only-late', [CompletionResultType]::ParameterName, 'Set to only prepare the late submissions, the others are listed in the summary')
+            [CompletionResult]::new('--exclude-late', '--exclude-late', [CompletionResultType]::ParameterName, 'Set to only prepare the submissions in time, the late ones are listed in the summary')
             [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
             [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
             [CompletionResult]::new('-V', '-V ', [CompletionResultType]::ParameterName, 'Print version')
             [CompletionResult]::new('--version', '--version', [CompletionResultType]::ParameterName, 'Print version')
             [CompletionResult]::new('complete', 'complete', [CompletionResultType]::ParameterValue, 'complete')
+            [CompletionResult]::new('deanonymize', 'deanonymize', [CompletionResultType]::ParameterValue, 'Restore the names in the results of an anonymized run, only for staff, which may see them')
             [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
             break
         }
@@ -52,14 +78,27 @@ Register-ArgumentCompleter -Native -CommandName 'jplag_wrapper' -ScriptBlock {
             [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help')
             break
         }
+        'jplag_wrapper;deanonymize' {
+            [CompletionResult]::new('-m', '-m', [CompletionResultType]::ParameterName, 'The mapping of the pseudonyms, written by the anonymized run')
+            [CompletionResult]::new('--mapping', '--mapping', [CompletionResultType]::ParameterName, 'The mapping of the pseudonyms, written by the anonymized run')
+            [CompletionResult]::new('-o', '-o', [CompletionResultType]::ParameterName, 'Where to write the deanonymized results')
+            [CompletionResult]::new('--output', '--output', [CompletionResultType]::ParameterName, 'Where to write the deanonymized results')
+            [CompletionResult]::new('-h', '-h', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
+            [CompletionResult]::new('--help', '--help', [CompletionResultType]::ParameterName, 'Print help (see more with ''--help'')')
+            break
+        }
         'jplag_wrapper;help' {
             [CompletionResult]::new('complete', 'complete', [CompletionResultType]::ParameterValue, 'complete')
+            [CompletionResult]::new('deanonymize', 'deanonymize', [CompletionResultType]::ParameterValue, 'Restore the names in the results of an anonymized run, only for staff, which may see them')
             [CompletionResult]::new('help', 'help', [CompletionResultType]::ParameterValue, 'Print this message or the help of the given subcommand(s)')
             break
         }
         'jplag_wrapper;help;complete' {
             break
         }
+        'jplag_wrapper;help;deanonymize' {
+            break
+        }
         'jplag_wrapper;help;help' {
             break
         }
@@ -19,12 +19,18 @@ _jplag_wrapper() {
             jplag_wrapper,complete)
                 cmd="jplag_wrapper__complete"
                 ;;
+            jplag_wrapper,deanonymize)
+                cmd="jplag_wrapper__deanonymize"
+                ;;
             jplag_wrapper,help)
                 cmd="jplag_wrapper__help"
                 ;;
             jplag_wrapper__help,complete)
                 cmd="jplag_wrapper__help__complete"
                 ;;
+            jplag_wrapper__help,deanonymize)
+                cmd="jplag_wrapper__help__deanonymize"
+                ;;
             jplag_wrapper__help,help)
                 cmd="jplag_wrapper__help__help"
                 ;;
@@ -35,7 +41,7 @@ _jplag_wrapper() {
 
     case "${cmd}" in
         jplag_wrapper)
-            opts="-l -c -s -t -p -i -j -h -V --init --log-level --abort-on-err --config --source-zip --target-dir --tmp-dir --preserve-tmp-dir --ignore-file --ignore-output --jplag-jar --help --version [ADD_SUB_DIRS]... [JPLAG_ARGS]... complete help"
+            opts="-l -c -s -t -p -i -j -h -V --init --log-level --abort-on-err --config --source-zip --target-dir --tmp-dir --preserve-tmp-dir --ignore-file --base-code --ignore-output --max-nesting-depth --jobs --max-submission-bytes --max-entries --max-compression-ratio --max-total-bytes --password --stream --keep-loose-files --keep-non-ascii --flatten-wrapper-dirs --salvage-corrupt --cache-dir --multi-archive-policy --duplicate-policy --layout --git-deadline --git-ref --old-submission --anonymize --deadline --only-late --exclude-late --timestamps --jplag-jar --help --version [ADD_SUB_DIRS]... [JPLAG_ARGS]... complete deanonymize help"
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 1 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
@@ -85,6 +91,74 @@ _jplag_wrapper() {
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
                     ;;
+                --base-code)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --max-nesting-depth)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --jobs)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --max-submission-bytes)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --max-entries)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --max-compression-ratio)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --max-total-bytes)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --password)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --cache-dir)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --multi-archive-policy)
+                    COMPREPLY=($(compgen -W "reject merge numbered newest largest" -- "${cur}"))
+                    return 0
+                    ;;
+                --duplicate-policy)
+                    COMPREPLY=($(compgen -W "error suffix newest" -- "${cur}"))
+                    return 0
+                    ;;
+                --layout)
+                    COMPREPLY=($(compgen -W "auto plain moodle ilias canvas blackboard github-classroom" -- "${cur}"))
+                    return 0
+                    ;;
+                --git-deadline)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --git-ref)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --old-submission)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --deadline)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --timestamps)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
                 --jplag-jar)
                     COMPREPLY=($(compgen -f "${cur}"))
                     return 0
@@ -114,8 +188,38 @@ _jplag_wrapper() {
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
+        jplag_wrapper__deanonymize)
+            opts="-m -o -h --mapping --output --help <RESULTS>"
+            if [[ ${cur} == -* || ${COMP_CWORD} -eq 2 ]] ; then
+                COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+                return 0
+            fi
+            case "${prev}" in
+                --mapping)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                -m)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                --output)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                -o)
+                    COMPREPLY=($(compgen -f "${cur}"))
+                    return 0
+                    ;;
+                *)
+                    COMPREPLY=()
+                    ;;
+            esac
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
         jplag_wrapper__help)
-            opts="complete help"
+            opts="complete deanonymize help"
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 2 ]] ; then
                 COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
                 return 0
@@ -142,6 +246,20 @@ _jplag_wrapper() {
             COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
             return 0
             ;;
+        jplag_wrapper__help__deanonymize)
+            opts=""
+            if [[ ${cur} == -* || ${COMP_CWORD} -eq 3 ]] ; then
+                COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+                return 0
+            fi
+            case "${prev}" in
+                *)
+                    COMPREPLY=()
+                    ;;
+            esac
+            COMPREPLY=( $(compgen -W "${opts}" -- "${cur}") )
+            return 0
+            ;;
         jplag_wrapper__help__help)
             opts=""
             if [[ ${cur} == -* || ${COMP_CWORD} -eq 3 ]] ; then
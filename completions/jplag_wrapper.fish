@@ -1,6 +1,6 @@
 # Print an optspec for argparse to handle cmd's options that are independent of any subcommand.
 function __fish_jplag_wrapper_global_optspecs
-	string join \n init l/log-level= abort-on-err c/config= s/source-zip= t/target-dir= tmp-dir= p/preserve-tmp-dir i/ignore-file= ignore-output j/jplag-jar= h/help V/version
+	string join \n init l/log-level= abort-on-err c/config= s/source-zip= t/target-dir= tmp-dir= p/preserve-tmp-dir i/ignore-file= base-code= ignore-output max-nesting-depth= jobs= max-submission-bytes= max-entries= max-compression-ratio= max-total-bytes= password= stream keep-loose-files keep-non-ascii flatten-wrapper-dirs salvage-corrupt cache-dir= multi-archive-policy= duplicate-policy= layout= git-deadline= git-ref= old-submission= anonymize deadline= only-late exclude-late timestamps= j/jplag-jar= h/help V/version
 end
 
 function __fish_jplag_wrapper_needs_command
@@ -26,19 +26,61 @@ end
 
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -s l -l log-level -d 'Log Level to use' -r
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -s c -l config -d 'Specify the config toml file to look for if you don\'t want to use the default config.toml' -r
-complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -s s -l source-zip -d 'Where the input file can be found' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -s s -l source-zip -d 'Where the input file can be found, can be repeated' -r
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -s t -l target-dir -d 'Where to put the results' -r
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l tmp-dir -d 'Where to put the temporary files' -r
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -s i -l ignore-file -d 'Where to find the ignore-file' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l base-code -d 'Where to find the base code (the template handed out to the students), either a dir or an archive' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l max-nesting-depth -d 'How deep archives inside of extracted submissions are extracted' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l jobs -d 'How many submissions are extracted in parallel' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l max-submission-bytes -d 'Max uncompressed bytes a single submission may expand to' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l max-entries -d 'Max files and dirs a single submission may contain' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l max-compression-ratio -d 'Max ratio between the uncompressed size of a submission and its archive' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l max-total-bytes -d 'Max uncompressed bytes of all submissions together' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l password -d 'Password to try for encrypted zip, 7z and rar submissions, can be repeated' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l cache-dir -d 'Where to cache extracted submissions, to restore unchanged ones on the next run' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l multi-archive-policy -d 'What to do with a submission containing more than one archive' -r -f -a "reject\t'Reject the submission and remove it'
+merge\t'Extract all archives into the submission directory'
+numbered\t'Extract each archive into its own numbered subdirectory (`1/`, `2/`, ...)'
+newest\t'Only extract the archive with the newest modification time'
+largest\t'Only extract the largest archive'"
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l duplicate-policy -d 'What to do, if a submission with the same name is in more than one source' -r -f -a "error\t'Abort the run'
+suffix\t'Keep both, a counter is appended to the name of the later one (`alice_1`)'
+newest\t'Keep the one with the newest file'"
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l layout -d 'How the submissions are laid out in the `{{source_zip}}`' -r -f -a "auto\t'Detect the layout by the names of the submissions, fall back to `plain`'
+plain\t'One directory per student, its name is the submission ID'
+moodle\t'Moodle assignment export, `Jane Doe_123456_assignsubmission_file_/` (or `Jane Doe_123456_assignsubmission_file_Main.zip` without a folder per submission)'
+ilias\t'ILIAS exercise export, `jdoe/<attempt>/`, only the latest attempt is kept'
+canvas\t'Canvas export, `lastfirst_12345_67890_Main.zip`'
+blackboard\t'Blackboard export, `Assignment_jdoe_attempt_2024-01-31-23-59-59_Main.zip`, only the latest attempt is kept'
+github-classroom\t'GitHub Classroom, a dir of repositories named `assignment-username`, their sources are kept as loose files, only detected by `auto`, if all entries are repositories'"
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l git-deadline -d 'Check out the last commit before this deadline of each git repository, in any format git understands (e.g. `2024-05-01 23:59`)' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l git-ref -d 'Check out this tag or branch of each git repository (combined with `--git-deadline`, the last commit of it before the deadline)' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l old-submission -d 'Where old submissions (e.g. of previous semesters) can be found, can be repeated' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l deadline -d 'The deadline of the assignment, submissions after it are late (e.g. `2024-05-01T23:59:00+02:00`, or `2024-05-01 23:59`, which is taken as UTC)' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l timestamps -d 'A CSV with the submission time of each student, `student,timestamp` per line' -r
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -s j -l jplag-jar -d 'Where the jplag jar can be found' -r
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l init -d 'Initialize the config, will create (or override!) `config.toml` with all values and fill it with the defaults'
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l abort-on-err -d 'Set to abort on any extraction related error'
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -s p -l preserve-tmp-dir -d 'Set to not remove `{{tmp_dir}}` when the program finishes'
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l ignore-output -d 'Set to ignore the output of jplag'
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l stream -d 'Set to extract the archives of the submissions directly from the `{{source_zip}}`, instead of unzipping them to `{{tmp_dir}}` first'
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l keep-loose-files -d 'Set to keep files, which are no archives, instead of removing them'
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l keep-non-ascii -d 'Set to keep non-ASCII characters (e.g. umlauts) in file names'
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l flatten-wrapper-dirs -d 'Set to collapse directories, which only contain a single directory, e.g. `alice/Assignment3/Assignment3/src` becomes `alice/src`'
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l salvage-corrupt -d 'Set to keep the readable entries of corrupt or truncated zips and tars, instead of dropping the submission'
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l anonymize -d 'Set to rename every submission to a pseudonym (e.g. `student_3f9a1c2b7d4e8a60`), before jplag runs, so the results contain no names'
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l only-late -d 'Set to only prepare the late submissions, the others are listed in the summary'
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -l exclude-late -d 'Set to only prepare the submissions in time, the late ones are listed in the summary'
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -s h -l help -d 'Print help (see more with \'--help\')'
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -s V -l version -d 'Print version'
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -a "complete"
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -a "deanonymize" -d 'Restore the names in the results of an anonymized run, only for staff, which may see them'
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_needs_command" -a "help" -d 'Print this message or the help of the given subcommand(s)'
 complete -c jplag_wrapper -n "__fish_jplag_wrapper_using_subcommand complete" -s h -l help -d 'Print help'
-complete -c jplag_wrapper -n "__fish_jplag_wrapper_using_subcommand help; and not __fish_seen_subcommand_from complete help" -f -a "complete"
-complete -c jplag_wrapper -n "__fish_jplag_wrapper_using_subcommand help; and not __fish_seen_subcommand_from complete help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_using_subcommand deanonymize" -s m -l mapping -d 'The mapping of the pseudonyms, written by the anonymized run' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_using_subcommand deanonymize" -s o -l output -d 'Where to write the deanonymized results' -r
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_using_subcommand deanonymize" -s h -l help -d 'Print help (see more with \'--help\')'
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_using_subcommand help; and not __fish_seen_subcommand_from complete deanonymize help" -f -a "complete"
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_using_subcommand help; and not __fish_seen_subcommand_from complete deanonymize help" -f -a "deanonymize" -d 'Restore the names in the results of an anonymized run, only for staff, which may see them'
+complete -c jplag_wrapper -n "__fish_jplag_wrapper_using_subcommand help; and not __fish_seen_subcommand_from complete deanonymize help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
@@ -0,0 +1,371 @@
+use color_eyre::Result;
+use color_eyre::eyre::{Context, ContextCompat, bail};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt::{Debug, Write as _};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, instrument, trace};
+use walkdir::WalkDir;
+use zip::write::SimpleFileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+/// Written to the target dir, next to (never into) the results of jplag
+pub const MAPPING_FILE_NAME: &str = "pseudonym_mapping.toml";
+/// Every pseudonym starts with it, followed by [`HASH_LEN`] hex digits
+const PREFIX: &str = "student_";
+/// 64 bits of the HMAC, collisions are detected anyway
+const HASH_LEN: usize = 16;
+
+/// The pseudonyms of a run and the names they replace,
+/// everyone with this file can deanonymize the results
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Pseudonyms {
+    pub students: Vec<Pseudonym>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Pseudonym {
+    pub pseudonym: String,
+    /// The name of the submission dir, e.g. a clean ID of the layout
+    pub name: String,
+}
+
+/// The stable pseudonym of `name`, a keyed HMAC with the `secret`,
+/// e.g. `student_3f9a1c2b7d4e8a60`
+pub fn pseudonym(secret: &str, name: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(name.as_bytes());
+
+    let mut pseudonym = String::from(PREFIX);
+    for byte in &mac.finalize().into_bytes()[..HASH_LEN / 2] {
+        let _ = write!(pseudonym, "{byte:02x}");
+    }
+
+    pseudonym
+}
+
+/// Renames every submission dir in `roots` to its [`pseudonym`]
+/// and writes the mapping back to the names to `target_dir`
+///
+/// The same name gets the same pseudonym in every root (and run with the same `secret`),
+/// so a student of an old semester is recognizable
+#[instrument(skip(secret))]
+pub fn pseudonymize<P>(roots: &[PathBuf], secret: &str, target_dir: P) -> Result<usize>
+where
+    P: AsRef<Path> + Debug,
+{
+    let mut names: HashMap<String, String> = HashMap::new();
+    let mut renamed_cnt = 0;
+    for root in roots {
+        let mut submissions = fs::read_dir(root)
+            .with_context(|| format!("unable to read {root:?}"))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("unable to read a dir in {root:?}"))?;
+        submissions.sort();
+
+        for submission in submissions {
+            let name = submission
+                .file_name()
+                .with_context(|| format!("unable to get the name of {submission:?}"))?
+                .to_string_lossy()
+                .into_owned();
+            let pseudonym = pseudonym(secret, &name);
+            let target = root.join(&pseudonym);
+            insert_unique(&mut names, pseudonym, name)?;
+
+            trace!("renaming {submission:?} to {target:?}");
+            fs::rename(&submission, &target)
+                .with_context(|| format!("unable to rename {submission:?} to {target:?}"))?;
+            renamed_cnt += 1;
+        }
+    }
+
+    let mut students = names
+        .into_iter()
+        .map(|(pseudonym, name)| Pseudonym { pseudonym, name })
+        .collect::<Vec<_>>();
+    students.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let path = target_dir.as_ref().join(MAPPING_FILE_NAME);
+    let toml = toml::to_string_pretty(&Pseudonyms { students })
+        .context("unable to serialize the pseudonyms")?;
+    fs::write(&path, toml).with_context(|| format!("unable to write {path:?}"))?;
+    info!("pseudonymized {renamed_cnt} submissions, the mapping is in {path:?}");
+
+    Ok(renamed_cnt)
+}
+
+/// Adds `pseudonym` of `name` to `names`,
+/// fails if it is already taken by another name (the same name in another root is fine)
+fn insert_unique(
+    names: &mut HashMap<String, String>,
+    pseudonym: String,
+    name: String,
+) -> Result<()> {
+    if let Some(other) = names.get(&pseudonym)
+        && *other != name
+    {
+        bail!("{name:?} and {other:?} have the same pseudonym {pseudonym:?}");
+    }
+    names.insert(pseudonym, name);
+
+    Ok(())
+}
+
+/// Writes a copy of the `results` of jplag (a zip or a dir of an extracted export)
+/// to `output`, with every pseudonym of the `mapping` replaced by the name
+///
+/// Entry names and the content of text entries are rewritten, everything else is copied as is
+#[instrument]
+pub fn deanonymize(results: &str, mapping: &str, output: Option<&str>) -> Result<PathBuf> {
+    let toml =
+        fs::read_to_string(mapping).with_context(|| format!("unable to read {mapping:?}"))?;
+    let pseudonyms = toml::from_str::<Pseudonyms>(&toml)
+        .with_context(|| format!("unable to parse the pseudonyms of {mapping:?}"))?;
+    let names = pseudonyms
+        .students
+        .into_iter()
+        .map(|student| (student.pseudonym, student.name))
+        .collect::<HashMap<_, _>>();
+    debug!("read {} pseudonyms", names.len());
+
+    let results = Path::new(results);
+    let output = output.map_or_else(
+        || {
+            let mut name = results.file_stem().unwrap_or_default().to_owned();
+            name.push("_deanonymized");
+            let output = results.with_file_name(name);
+            if results.is_dir() {
+                output
+            } else {
+                output.with_extension("zip")
+            }
+        },
+        PathBuf::from,
+    );
+    if fs::exists(&output).with_context(|| format!("unable to check if {output:?} exists"))? {
+        bail!("{output:?} already exists");
+    }
+
+    if results.is_dir() {
+        deanonymize_dir(results, &output, &names)?;
+    } else {
+        deanonymize_zip(results, &output, &names)?;
+    }
+    info!("wrote the deanonymized results to {output:?}");
+
+    Ok(output)
+}
+
+fn deanonymize_dir(results: &Path, output: &Path, names: &HashMap<String, String>) -> Result<()> {
+    for entry in WalkDir::new(results) {
+        let entry = entry.with_context(|| format!("invalid entry in {results:?}"))?;
+        let relative = entry.path().strip_prefix(results)?;
+        let relative = restore(&relative.to_string_lossy(), names, false).into_owned();
+        let path = output.join(relative);
+
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&path).with_context(|| format!("unable to create {path:?}"))?;
+            continue;
+        }
+        let content =
+            fs::read(entry.path()).with_context(|| format!("unable to read {:?}", entry.path()))?;
+        let is_json = path.extension().is_some_and(|ext| ext == "json");
+        fs::write(&path, restore_content(content, names, is_json))
+            .with_context(|| format!("unable to write {path:?}"))?;
+    }
+
+    Ok(())
+}
+
+fn deanonymize_zip(results: &Path, output: &Path, names: &HashMap<String, String>) -> Result<()> {
+    let file = File::open(results).with_context(|| format!("unable to open {results:?}"))?;
+    let mut archive =
+        ZipArchive::new(file).with_context(|| format!("{results:?} is no zip of results"))?;
+    let out = File::create(output).with_context(|| format!("unable to create {output:?}"))?;
+    let mut writer = ZipWriter::new(out);
+
+    for i in 0..archive.len() {
+        let mut entry = archive
+            .by_index(i)
+            .with_context(|| format!("unable to read entry {i} of {results:?}"))?;
+        let name = restore(entry.name(), names, false).into_owned();
+        let options = SimpleFileOptions::default();
+
+        if entry.is_dir() {
+            writer
+                .add_directory(name, options)
+                .with_context(|| format!("unable to write to {output:?}"))?;
+            continue;
+        }
+        let mut content = vec![];
+        entry
+            .read_to_end(&mut content)
+            .with_context(|| format!("unable to read {:?} of {results:?}", entry.name()))?;
+        let is_json = Path::new(&name)
+            .extension()
+            .is_some_and(|ext| ext == "json");
+        writer
+            .start_file(name, options)
+            .with_context(|| format!("unable to write to {output:?}"))?;
+        writer
+            .write_all(&restore_content(content, names, is_json))
+            .with_context(|| format!("unable to write to {output:?}"))?;
+    }
+
+    writer
+        .finish()
+        .with_context(|| format!("unable to finish {output:?}"))?;
+
+    Ok(())
+}
+
+/// Restores the names in a text entry, binary entries are returned as they are
+fn restore_content(content: Vec<u8>, names: &HashMap<String, String>, is_json: bool) -> Vec<u8> {
+    match String::from_utf8(content) {
+        Ok(text) => match restore(&text, names, is_json) {
+            Cow::Borrowed(_) => text.into_bytes(),
+            Cow::Owned(restored) => restored.into_bytes(),
+        },
+        Err(e) => e.into_bytes(),
+    }
+}
+
+/// Replaces every known pseudonym in `text` by its name, escaped for a JSON string if `is_json`
+fn restore<'a>(text: &'a str, names: &HashMap<String, String>, is_json: bool) -> Cow<'a, str> {
+    if !text.contains(PREFIX) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut restored = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(PREFIX) {
+        let end = start + PREFIX.len() + HASH_LEN;
+        if let Some(name) = rest
+            .get(start..end)
+            .and_then(|pseudonym| names.get(pseudonym))
+        {
+            restored.push_str(&rest[..start]);
+            if is_json {
+                restored.push_str(&name.replace('\\', "\\\\").replace('"', "\\\""));
+            } else {
+                restored.push_str(name);
+            }
+            rest = &rest[end..];
+        } else {
+            restored.push_str(&rest[..start + PREFIX.len()]);
+            rest = &rest[start + PREFIX.len()..];
+        }
+    }
+    restored.push_str(rest);
+
+    Cow::Owned(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper;
+
+    #[test]
+    fn pseudonym_is_a_stable_hmac() {
+        let pseudonym = pseudonym("secret", "Jane_Doe_123456");
+
+        assert_eq!(pseudonym, super::pseudonym("secret", "Jane_Doe_123456"));
+        assert_ne!(
+            pseudonym,
+            super::pseudonym("other secret", "Jane_Doe_123456")
+        );
+        assert_ne!(pseudonym, super::pseudonym("secret", "Jane_Doe_654321"));
+
+        let hash = pseudonym.strip_prefix(PREFIX).unwrap();
+        assert_eq!(hash.len(), HASH_LEN);
+        assert!(
+            hash.bytes()
+                .all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase())
+        );
+    }
+
+    #[test]
+    fn insert_unique_rejects_collisions() {
+        let mut names = HashMap::new();
+        insert_unique(&mut names, "student_0".to_owned(), "alice".to_owned()).unwrap();
+        insert_unique(&mut names, "student_0".to_owned(), "alice".to_owned()).unwrap();
+
+        let err = insert_unique(&mut names, "student_0".to_owned(), "bob".to_owned()).unwrap_err();
+        assert!(err.to_string().contains("the same pseudonym"), "{err}");
+        assert_eq!(names["student_0"], "alice");
+    }
+
+    #[test]
+    fn restore_known_pseudonyms() {
+        let alice = pseudonym("secret", "alice");
+        let quoted = pseudonym("secret", "Doe, \"Jane\"");
+        let names = HashMap::from([
+            (alice.clone(), "alice".to_owned()),
+            (quoted.clone(), "Doe, \"Jane\"".to_owned()),
+        ]);
+        let unknown = pseudonym("secret", "bob");
+
+        assert_eq!(
+            restore(&format!("{alice}/Main.java"), &names, false),
+            "alice/Main.java"
+        );
+        assert_eq!(
+            restore(&format!(r#"{{"id":"{quoted}"}}"#), &names, true),
+            r#"{"id":"Doe, \"Jane\""}"#
+        );
+        assert_eq!(restore(&unknown, &names, false), unknown);
+        assert!(matches!(
+            restore("no pseudonyms", &names, false),
+            Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn pseudonymize_and_deanonymize() {
+        let dir = helper::scratch_dir("pseudonymize");
+        let roots = [dir.join("current"), dir.join("old")];
+        for submission in ["current/alice", "current/bob", "old/alice"] {
+            fs::create_dir_all(dir.join(submission)).unwrap();
+        }
+
+        let renamed = pseudonymize(&roots, "secret", &dir).unwrap();
+
+        assert_eq!(renamed, 3);
+        let alice = pseudonym("secret", "alice");
+        let bob = pseudonym("secret", "bob");
+        assert!(dir.join("current").join(&alice).is_dir());
+        assert!(dir.join("current").join(&bob).is_dir());
+        assert!(dir.join("old").join(&alice).is_dir());
+
+        let results = dir.join("results");
+        fs::create_dir_all(results.join(&alice)).unwrap();
+        fs::write(
+            results.join("overview.json"),
+            format!(r#"["{alice}","{bob}"]"#),
+        )
+        .unwrap();
+        fs::write(results.join(&alice).join("Main.java"), "class Main {}").unwrap();
+        let mapping = dir.join(MAPPING_FILE_NAME);
+
+        let output =
+            deanonymize(results.to_str().unwrap(), mapping.to_str().unwrap(), None).unwrap();
+
+        assert_eq!(output, dir.join("results_deanonymized"));
+        assert_eq!(
+            fs::read_to_string(output.join("overview.json")).unwrap(),
+            r#"["alice","bob"]"#
+        );
+        assert!(output.join("alice/Main.java").is_file());
+        assert!(deanonymize(results.to_str().unwrap(), mapping.to_str().unwrap(), None).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
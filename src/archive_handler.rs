@@ -1,25 +1,752 @@
+use crate::conf::config::MultiArchivePolicy;
+use crate::detect::{self, ArchiveKind, Codec};
 use crate::helper;
+use crate::safe_extract::{Budget, LimitExceeded, LinkKind, SafeDest};
 use color_eyre::{
-    Result,
-    eyre::{Context, ContextCompat},
+    Report, Result,
+    eyre::{Context, ContextCompat, bail},
 };
-use flate2::read::GzDecoder;
-use std::fmt::Debug;
+use sevenz_rust::Password;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt::{Debug, Display, Formatter};
 use std::fs;
 use std::fs::File;
-use std::hint::unreachable_unchecked;
-use std::io::BufReader;
-use std::path::Path;
-use tracing::{debug, instrument, trace};
+use std::io::{self, BufReader, Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use time::{Date, Month, PrimitiveDateTime, Time};
+use tracing::{Level, debug, instrument, trace, warn};
+use unrar::error::UnrarError;
+use walkdir::WalkDir;
+
+/// Upper limit of nested archives we extract for a single submission,
+/// independent of the configured depth
+const MAX_NESTED_ARCHIVES: usize = 64;
+
+/// Everything a handler needs besides the paths
+#[derive(Debug)]
+pub struct ExtractCtx<'a> {
+    /// To look up the handler of nested archives
+    pub registry: &'a Registry,
+    pub budget: &'a Budget,
+    /// Tried in order for encrypted archives
+    pub passwords: &'a [String],
+    pub salvage: &'a Salvage,
+}
+
+/// Best-effort recovery of corrupt or truncated archives
+///
+/// If enabled, handlers keep every entry they could read and record the lost ones here,
+/// instead of failing the whole submission
+#[derive(Debug, Default)]
+pub struct Salvage {
+    enabled: bool,
+    lost: Mutex<Vec<String>>,
+}
+
+impl Salvage {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            lost: Mutex::default(),
+        }
+    }
+
+    pub const fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// `entry` is the name of the lost entry, or a description, if the name is unknown too
+    pub fn record_lost(&self, archive_file_path: &Path, entry: &str) {
+        warn!("lost {entry} of corrupt archive {archive_file_path:?}");
+        self.lost
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(format!("{entry} (of {archive_file_path:?})"));
+    }
+
+    /// Everything lost so far, empty if the submission was extracted completely
+    pub fn into_lost(self) -> Vec<String> {
+        self.lost
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[derive(Debug)]
+pub struct NoMatchingPassword;
+
+impl Display for NoMatchingPassword {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "encrypted, no matching password")
+    }
+}
+
+impl Error for NoMatchingPassword {}
 
 // tmp dir: tmp/
 // Student name dir path: tmp/name/
 // archive file path: tmp/name/archive
 // zip dir name: name/
 
+/// Extracts one or more kinds of archives
+///
+/// Implementations are collected in a [`Registry`],
+/// a new format only needs an implementation and a call to [`Registry::register`]
+pub trait ArchiveHandler: Debug + Send + Sync {
+    /// Whether this handler can extract archives of the detected `kind`
+    fn detect(&self, kind: ArchiveKind) -> bool;
+
+    /// Names of all entries in the archive, without extracting it
+    fn list_entries(&self, archive_file_path: &Path) -> Result<Vec<String>>;
+
+    /// Extracts the archive into `dest` and removes it afterward
+    ///
+    /// `tmp_dir` is the parent of `dest`
+    fn extract(
+        &self,
+        tmp_dir: &Path,
+        dest: &Path,
+        archive_file_path: &Path,
+        ctx: &ExtractCtx,
+    ) -> Result<()>;
+
+    /// Extracts an archive, which is not on disk (e.g. still inside the source zip), into `dest`
+    ///
+    /// `archive_file_path` is where the archive would be, it is only used for its name,
+    /// the default writes `data` there and calls [`Self::extract`]
+    fn extract_bytes(
+        &self,
+        tmp_dir: &Path,
+        dest: &Path,
+        archive_file_path: &Path,
+        data: &[u8],
+        ctx: &ExtractCtx,
+    ) -> Result<()> {
+        fs::write(archive_file_path, data)
+            .with_context(|| format!("unable to write {archive_file_path:?}"))?;
+        self.extract(tmp_dir, dest, archive_file_path, ctx)
+    }
+}
+
+#[derive(Debug)]
+pub struct ZipHandler;
+
+impl ArchiveHandler for ZipHandler {
+    fn detect(&self, kind: ArchiveKind) -> bool {
+        kind == ArchiveKind::Zip
+    }
+
+    fn list_entries(&self, archive_file_path: &Path) -> Result<Vec<String>> {
+        let file = File::open(archive_file_path)
+            .with_context(|| format!("unable to open zip {archive_file_path:?}"))?;
+        let archive = zip::ZipArchive::new(BufReader::new(file))
+            .with_context(|| format!("unable to parse {archive_file_path:?} to a zip archive"))?;
+
+        Ok(archive.file_names().map(ToOwned::to_owned).collect())
+    }
+
+    fn extract(
+        &self,
+        tmp_dir: &Path,
+        dest: &Path,
+        archive_file_path: &Path,
+        ctx: &ExtractCtx,
+    ) -> Result<()> {
+        zip(tmp_dir, dest, archive_file_path, ctx)
+    }
+
+    fn extract_bytes(
+        &self,
+        _tmp_dir: &Path,
+        dest: &Path,
+        archive_file_path: &Path,
+        data: &[u8],
+        ctx: &ExtractCtx,
+    ) -> Result<()> {
+        helper::unzip_reader(
+            Cursor::new(data),
+            archive_file_path,
+            dest,
+            ctx.budget,
+            ctx.passwords,
+            ctx.salvage,
+        )
+        .with_context(|| format!("unable to unzip {archive_file_path:?} to {dest:?}"))
+    }
+}
+
+#[derive(Debug)]
+pub struct RarHandler;
+
+impl ArchiveHandler for RarHandler {
+    fn detect(&self, kind: ArchiveKind) -> bool {
+        kind == ArchiveKind::Rar
+    }
+
+    fn list_entries(&self, archive_file_path: &Path) -> Result<Vec<String>> {
+        let archive = unrar::Archive::new(archive_file_path)
+            .open_for_listing()
+            .with_context(|| format!("unable to open {archive_file_path:?}"))?;
+
+        archive
+            .map(|header| {
+                header
+                    .map(|h| h.filename.to_string_lossy().into_owned())
+                    .with_context(|| format!("unable to read header of {archive_file_path:?}"))
+            })
+            .collect()
+    }
+
+    fn extract(
+        &self,
+        tmp_dir: &Path,
+        dest: &Path,
+        archive_file_path: &Path,
+        ctx: &ExtractCtx,
+    ) -> Result<()> {
+        rar(tmp_dir, dest, archive_file_path, ctx)
+    }
+}
+
+#[derive(Debug)]
+pub struct SevenZHandler;
+
+impl ArchiveHandler for SevenZHandler {
+    fn detect(&self, kind: ArchiveKind) -> bool {
+        kind == ArchiveKind::SevenZ
+    }
+
+    fn list_entries(&self, archive_file_path: &Path) -> Result<Vec<String>> {
+        let archive = sevenz_rust::Archive::open(archive_file_path)
+            .with_context(|| format!("unable to open 7z {archive_file_path:?}"))?;
+
+        Ok(archive
+            .files
+            .iter()
+            .map(|entry| entry.name().to_owned())
+            .collect())
+    }
+
+    fn extract(
+        &self,
+        tmp_dir: &Path,
+        dest: &Path,
+        archive_file_path: &Path,
+        ctx: &ExtractCtx,
+    ) -> Result<()> {
+        sz(tmp_dir, dest, archive_file_path, ctx)
+    }
+
+    fn extract_bytes(
+        &self,
+        _tmp_dir: &Path,
+        dest: &Path,
+        archive_file_path: &Path,
+        data: &[u8],
+        ctx: &ExtractCtx,
+    ) -> Result<()> {
+        let dest = SafeDest::new(dest, ctx.budget)?;
+
+        with_passwords(ctx, &dest, is_7z_password_err, |password| {
+            un7z(Cursor::new(data), &dest, password)
+        })
+        .with_context(|| format!("unable to decompress {archive_file_path:?}"))
+    }
+}
+
+/// A plain tar, or one compressed with `codec`
+#[derive(Debug)]
+pub struct TarHandler {
+    pub codec: Option<Codec>,
+}
+
+impl ArchiveHandler for TarHandler {
+    fn detect(&self, kind: ArchiveKind) -> bool {
+        kind == self
+            .codec
+            .map_or(ArchiveKind::Tar, ArchiveKind::CompressedTar)
+    }
+
+    fn list_entries(&self, archive_file_path: &Path) -> Result<Vec<String>> {
+        let file = BufReader::new(
+            File::open(archive_file_path)
+                .with_context(|| format!("unable to open tar {archive_file_path:?}"))?,
+        );
+
+        let mut names = vec![];
+        for entry in tar::Archive::new(self.reader(file)?)
+            .entries()
+            .context("unable to read tar entries")?
+        {
+            let entry = entry.context("unable to read tar entry")?;
+            let path = entry.path().context("invalid path in tar entry")?;
+            names.push(path.to_string_lossy().into_owned());
+        }
+
+        Ok(names)
+    }
+
+    fn extract(
+        &self,
+        tmp_dir: &Path,
+        dest: &Path,
+        archive_file_path: &Path,
+        ctx: &ExtractCtx,
+    ) -> Result<()> {
+        self.codec.map_or_else(
+            || tar(tmp_dir, dest, archive_file_path, ctx),
+            |codec| compressed_tar(codec, tmp_dir, dest, archive_file_path, ctx),
+        )
+    }
+
+    fn extract_bytes(
+        &self,
+        _tmp_dir: &Path,
+        dest: &Path,
+        archive_file_path: &Path,
+        data: &[u8],
+        ctx: &ExtractCtx,
+    ) -> Result<()> {
+        untar(
+            self.reader(data)?,
+            archive_file_path,
+            &SafeDest::new(dest, ctx.budget)?,
+            ctx.salvage,
+        )
+        .with_context(|| format!("unable to extract {archive_file_path:?} to {dest:?}"))
+    }
+}
+
+impl TarHandler {
+    /// Wraps `reader` into the decoder of `codec`, if any
+    fn reader<'a, R>(&self, reader: R) -> Result<Box<dyn Read + Send + 'a>>
+    where
+        R: Read + Send + 'a,
+    {
+        match self.codec {
+            None => Ok(Box::new(reader)),
+            Some(codec) => codec.decoder(reader),
+        }
+    }
+}
+
+/// A single file compressed with `codec` (e.g. `Main.java.gz`)
+#[derive(Debug)]
+pub struct SingleFileHandler {
+    pub codec: Codec,
+}
+
+impl ArchiveHandler for SingleFileHandler {
+    fn detect(&self, kind: ArchiveKind) -> bool {
+        kind == ArchiveKind::Compressed(self.codec)
+    }
+
+    fn list_entries(&self, archive_file_path: &Path) -> Result<Vec<String>> {
+        let out_name = single_file_name(self.codec, archive_file_path)?;
+
+        Ok(vec![out_name.to_string_lossy().into_owned()])
+    }
+
+    fn extract(
+        &self,
+        tmp_dir: &Path,
+        dest: &Path,
+        archive_file_path: &Path,
+        ctx: &ExtractCtx,
+    ) -> Result<()> {
+        single_file(self.codec, tmp_dir, dest, archive_file_path, ctx)
+    }
+
+    fn extract_bytes(
+        &self,
+        _tmp_dir: &Path,
+        dest: &Path,
+        archive_file_path: &Path,
+        data: &[u8],
+        ctx: &ExtractCtx,
+    ) -> Result<()> {
+        decompress_single(self.codec, data, dest, archive_file_path, ctx)
+    }
+}
+
+/// All known archive handlers
+#[derive(Debug, Default)]
+pub struct Registry {
+    handlers: Vec<Arc<dyn ArchiveHandler>>,
+}
+
+impl Registry {
+    /// A registry with handlers for every [`ArchiveKind`]
+    pub fn builtin() -> Self {
+        let mut registry = Self::default();
+        registry.register(ZipHandler);
+        registry.register(RarHandler);
+        registry.register(SevenZHandler);
+        registry.register(TarHandler { codec: None });
+        for codec in [Codec::Gzip, Codec::Bzip2, Codec::Xz, Codec::Zstd] {
+            registry.register(TarHandler { codec: Some(codec) });
+            registry.register(SingleFileHandler { codec });
+        }
+
+        registry
+    }
+
+    /// Adds a handler, which takes precedence over all handlers registered before,
+    /// so it can also replace a builtin one
+    pub fn register<H>(&mut self, handler: H)
+    where
+        H: ArchiveHandler + 'static,
+    {
+        self.handlers.push(Arc::new(handler));
+    }
+
+    /// Returns the handler for archives of `kind`
+    pub fn find(&self, kind: ArchiveKind) -> Option<Arc<dyn ArchiveHandler>> {
+        self.handlers
+            .iter()
+            .rev()
+            .find(|handler| handler.detect(kind))
+            .cloned()
+    }
+}
+
+/// Where the bytes of an archive come from
+#[derive(Clone, Debug)]
+pub enum ArchiveSource {
+    /// The archive is on disk, it is removed after extracting
+    File,
+    /// The archive is still inside the source zip and never written to disk
+    Entry(SourceEntry),
+}
+
+/// An archive inside the source zip
+#[derive(Clone, Debug)]
+pub struct SourceEntry {
+    pub source_zip: Arc<Path>,
+    pub index: usize,
+    pub size: u64,
+    /// As stored in the source zip, so the entry can be identified without reading it
+    pub crc32: u32,
+    pub modified: Option<SystemTime>,
+}
+
+impl SourceEntry {
+    /// Reads the whole entry into memory
+    pub fn read(&self) -> Result<Vec<u8>> {
+        let source_zip = &self.source_zip;
+        let file = File::open(source_zip)
+            .with_context(|| format!("unable to open source zip {source_zip:?}"))?;
+        let mut archive = zip::ZipArchive::new(BufReader::new(file))
+            .with_context(|| format!("unable to parse {source_zip:?} to a zip archive"))?;
+        let mut entry = archive
+            .by_index(self.index)
+            .with_context(|| format!("unable to get entry {} of {source_zip:?}", self.index))?;
+
+        let mut data = Vec::with_capacity(usize::try_from(self.size).unwrap_or_default());
+        entry
+            .read_to_end(&mut data)
+            .with_context(|| format!("unable to read entry {} of {source_zip:?}", self.index))?;
+
+        Ok(data)
+    }
+}
+
+/// A single archive of a submission and where to extract it to
+#[derive(Debug)]
+pub struct Extraction {
+    pub handler: Arc<dyn ArchiveHandler>,
+    /// Parent of `dest`, passed as the tmp dir to the handler
+    pub parent: PathBuf,
+    pub dest: PathBuf,
+    pub archive: PathBuf,
+    pub source: ArchiveSource,
+}
+
+impl Extraction {
+    /// Size of the archive itself, not of its content
+    pub fn archive_bytes(&self) -> Result<u64> {
+        match &self.source {
+            ArchiveSource::File => Ok(fs::metadata(&self.archive)
+                .with_context(|| format!("unable to get metadata of {:?}", self.archive))?
+                .len()),
+            ArchiveSource::Entry(entry) => Ok(entry.size),
+        }
+    }
+
+    pub fn run(&self, ctx: &ExtractCtx) -> Result<()> {
+        match &self.source {
+            ArchiveSource::File => {
+                if tracing::enabled!(Level::TRACE) {
+                    match self.handler.list_entries(&self.archive) {
+                        Ok(entries) => trace!(?entries, "entries of {:?}", self.archive),
+                        Err(e) => trace!(?e, "unable to list entries of {:?}", self.archive),
+                    }
+                }
+                self.handler
+                    .extract(&self.parent, &self.dest, &self.archive, ctx)
+            }
+            ArchiveSource::Entry(entry) => {
+                trace!("reading {:?} from the source zip", self.archive);
+                let data = entry.read()?;
+                self.handler
+                    .extract_bytes(&self.parent, &self.dest, &self.archive, &data, ctx)
+            }
+        }
+    }
+}
+
+/// Decides, which of the `archives` found in a submission are extracted where
+///
+/// Archives, which are not picked by the `policy`, are removed
+///
+/// A single archive is always extracted into the submission directory,
+/// [`MultiArchivePolicy::Reject`] has to be handled by the caller
+#[instrument(skip(archives, registry))]
+pub fn select(
+    student_name_dir_path: &Path,
+    mut archives: Vec<(PathBuf, ArchiveKind, ArchiveSource)>,
+    policy: MultiArchivePolicy,
+    registry: &Registry,
+) -> Result<Vec<Extraction>> {
+    let tmp_dir = student_name_dir_path
+        .parent()
+        .with_context(|| format!("unable to get parent of {student_name_dir_path:?}"))?;
+    let handler = |kind| {
+        registry
+            .find(kind)
+            .with_context(|| format!("no handler registered for {kind}"))
+    };
+    let extraction = |(archive, kind, source): (PathBuf, ArchiveKind, ArchiveSource)| {
+        Ok(Extraction {
+            handler: handler(kind)?,
+            parent: tmp_dir.to_owned(),
+            dest: student_name_dir_path.to_owned(),
+            archive,
+            source,
+        })
+    };
+
+    // Deterministic numbering and tie-breaking, independent of the file system order
+    archives.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+
+    if archives.len() < 2 {
+        return archives.into_iter().map(extraction).collect();
+    }
+
+    match policy {
+        MultiArchivePolicy::Reject | MultiArchivePolicy::Merge => {
+            archives.into_iter().map(extraction).collect()
+        }
+        MultiArchivePolicy::Numbered => archives
+            .into_iter()
+            .enumerate()
+            .map(|(i, (archive, kind, source))| {
+                Ok(Extraction {
+                    handler: handler(kind)?,
+                    parent: student_name_dir_path.to_owned(),
+                    dest: student_name_dir_path.join((i + 1).to_string()),
+                    archive,
+                    source,
+                })
+            })
+            .collect(),
+        MultiArchivePolicy::Newest | MultiArchivePolicy::Largest => {
+            let mut keys = Vec::with_capacity(archives.len());
+            for (archive, _, source) in &archives {
+                let (modified, len) = match source {
+                    ArchiveSource::File => {
+                        let metadata = fs::metadata(archive)
+                            .with_context(|| format!("unable to get metadata of {archive:?}"))?;
+                        (metadata.modified().ok(), metadata.len())
+                    }
+                    ArchiveSource::Entry(entry) => (entry.modified, entry.size),
+                };
+                let key = if policy == MultiArchivePolicy::Newest {
+                    modified
+                        .with_context(|| format!("unable to get modification time of {archive:?}"))?
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_nanos()
+                } else {
+                    u128::from(len)
+                };
+                keys.push(key);
+            }
+
+            // `max_by_key` returns the last maximum, we want the first on ties
+            let picked = (0..archives.len())
+                .rev()
+                .max_by_key(|&i| keys[i])
+                .with_context(|| "no archive to pick from")?;
+            let picked = archives.swap_remove(picked);
+
+            for (archive, _, source) in archives {
+                if matches!(source, ArchiveSource::File) {
+                    debug!("{policy} policy skips {archive:?}, removing");
+                    fs::remove_file(&archive)
+                        .with_context(|| format!("unable to remove skipped archive {archive:?}"))?;
+                } else {
+                    debug!("{policy} policy skips {archive:?}");
+                }
+            }
+
+            Ok(vec![extraction(picked)?])
+        }
+    }
+}
+
+/// Extracts archives inside an already extracted submission,
+/// up to `max_depth` levels deep
+///
+/// Every archive is extracted next to itself,
+/// into a directory named like the archive without its extension
+/// (e.g. `src.tar.gz` -> `src/`) and removed afterward
+///
+/// Archives, which fail to extract, are left as they are,
+/// unless they exceed the limits of the budget
+#[instrument(skip(max_depth, ctx))]
+pub fn nested<P>(student_name_dir_path: P, max_depth: usize, ctx: &ExtractCtx) -> Result<()>
+where
+    P: AsRef<Path> + Debug,
+{
+    let student_name_dir_path = student_name_dir_path.as_ref();
+    let mut extracted_cnt = 0;
+    let mut failed = vec![];
+
+    for depth in 1..=max_depth {
+        let mut found = vec![];
+        for entry in WalkDir::new(&student_name_dir_path) {
+            let entry =
+                entry.with_context(|| format!("invalid entry in {student_name_dir_path:?}"))?;
+            let path = entry.path();
+
+            if !entry.file_type().is_file() || failed.iter().any(|f| f == path) {
+                continue;
+            }
+
+            let detection = detect::detect(&path)
+                .with_context(|| format!("unable to detect archive type of {path:?}"))?;
+            if let Some(kind) = detection.kind() {
+                found.push((path.to_owned(), kind));
+            }
+        }
+
+        if found.is_empty() {
+            trace!("no nested archives at depth {depth}");
+            return Ok(());
+        }
+
+        debug!("found {} nested archives at depth {depth}", found.len());
+
+        for (archive_file_path, kind) in found {
+            extracted_cnt += 1;
+            if extracted_cnt > MAX_NESTED_ARCHIVES {
+                bail!(
+                    "more than {MAX_NESTED_ARCHIVES} nested archives \
+                    in {student_name_dir_path:?}"
+                );
+            }
+
+            let Some(handler) = ctx.registry.find(kind) else {
+                warn!("no handler registered for {kind}, keeping {archive_file_path:?}");
+                failed.push(archive_file_path);
+                continue;
+            };
+
+            if let ArchiveKind::Compressed(_) = kind {
+                // A single file is decompressed next to the archive, no extra dir needed
+                let parent = archive_file_path
+                    .parent()
+                    .with_context(|| format!("unable to get parent of {archive_file_path:?}"))?
+                    .to_owned();
+
+                trace!("decompressing nested {archive_file_path:?}");
+                if let Err(e) = handler.extract(&parent, &parent, &archive_file_path, ctx) {
+                    if is_limit_exceeded(&e) {
+                        return Err(e);
+                    }
+                    warn!(
+                        ?e,
+                        "unable to decompress nested file {archive_file_path:?}, keeping it"
+                    );
+                    failed.push(archive_file_path);
+                }
+                continue;
+            }
+
+            let dest = nested_dest(&archive_file_path)?;
+            let parent = dest
+                .parent()
+                .with_context(|| format!("unable to get parent of {dest:?}"))?
+                .to_owned();
+
+            trace!("extracting nested {archive_file_path:?} to {dest:?}");
+            fs::create_dir_all(&dest).with_context(|| format!("unable to create {dest:?}"))?;
+
+            if let Err(e) = handler.extract(&parent, &dest, &archive_file_path, ctx) {
+                if is_limit_exceeded(&e) {
+                    return Err(e);
+                }
+                warn!(
+                    ?e,
+                    "unable to extract nested archive {archive_file_path:?}, keeping it"
+                );
+                let _ = fs::remove_dir_all(&dest);
+                failed.push(archive_file_path);
+            }
+        }
+    }
+
+    trace!("reached max nesting depth {max_depth}");
+
+    Ok(())
+}
+
+/// A nested bomb has to fail the whole submission, not just be kept as an archive
+fn is_limit_exceeded(e: &Report) -> bool {
+    e.downcast_ref::<LimitExceeded>().is_some()
+}
+
+/// Picks a free directory next to `archive_file_path`,
+/// named after the archive without its (compound) extension
+fn nested_dest(archive_file_path: &Path) -> Result<PathBuf> {
+    let mut stem = archive_file_path
+        .file_stem()
+        .with_context(|| format!("unable to get file stem of {archive_file_path:?}"))?
+        .to_owned();
+
+    let inner = Path::new(&stem);
+    if inner
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("tar"))
+        && let Some(inner_stem) = inner.file_stem()
+    {
+        stem = inner_stem.to_owned();
+    }
+
+    let mut dest = archive_file_path.with_file_name(&stem);
+    let mut suffix = 1;
+    while dest.exists() {
+        let mut name = stem.clone();
+        name.push(format!("_{suffix}"));
+        dest = archive_file_path.with_file_name(name);
+        suffix += 1;
+    }
+
+    Ok(dest)
+}
+
 // Both are set in a span before calling one of these functions
-#[instrument(skip(tmp_dir, student_name_dir_path))]
-pub fn zip<P, Q, R>(tmp_dir: P, student_name_dir_path: Q, archive_file_path: R) -> Result<()>
+#[instrument(skip(tmp_dir, student_name_dir_path, ctx))]
+pub fn zip<P, Q, R>(
+    tmp_dir: P,
+    student_name_dir_path: Q,
+    archive_file_path: R,
+    ctx: &ExtractCtx,
+) -> Result<()>
 where
     P: AsRef<Path> + Debug,
     Q: AsRef<Path> + Debug,
@@ -42,8 +769,14 @@ where
 
     trace!("created {dest:?}");
 
-    helper::unzip_to(&archive_file_path, &dest)
-        .with_context(|| format!("unable to unzip {archive_file_path:?} to {dest:?}"))?;
+    helper::unzip_to(
+        &archive_file_path,
+        &dest,
+        ctx.budget,
+        ctx.passwords,
+        ctx.salvage,
+    )
+    .with_context(|| format!("unable to unzip {archive_file_path:?} to {dest:?}"))?;
 
     debug!("successfully decompressed");
     trace!("removing source");
@@ -56,8 +789,13 @@ where
     Ok(())
 }
 
-#[instrument(skip(tmp_dir, student_name_dir_path))]
-pub fn rar<P, Q, R>(tmp_dir: P, student_name_dir_path: Q, archive_file_path: R) -> Result<()>
+#[instrument(skip(tmp_dir, student_name_dir_path, ctx))]
+pub fn rar<P, Q, R>(
+    tmp_dir: P,
+    student_name_dir_path: Q,
+    archive_file_path: R,
+    ctx: &ExtractCtx,
+) -> Result<()>
 where
     P: AsRef<Path> + Debug,
     Q: AsRef<Path> + Debug,
@@ -68,39 +806,17 @@ where
     let student_name_dir_path = student_name_dir_path.as_ref();
     let archive_file_path = archive_file_path.as_ref();
 
-    let mut archive = unrar::Archive::new(&archive_file_path)
-        .open_for_processing()
-        .with_context(|| format!("unable to open {archive_file_path:?}"))?;
-
     let rar_dir_name = student_name_dir_path
         .file_name()
         .and_then(|f| f.to_str())
         .with_context(|| format!("unable to get file name of {student_name_dir_path:?}"))?;
 
-    let dest = tmp_dir.join(format!("{rar_dir_name}/"));
-
-    fs::create_dir_all(&dest).with_context(|| format!("unable to create dest dir {dest:?}"))?;
-
-    while let Some(header) = archive
-        .read_header()
-        .with_context(|| format!("unable to read header of {archive_file_path:?}"))?
-    {
-        let src_name = header.entry().filename.to_string_lossy().to_string();
-        let dest_name = format!("{}/{src_name}", dest.display());
-        trace!("{} bytes: {src_name}", header.entry().unpacked_size);
+    let dest = SafeDest::new(tmp_dir.join(rar_dir_name), ctx.budget)?;
 
-        archive = if header.entry().is_file() {
-            trace!("unpacking {}{src_name} to {dest_name}", tmp_dir.display());
-            header
-                .extract_to(&dest_name)
-                .with_context(|| format!("unable to unrar {src_name} to {dest_name}"))?
-        } else {
-            trace!("skipping {src_name}, is dir");
-            header
-                .skip()
-                .with_context(|| format!("unable to skip rar {src_name}"))?
-        }
-    }
+    with_passwords(ctx, &dest, is_rar_password_err, |password| {
+        unrar(archive_file_path, &dest, ctx, password)
+    })
+    .with_context(|| format!("unable to unrar {archive_file_path:?}"))?;
 
     debug!("successfully unrawred");
     trace!("removing source");
@@ -113,8 +829,13 @@ where
     Ok(())
 }
 
-#[instrument(skip(_tmp_dir, student_name_dir_path))]
-pub fn sz<P, Q, R>(_tmp_dir: P, student_name_dir_path: Q, archive_file_path: R) -> Result<()>
+#[instrument(skip(_tmp_dir, student_name_dir_path, ctx))]
+pub fn sz<P, Q, R>(
+    _tmp_dir: P,
+    student_name_dir_path: Q,
+    archive_file_path: R,
+    ctx: &ExtractCtx,
+) -> Result<()>
 where
     P: AsRef<Path> + Debug,
     Q: AsRef<Path> + Debug,
@@ -124,8 +845,14 @@ where
     let student_name_dir_path = student_name_dir_path.as_ref();
     let archive_file_path = archive_file_path.as_ref();
 
-    sevenz_rust::decompress_file(archive_file_path, student_name_dir_path)
-        .with_context(|| format!("unable to decompress {student_name_dir_path:?}"))?;
+    let dest = SafeDest::new(student_name_dir_path, ctx.budget)?;
+
+    with_passwords(ctx, &dest, is_7z_password_err, |password| {
+        let file = File::open(&archive_file_path)
+            .with_context(|| format!("unable to open 7z {archive_file_path:?}"))?;
+        un7z(file, &dest, password)
+    })
+    .with_context(|| format!("unable to decompress {archive_file_path:?}"))?;
 
     debug!("successfully decompressed");
     trace!("removing source");
@@ -138,8 +865,189 @@ where
     Ok(())
 }
 
-#[instrument(skip(_tmp_dir, student_name_dir_path))]
-pub fn tar<P, Q, R>(_tmp_dir: P, student_name_dir_path: Q, archive_file_path: R) -> Result<()>
+/// Runs `extract` without a password and, if that failed because of the encryption,
+/// with every password of `ctx` in order, until one works
+///
+/// Before each attempt, `dest` is rolled back, an attempt with a wrong password
+/// may have written broken entries and spent the budget before it failed
+fn with_passwords<F>(
+    ctx: &ExtractCtx,
+    dest: &SafeDest,
+    is_password_err: fn(&Report, bool) -> bool,
+    mut extract: F,
+) -> Result<()>
+where
+    F: FnMut(Option<&str>) -> Result<()>,
+{
+    let checkpoint = dest.checkpoint()?;
+    match extract(None) {
+        Err(e) if is_password_err(&e, false) => {
+            debug!(
+                "archive is encrypted, trying {} passwords",
+                ctx.passwords.len()
+            );
+        }
+        res => return res,
+    }
+
+    for password in ctx.passwords {
+        dest.roll_back(&checkpoint)?;
+        match extract(Some(password)) {
+            Err(e) if is_password_err(&e, true) => trace!("password did not match"),
+            res => return res,
+        }
+    }
+    dest.roll_back(&checkpoint)?;
+
+    Err(Report::new(NoMatchingPassword))
+}
+
+/// With a wrong password, rar4 only notices a broken checksum
+fn is_rar_password_err(e: &Report, with_password: bool) -> bool {
+    e.downcast_ref::<UnrarError>()
+        .is_some_and(|e| match e.code {
+            unrar::error::Code::MissingPassword | unrar::error::Code::BadPassword => true,
+            unrar::error::Code::BadData => with_password,
+            _ => false,
+        })
+}
+
+fn unrar(
+    archive_file_path: &Path,
+    dest: &SafeDest,
+    ctx: &ExtractCtx,
+    password: Option<&str>,
+) -> Result<()> {
+    let archive = password.map_or_else(
+        || unrar::Archive::new(archive_file_path),
+        |password| unrar::Archive::with_password(archive_file_path, password),
+    );
+    let mut archive = archive
+        .open_for_processing()
+        .with_context(|| format!("unable to open {archive_file_path:?}"))?;
+
+    while let Some(header) = archive
+        .read_header()
+        .with_context(|| format!("unable to read header of {archive_file_path:?}"))?
+    {
+        let src_name = header.entry().filename.clone();
+        let modified = dos_time(header.entry().file_time);
+        trace!("{} bytes: {src_name:?}", header.entry().unpacked_size);
+
+        // We read into memory instead of `extract_to`, so unrar never creates paths or links itself
+        archive = if header.entry().is_file() {
+            trace!("unpacking {src_name:?} to {:?}", dest.root());
+            // `read` buffers the whole entry, so we can't wait for the streaming check
+            ctx.budget.check_declared(header.entry().unpacked_size)?;
+            let (data, archive) = header
+                .read()
+                .with_context(|| format!("unable to unrar {src_name:?}"))?;
+            dest.write_file(&src_name, &mut data.as_slice())?;
+            // Keeps the upload time, like for zips
+            if let Some(modified) = modified {
+                dest.set_modified(&src_name, modified)?;
+            }
+            archive
+        } else {
+            trace!("skipping {src_name:?}, is dir");
+            dest.create_dir(&src_name)?;
+            header
+                .skip()
+                .with_context(|| format!("unable to skip rar {src_name:?}"))?
+        }
+    }
+
+    Ok(())
+}
+
+/// Decodes the MS-DOS date and time of a rar entry, without a time zone, so it is taken as UTC
+fn dos_time(dos_time: u32) -> Option<SystemTime> {
+    let date = Date::from_calendar_date(
+        i32::try_from(1980 + (dos_time >> 25)).ok()?,
+        Month::try_from(u8::try_from((dos_time >> 21) & 0x0f).ok()?).ok()?,
+        u8::try_from((dos_time >> 16) & 0x1f).ok()?,
+    )
+    .ok()?;
+    let time = Time::from_hms(
+        u8::try_from((dos_time >> 11) & 0x1f).ok()?,
+        u8::try_from((dos_time >> 5) & 0x3f).ok()?,
+        u8::try_from((dos_time & 0x1f) * 2).ok()?,
+    )
+    .ok()?;
+
+    Some(PrimitiveDateTime::new(date, time).assume_utc().into())
+}
+
+/// A wrong password shows up as a broken checksum (of the header or an entry),
+/// any other error is corruption, errors of our own (e.g. exceeded limits) are never a password error
+///
+/// Errors of an entry are wrapped in an [`io::Error`] by its reader, so the whole chain is searched
+fn is_7z_password_err(e: &Report, with_password: bool) -> bool {
+    e.chain()
+        .filter_map(|cause| {
+            cause.downcast_ref::<sevenz_rust::Error>().or_else(|| {
+                cause
+                    .downcast_ref::<io::Error>()?
+                    .get_ref()?
+                    .downcast_ref::<sevenz_rust::Error>()
+            })
+        })
+        .any(|e| match e {
+            sevenz_rust::Error::PasswordRequired => true,
+            sevenz_rust::Error::MaybeBadPassword(_)
+            | sevenz_rust::Error::ChecksumVerificationFailed => with_password,
+            _ => false,
+        })
+}
+
+fn un7z<R>(reader: R, dest: &SafeDest, password: Option<&str>) -> Result<()>
+where
+    R: Read + Seek,
+{
+    let password = password.map_or_else(Password::empty, Password::from);
+    let mut entry_err = None;
+
+    let res = sevenz_rust::decompress_with_extract_fn_and_password(
+        reader,
+        dest.root(),
+        password,
+        |entry, reader, _| {
+            let res = if entry.is_directory() {
+                dest.create_dir(entry.name())
+            } else if entry.is_anti_item() {
+                Ok(())
+            } else {
+                dest.write_file(entry.name(), reader).and_then(|()| {
+                    // Keeps the upload time, like for zips
+                    if entry.has_last_modified_date {
+                        dest.set_modified(entry.name(), entry.last_modified_date().into())
+                    } else {
+                        Ok(())
+                    }
+                })
+            };
+
+            res.map(|()| true).map_err(|e| {
+                let msg = e.to_string();
+                entry_err = Some(e);
+                sevenz_rust::Error::other(msg)
+            })
+        },
+    );
+
+    if let Some(e) = entry_err {
+        return Err(e);
+    }
+    res.map_err(Report::new)
+}
+
+#[instrument(skip(_tmp_dir, student_name_dir_path, ctx))]
+pub fn tar<P, Q, R>(
+    _tmp_dir: P,
+    student_name_dir_path: Q,
+    archive_file_path: R,
+    ctx: &ExtractCtx,
+) -> Result<()>
 where
     P: AsRef<Path> + Debug,
     Q: AsRef<Path> + Debug,
@@ -149,11 +1057,15 @@ where
     let student_name_dir_path = student_name_dir_path.as_ref();
     let archive_file_path = archive_file_path.as_ref();
 
-    tar::Archive::new(BufReader::new(
-        File::open(&archive_file_path)
-            .with_context(|| format!("unable to open tar {archive_file_path:?}"))?,
-    ))
-    .unpack(&student_name_dir_path)
+    untar(
+        BufReader::new(
+            File::open(&archive_file_path)
+                .with_context(|| format!("unable to open tar {archive_file_path:?}"))?,
+        ),
+        archive_file_path,
+        &SafeDest::new(student_name_dir_path, ctx.budget)?,
+        ctx.salvage,
+    )
     .with_context(|| {
         format!(
             "unable to untar {archive_file_path:?} \
@@ -172,8 +1084,14 @@ where
     Ok(())
 }
 
-#[instrument(skip(_tmp_dir, student_name_dir_path))]
-pub fn gz<P, Q, R>(_tmp_dir: P, student_name_dir_path: Q, archive_file_path: R) -> Result<()>
+#[instrument(skip(_tmp_dir, student_name_dir_path, ctx))]
+fn compressed_tar<P, Q, R>(
+    codec: Codec,
+    _tmp_dir: P,
+    student_name_dir_path: Q,
+    archive_file_path: R,
+    ctx: &ExtractCtx,
+) -> Result<()>
 where
     P: AsRef<Path> + Debug,
     Q: AsRef<Path> + Debug,
@@ -183,23 +1101,23 @@ where
     let student_name_dir_path = student_name_dir_path.as_ref();
     let archive_file_path = archive_file_path.as_ref();
 
-    tar::Archive::new(GzDecoder::new(BufReader::new(
-        File::open(&archive_file_path).with_context(|| {
-            format!(
-                "unable to open tar.gz file \
-                {archive_file_path:?}"
-            )
-        })?,
-    )))
-    .unpack(&student_name_dir_path)
+    let file = File::open(&archive_file_path)
+        .with_context(|| format!("unable to open tar.{codec} file {archive_file_path:?}"))?;
+
+    untar(
+        codec.decoder(BufReader::new(file))?,
+        archive_file_path,
+        &SafeDest::new(student_name_dir_path, ctx.budget)?,
+        ctx.salvage,
+    )
     .with_context(|| {
         format!(
             "unable to extract {archive_file_path:?} \
-            to {student_name_dir_path:?}"
+                to {student_name_dir_path:?}"
         )
     })?;
 
-    debug!("successfully ungzipped");
+    debug!("successfully extracted tar.{codec}");
     trace!("removing source");
 
     fs::remove_file(&archive_file_path)
@@ -210,14 +1128,836 @@ where
     Ok(())
 }
 
-#[instrument]
-pub fn dummy<P, Q, R>(_tmp_dir: P, _student_name_dir_path: Q, _archive_file_path: R) -> Result<()>
+/// Unpacks every entry of a tar through `dest`,
+/// instead of `tar::Archive::unpack`, which trusts the paths and links of the archive
+///
+/// With `salvage` enabled, everything before a corrupt block is kept,
+/// a tar has no index, so nothing after it can be found
+fn untar<R>(reader: R, archive_file_path: &Path, dest: &SafeDest, salvage: &Salvage) -> Result<()>
+where
+    R: Read,
+{
+    let mut archive = tar::Archive::new(reader);
+    let mut last_path: Option<PathBuf> = None;
+
+    for entry in archive.entries().context("unable to read tar entries")? {
+        let header = entry.context("unable to read tar entry").and_then(|entry| {
+            let path = entry
+                .path()
+                .context("invalid path in tar entry")?
+                .into_owned();
+            Ok((entry, path))
+        });
+        let (mut entry, path) = match header {
+            Ok(header) => header,
+            Err(e) => {
+                // Nothing to keep, if the very first header is corrupt
+                let Some(last_path) = last_path else {
+                    return Err(e);
+                };
+                salvage_tar(
+                    e,
+                    archive_file_path,
+                    &format!("everything after {last_path:?}"),
+                    salvage,
+                )?;
+                break;
+            }
+        };
+
+        if let Err(e) = untar_entry(&mut entry, &path, dest) {
+            if salvage.enabled() && !is_limit_exceeded(&e) {
+                // Don't keep a truncated file
+                let _ = fs::remove_file(dest.resolve(&path)?);
+            }
+            salvage_tar(
+                e,
+                archive_file_path,
+                &format!("{path:?} and everything after"),
+                salvage,
+            )?;
+            break;
+        }
+        last_path = Some(path);
+    }
+
+    Ok(())
+}
+
+/// Records the `lost` part of a corrupt tar, fails if `salvage` is disabled or there is nothing to keep
+fn salvage_tar(e: Report, archive_file_path: &Path, lost: &str, salvage: &Salvage) -> Result<()> {
+    if !salvage.enabled() || is_limit_exceeded(&e) {
+        return Err(e);
+    }
+
+    debug!(?e, "tar is corrupt, keeping the entries before");
+    salvage.record_lost(archive_file_path, lost);
+
+    Ok(())
+}
+
+fn untar_entry<R>(entry: &mut tar::Entry<'_, R>, path: &Path, dest: &SafeDest) -> Result<()>
+where
+    R: Read,
+{
+    let entry_type = entry.header().entry_type();
+
+    if entry_type.is_dir() {
+        dest.create_dir(path)?;
+    } else if entry_type.is_file() {
+        dest.write_file(path, entry)?;
+        // A truncated plain tar just ends, reading the entry doesn't fail
+        let written = fs::metadata(dest.resolve(path)?)
+            .with_context(|| format!("unable to get metadata of {path:?}"))?
+            .len();
+        if written != entry.size() {
+            bail!("{path:?} is truncated, {written} of {} bytes", entry.size());
+        }
+        // Keeps the upload time, like for zips
+        if let Ok(mtime) = entry.header().mtime()
+            && mtime > 0
+        {
+            dest.set_modified(path, UNIX_EPOCH + Duration::from_secs(mtime))?;
+        }
+    } else if entry_type.is_symlink() || entry_type.is_hard_link() {
+        let target = entry
+            .link_name()
+            .with_context(|| format!("invalid link target of {path:?}"))?
+            .with_context(|| format!("link {path:?} has no target"))?;
+        let kind = if entry_type.is_symlink() {
+            LinkKind::Symbolic
+        } else {
+            LinkKind::Hard
+        };
+        dest.link(path, &target, kind)?;
+    } else {
+        trace!("skipping {path:?} of type {entry_type:?}");
+    }
+
+    Ok(())
+}
+
+/// Decompresses a single compressed file (e.g. `Main.java.gz`)
+/// into `student_name_dir_path`, named like the archive without the codec extension
+#[instrument(skip(_tmp_dir, student_name_dir_path, ctx))]
+fn single_file<P, Q, R>(
+    codec: Codec,
+    _tmp_dir: P,
+    student_name_dir_path: Q,
+    archive_file_path: R,
+    ctx: &ExtractCtx,
+) -> Result<()>
 where
     P: AsRef<Path> + Debug,
     Q: AsRef<Path> + Debug,
     R: AsRef<Path> + Debug,
 {
-    unsafe {
-        unreachable_unchecked();
+    debug!("processing");
+    let student_name_dir_path = student_name_dir_path.as_ref();
+    let archive_file_path = archive_file_path.as_ref();
+
+    let file = File::open(&archive_file_path)
+        .with_context(|| format!("unable to open {codec} file {archive_file_path:?}"))?;
+    decompress_single(
+        codec,
+        BufReader::new(file),
+        student_name_dir_path,
+        archive_file_path,
+        ctx,
+    )?;
+
+    debug!("successfully decompressed {codec}");
+    trace!("removing source");
+
+    fs::remove_file(&archive_file_path)
+        .with_context(|| format!("unable to remove {archive_file_path:?}"))?;
+
+    trace!("successfully removed source");
+
+    Ok(())
+}
+
+/// Decompresses `reader` into `dest`, named like `archive_file_path` without the codec extension
+fn decompress_single<R>(
+    codec: Codec,
+    reader: R,
+    dest: &Path,
+    archive_file_path: &Path,
+    ctx: &ExtractCtx,
+) -> Result<()>
+where
+    R: Read + Send,
+{
+    let mut out_name = single_file_name(codec, archive_file_path)?;
+    if dest.join(&out_name) == archive_file_path {
+        // No extension to strip, the archive would be overwritten while reading it
+        out_name.push(".decompressed");
+    }
+
+    trace!("decompressing to {out_name:?}");
+
+    let mut decoder = codec.decoder(reader)?;
+    SafeDest::new(dest, ctx.budget)?
+        .write_file(&out_name, &mut decoder)
+        .with_context(|| format!("unable to decompress {archive_file_path:?} to {out_name:?}"))
+}
+
+/// Name of a decompressed single file, the archive name without the codec extension
+fn single_file_name(codec: Codec, archive_file_path: &Path) -> Result<OsString> {
+    let has_codec_extension = archive_file_path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(codec.to_string()));
+    let out_name = if has_codec_extension {
+        archive_file_path.file_stem()
+    } else {
+        archive_file_path.file_name()
+    }
+    .with_context(|| format!("unable to get file name of {archive_file_path:?}"))?;
+
+    Ok(out_name.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::safe_extract::Limits;
+    use std::io::{Cursor, Write};
+    use std::sync::{Arc, LazyLock};
+    use zip::write::SimpleFileOptions;
+    use zip::{AesMode, ZipWriter};
+
+    const MAIN: &[u8] = b"class Main {}";
+
+    fn zip_bytes(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, data) in entries {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn encrypted_zip_bytes(entries: &[(&str, &[u8])], password: &str) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        let options = SimpleFileOptions::default().with_aes_encryption(AesMode::Aes256, password);
+        for (name, data) in entries {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn tar_bytes(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            builder.append_data(&mut header, name, *data).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn compress(codec: Codec, data: &[u8]) -> Vec<u8> {
+        match codec {
+            Codec::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Codec::Bzip2 => {
+                let mut encoder =
+                    bzip2::write::BzEncoder::new(vec![], bzip2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Codec::Xz => {
+                let mut writer =
+                    lzma_rust2::XzWriter::new(vec![], lzma_rust2::XzOptions::default()).unwrap();
+                writer.write_all(data).unwrap();
+                writer.finish().unwrap()
+            }
+            Codec::Zstd => zstd::encode_all(data, 0).unwrap(),
+        }
+    }
+
+    static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::builtin);
+
+    fn unlimited() -> ExtractCtx<'static> {
+        static BUDGET: LazyLock<Budget> = LazyLock::new(Budget::unlimited);
+        static SALVAGE: LazyLock<Salvage> = LazyLock::new(Salvage::default);
+        ExtractCtx {
+            registry: &REGISTRY,
+            budget: &BUDGET,
+            passwords: &[],
+            salvage: &SALVAGE,
+        }
+    }
+
+    fn extract(test: &str, name: &str, data: &[u8]) -> Result<PathBuf> {
+        extract_within(test, name, data, Limits::UNLIMITED, &[])
+    }
+
+    /// Extracts the archive `name` with `data` into the submission `alice` of a new tmp dir,
+    /// returns the submission dir
+    fn extract_within(
+        test: &str,
+        name: &str,
+        data: &[u8],
+        limits: Limits,
+        passwords: &[String],
+    ) -> Result<PathBuf> {
+        let tmp_dir = helper::scratch_dir(test);
+        let student_name_dir_path = tmp_dir.join("alice");
+        fs::create_dir(&student_name_dir_path).unwrap();
+        let archive_file_path = student_name_dir_path.join(name);
+        fs::write(&archive_file_path, data).unwrap();
+
+        let kind = detect::detect(&archive_file_path)?
+            .kind()
+            .context("no archive")?;
+        let budget = Budget::new(limits, data.len() as u64, Arc::default());
+        let salvage = Salvage::default();
+        let ctx = ExtractCtx {
+            registry: &REGISTRY,
+            budget: &budget,
+            passwords,
+            salvage: &salvage,
+        };
+        REGISTRY.find(kind).context("no handler")?.extract(
+            &tmp_dir,
+            &student_name_dir_path,
+            &archive_file_path,
+            &ctx,
+        )?;
+
+        Ok(student_name_dir_path)
+    }
+
+    #[derive(Debug)]
+    struct ListOnly;
+
+    impl ArchiveHandler for ListOnly {
+        fn detect(&self, kind: ArchiveKind) -> bool {
+            kind == ArchiveKind::Zip
+        }
+
+        fn list_entries(&self, _archive_file_path: &Path) -> Result<Vec<String>> {
+            Ok(vec![])
+        }
+
+        fn extract(&self, _: &Path, _: &Path, _: &Path, _: &ExtractCtx) -> Result<()> {
+            bail!("only lists entries")
+        }
+    }
+
+    #[test]
+    fn registered_handlers_take_precedence() {
+        let dir = helper::scratch_dir("registry");
+        let archive_file_path = dir.join("src.zip");
+        fs::write(&archive_file_path, zip_bytes(&[("src/Main.java", MAIN)])).unwrap();
+
+        let mut registry = Registry::builtin();
+        let builtin = registry.find(ArchiveKind::Zip).unwrap();
+        assert_eq!(
+            builtin.list_entries(&archive_file_path).unwrap(),
+            ["src/Main.java"]
+        );
+
+        registry.register(ListOnly);
+        let custom = registry.find(ArchiveKind::Zip).unwrap();
+        assert!(custom.list_entries(&archive_file_path).unwrap().is_empty());
+        assert!(registry.find(ArchiveKind::Rar).is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compressed_tars() {
+        let tar = tar_bytes(&[("src/Main.java", MAIN)]);
+        for (codec, name) in [
+            (Codec::Gzip, "src.tgz"),
+            (Codec::Bzip2, "src.tar.bz2"),
+            (Codec::Xz, "src.tar.xz"),
+            (Codec::Zstd, "src.tar.zst"),
+            // Sniffed, even without the extension
+            (Codec::Zstd, "src.zst"),
+        ] {
+            let dir = extract(
+                &format!("compressed_tar_{name}"),
+                name,
+                &compress(codec, &tar),
+            )
+            .unwrap();
+
+            assert_eq!(fs::read(dir.join("src/Main.java")).unwrap(), MAIN, "{name}");
+            assert!(!dir.join(name).exists(), "{name}");
+            fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn single_compressed_files() {
+        for (codec, name, decompressed) in [
+            (Codec::Gzip, "Main.java.gz", "Main.java"),
+            (Codec::Xz, "Main.java.XZ", "Main.java"),
+            (Codec::Gzip, "Main", "Main.decompressed"),
+        ] {
+            let dir =
+                extract(&format!("single_file_{name}"), name, &compress(codec, MAIN)).unwrap();
+
+            assert_eq!(fs::read(dir.join(decompressed)).unwrap(), MAIN, "{name}");
+            assert!(!dir.join(name).exists(), "{name}");
+            fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn traversing_entries_are_rejected() {
+        let archive = zip_bytes(&[("Main.java", MAIN), ("../../Evil.java", MAIN)]);
+        let err = extract("zip_traversal", "src.zip", &archive).unwrap_err();
+
+        assert!(format!("{err:?}").contains("outside"), "{err:?}");
+        let tmp_dir = helper::scratch_dir("zip_traversal");
+        assert!(!tmp_dir.parent().unwrap().join("Evil.java").exists());
+        fs::remove_dir_all(tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn passwords_are_tried_in_order() {
+        let archive = encrypted_zip_bytes(&[("Main.java", MAIN)], "course");
+        let passwords = ["wrong".to_owned(), "course".to_owned()];
+        let dir = extract_within(
+            "zip_password",
+            "src.zip",
+            &archive,
+            Limits::UNLIMITED,
+            &passwords,
+        )
+        .unwrap();
+        assert_eq!(fs::read(dir.join("Main.java")).unwrap(), MAIN);
+
+        let err = extract_within(
+            "zip_password",
+            "src.zip",
+            &archive,
+            Limits::UNLIMITED,
+            &passwords[..1],
+        )
+        .unwrap_err();
+        assert!(
+            err.downcast_ref::<NoMatchingPassword>().is_some(),
+            "{err:?}"
+        );
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn limits_stop_the_extraction() {
+        let files = [("A.java", MAIN), ("B.java", MAIN), ("C.java", MAIN)];
+        let err = extract_within(
+            "limit_entries",
+            "src.zip",
+            &zip_bytes(&files),
+            Limits {
+                max_entries: 2,
+                ..Limits::UNLIMITED
+            },
+            &[],
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<LimitExceeded>(),
+            Some(LimitExceeded::Entries(2))
+        ));
+
+        let zeros = vec![0; 4 * 1024 * 1024];
+        let bomb = zip_bytes(&[("Main.java", &zeros)]);
+        let err = extract_within(
+            "limit_ratio",
+            "src.zip",
+            &bomb,
+            Limits {
+                max_compression_ratio: 100,
+                ..Limits::UNLIMITED
+            },
+            &[],
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<LimitExceeded>(),
+            Some(LimitExceeded::CompressionRatio(100))
+        ));
+
+        let err = extract_within(
+            "limit_bytes",
+            "src.tar.zst",
+            &compress(Codec::Zstd, &tar_bytes(&[("Main.java", &zeros)])),
+            Limits {
+                max_submission_bytes: 1024,
+                ..Limits::UNLIMITED
+            },
+            &[],
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<LimitExceeded>(),
+            Some(LimitExceeded::SubmissionBytes(1024))
+        ));
+        for test in ["limit_entries", "limit_ratio", "limit_bytes"] {
+            fs::remove_dir_all(helper::scratch_dir(test)).unwrap();
+        }
+    }
+
+    #[test]
+    fn salvages_truncated_archives() {
+        let dir = helper::scratch_dir("salvage");
+        let zip = zip_bytes(&[("A.java", MAIN), ("B.java", &[b'b'; 4096])]);
+        let zip_file_path = dir.join("src.zip");
+        // Cuts into the data of `B.java`, the central directory is lost
+        let local_header_b = zip.windows(6).position(|w| w == b"B.java").unwrap();
+        fs::write(&zip_file_path, &zip[..local_header_b + 10]).unwrap();
+        let tar = tar_bytes(&[("A.java", MAIN), ("B.java", &[b'b'; 4096])]);
+        let tar_file_path = dir.join("src.tar");
+        fs::write(&tar_file_path, &tar[..2048]).unwrap();
+
+        let budget = Budget::unlimited();
+        for archive_file_path in [&zip_file_path, &tar_file_path] {
+            let dest = dir.join("dest");
+            fs::create_dir_all(&dest).unwrap();
+            let kind = detect::detect(archive_file_path).unwrap().kind().unwrap();
+            let handler = REGISTRY.find(kind).unwrap();
+            let extract = |salvage: &Salvage| {
+                let ctx = ExtractCtx {
+                    registry: &REGISTRY,
+                    budget: &budget,
+                    passwords: &[],
+                    salvage,
+                };
+                handler.extract(&dir, &dest, archive_file_path, &ctx)
+            };
+
+            assert!(
+                extract(&Salvage::default()).is_err(),
+                "{archive_file_path:?}"
+            );
+
+            let salvage = Salvage::new(true);
+            extract(&salvage).unwrap();
+            assert_eq!(fs::read(dest.join("A.java")).unwrap(), MAIN);
+            assert!(!dest.join("B.java").exists());
+            let lost = salvage.into_lost();
+            assert!(lost[0].starts_with("\"B.java\""), "{lost:?}");
+            fs::remove_dir_all(&dest).unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn select_follows_the_policy() {
+        let tmp_dir = helper::scratch_dir("select_policy");
+        let student_name_dir_path = tmp_dir.join("alice");
+        fs::create_dir(&student_name_dir_path).unwrap();
+        let big = student_name_dir_path.join("big.zip");
+        let new = student_name_dir_path.join("new.zip");
+
+        for policy in [
+            MultiArchivePolicy::Merge,
+            MultiArchivePolicy::Numbered,
+            MultiArchivePolicy::Newest,
+            MultiArchivePolicy::Largest,
+        ] {
+            fs::write(&big, zip_bytes(&[("Main.java", &[b' '; 4096])])).unwrap();
+            File::options()
+                .write(true)
+                .open(&big)
+                .unwrap()
+                .set_modified(UNIX_EPOCH)
+                .unwrap();
+            fs::write(&new, zip_bytes(&[("Main.java", MAIN)])).unwrap();
+            let archives = vec![
+                (new.clone(), ArchiveKind::Zip, ArchiveSource::File),
+                (big.clone(), ArchiveKind::Zip, ArchiveSource::File),
+            ];
+
+            let extractions = select(&student_name_dir_path, archives, policy, &REGISTRY).unwrap();
+            let picked = extractions
+                .iter()
+                .map(|e| (e.archive.clone(), e.dest.clone()))
+                .collect::<Vec<_>>();
+
+            let expected = match policy {
+                MultiArchivePolicy::Merge => vec![
+                    (big.clone(), student_name_dir_path.clone()),
+                    (new.clone(), student_name_dir_path.clone()),
+                ],
+                MultiArchivePolicy::Numbered => vec![
+                    (big.clone(), student_name_dir_path.join("1")),
+                    (new.clone(), student_name_dir_path.join("2")),
+                ],
+                MultiArchivePolicy::Newest => vec![(new.clone(), student_name_dir_path.clone())],
+                _ => vec![(big.clone(), student_name_dir_path.clone())],
+            };
+            assert_eq!(picked, expected, "{policy}");
+            // Skipped archives are removed
+            assert_eq!(
+                big.exists(),
+                policy != MultiArchivePolicy::Newest,
+                "{policy}"
+            );
+            assert_eq!(
+                new.exists(),
+                policy != MultiArchivePolicy::Largest,
+                "{policy}"
+            );
+        }
+        fs::remove_dir_all(&tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn nested_stops_at_max_depth() {
+        let inner = zip_bytes(&[("Main.java", MAIN)]);
+        let outer = zip_bytes(&[("inner.zip", &inner)]);
+        for (max_depth, extracted) in [(1, "outer/inner.zip"), (2, "outer/inner/Main.java")] {
+            let dir = helper::scratch_dir(&format!("nested_depth_{max_depth}"));
+            fs::write(dir.join("outer.zip"), &outer).unwrap();
+
+            nested(&dir, max_depth, &unlimited()).unwrap();
+
+            assert!(dir.join(extracted).is_file(), "{max_depth}");
+            assert!(!dir.join("outer.zip").exists());
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn nested_keeps_broken_archives() {
+        let dir = helper::scratch_dir("nested_broken");
+        fs::write(dir.join("broken.zip"), b"PK\x03\x04 truncated").unwrap();
+
+        nested(&dir, 3, &unlimited()).unwrap();
+
+        assert!(dir.join("broken.zip").is_file());
+        assert!(!dir.join("broken").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn nested_archives_are_capped() {
+        let dir = helper::scratch_dir("nested_capped");
+        let archive = zip_bytes(&[("Main.java", MAIN)]);
+        for i in 0..=MAX_NESTED_ARCHIVES {
+            fs::write(dir.join(format!("{i}.zip")), &archive).unwrap();
+        }
+
+        let err = nested(&dir, 1, &unlimited()).unwrap_err();
+
+        assert!(err.to_string().contains("nested archives"), "{err}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn dos_time_is_utc() {
+        // 2024-01-31 23:59:58
+        let encoded = (44 << 25) | (1 << 21) | (31 << 16) | (23 << 11) | (59 << 5) | (58 >> 1);
+        assert_eq!(
+            dos_time(encoded),
+            Some(SystemTime::from(
+                time::macros::datetime!(2024-01-31 23:59:58 UTC)
+            ))
+        );
+        // Month 0
+        assert_eq!(dos_time(0), None);
+    }
+
+    #[test]
+    fn tars_keep_entry_times() {
+        let mut builder = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(MAIN.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(1_706_745_599);
+        builder.append_data(&mut header, "Main.java", MAIN).unwrap();
+        let data = builder.into_inner().unwrap();
+
+        let dir = extract("tar_times", "src.tar", &data).unwrap();
+
+        let modified = fs::metadata(dir.join("Main.java"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        assert_eq!(modified, UNIX_EPOCH + Duration::from_secs(1_706_745_599));
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    fn encrypted_7z(password: &str) -> Vec<u8> {
+        let src = helper::scratch_dir("encrypted_7z_src");
+        fs::write(src.join("Main.java"), "class Main {}").unwrap();
+        let data =
+            sevenz_rust::compress_encypted(&src, Cursor::new(vec![]), Password::from(password))
+                .unwrap()
+                .into_inner();
+        fs::remove_dir_all(&src).unwrap();
+        data
+    }
+
+    fn extract_7z(data: &[u8], passwords: &[&str], name: &str) -> Result<PathBuf> {
+        let dir = helper::scratch_dir(name);
+        let registry = Registry::builtin();
+        let budget = Budget::unlimited();
+        let salvage = Salvage::default();
+        let passwords = passwords
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        let ctx = ExtractCtx {
+            registry: &registry,
+            budget: &budget,
+            passwords: &passwords,
+            salvage: &salvage,
+        };
+        let dest = SafeDest::new(&dir, &budget)?;
+        with_passwords(&ctx, &dest, is_7z_password_err, |password| {
+            un7z(Cursor::new(data), &dest, password)
+        })?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn seven_z_tries_every_password() {
+        let data = encrypted_7z("secret");
+        let dir = extract_7z(&data, &["wrong", "secret"], "seven_z_tries_every_password").unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("Main.java")).unwrap(),
+            "class Main {}"
+        );
+    }
+
+    #[test]
+    fn seven_z_opens_with_the_second_password() {
+        let data = encrypted_7z("secret");
+        let passwords = ["wrong".to_owned(), "secret".to_owned()];
+
+        let dir = extract_within(
+            "seven_z_second_password",
+            "src.7z",
+            &data,
+            Limits::UNLIMITED,
+            &passwords,
+        )
+        .unwrap();
+
+        assert_eq!(fs::read(dir.join("Main.java")).unwrap(), MAIN);
+        assert!(!dir.join("src.7z").exists());
+        fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn failed_attempts_are_rolled_back() {
+        let dir = helper::scratch_dir("password_roll_back");
+        fs::write(dir.join("src.7z"), "archive").unwrap();
+        // Only room for the entry of a single attempt
+        let budget = Budget::new(
+            Limits {
+                max_entries: 1,
+                ..Limits::UNLIMITED
+            },
+            0,
+            Arc::default(),
+        );
+        let passwords = ["wrong".to_owned(), "secret".to_owned()];
+        let ctx = ExtractCtx {
+            passwords: &passwords,
+            budget: &budget,
+            ..unlimited()
+        };
+        let dest = SafeDest::new(&dir, &budget).unwrap();
+
+        // Like a wrong password, which is only noticed after an entry was written
+        with_passwords(
+            &ctx,
+            &dest,
+            |e, _| e.to_string() == "wrong password",
+            |password| {
+                let name = format!("{}.java", password.unwrap_or("None"));
+                dest.write_file(&name, &mut Cursor::new(MAIN))?;
+                if password == Some("secret") {
+                    Ok(())
+                } else {
+                    bail!("wrong password")
+                }
+            },
+        )
+        .unwrap();
+
+        let mut names = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["secret.java", "src.7z"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn seven_z_without_matching_password() {
+        let data = encrypted_7z("secret");
+        let e = extract_7z(&data, &["wrong"], "seven_z_without_matching_password").unwrap_err();
+        assert!(e.downcast_ref::<NoMatchingPassword>().is_some(), "{e:?}");
+    }
+
+    #[test]
+    fn seven_z_truncated_is_no_password_err() {
+        let data = encrypted_7z("secret");
+        let e = extract_7z(
+            &data[..data.len() / 2],
+            &["secret"],
+            "seven_z_truncated_is_no_password_err",
+        )
+        .unwrap_err();
+        assert!(e.downcast_ref::<NoMatchingPassword>().is_none(), "{e:?}");
+    }
+
+    #[test]
+    fn seven_z_password_errs() {
+        fn err<E>(e: E) -> Report
+        where
+            E: Error + Send + Sync + 'static,
+        {
+            Report::new(e).wrap_err("unable to decompress")
+        }
+        // Wrapped by the reader of an entry and by `SafeDest::write_file`
+        fn entry_err(e: sevenz_rust::Error) -> Report {
+            err(io::Error::other(e)).wrap_err("unable to write")
+        }
+
+        assert!(is_7z_password_err(
+            &err(sevenz_rust::Error::PasswordRequired),
+            false
+        ));
+        assert!(is_7z_password_err(
+            &entry_err(sevenz_rust::Error::ChecksumVerificationFailed),
+            true
+        ));
+        assert!(!is_7z_password_err(
+            &entry_err(sevenz_rust::Error::ChecksumVerificationFailed),
+            false
+        ));
+        assert!(is_7z_password_err(
+            &err(sevenz_rust::Error::MaybeBadPassword(io::Error::other(
+                "broken"
+            ))),
+            true
+        ));
+        assert!(!is_7z_password_err(
+            &err(sevenz_rust::Error::BadTerminatedUnpackInfo),
+            true
+        ));
+        assert!(!is_7z_password_err(
+            &err(sevenz_rust::Error::other("truncated")),
+            true
+        ));
     }
 }
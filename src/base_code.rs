@@ -0,0 +1,142 @@
+use crate::archive_handler::Registry;
+use crate::git::Checkout;
+use crate::helper;
+use crate::progress::Progress;
+use color_eyre::Result;
+use color_eyre::eyre::{Context, bail};
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, instrument};
+
+/// Appended to the name of the tmp dir, for the dir the base code is prepared in
+const DIR_SUFFIX: &str = "_base_code";
+
+/// The dir next to `tmp_dir`, which the base code is prepared in
+pub fn dir<P>(tmp_dir: P) -> Result<PathBuf>
+where
+    P: AsRef<Path> + Debug,
+{
+    helper::sibling_dir(tmp_dir, DIR_SUFFIX)
+}
+
+/// Extracts the base code `source` (a dir or an archive) next to `tmp_dir`
+/// and sanitizes it like the submissions, returns the dir to pass to `JPlag`
+///
+/// Fails, if the base code would be a submission itself,
+/// i.e. it is one of the `sources` (new or old) or inside one, or in one of the dirs of `jplag_args`
+#[instrument(skip(sources, jplag_args, registry, progress))]
+// Takes the options one by one, like `prepare`
+#[allow(clippy::too_many_arguments)]
+pub fn prepare<P>(
+    source: &str,
+    tmp_dir: P,
+    sources: &[String],
+    jplag_args: &[String],
+    keep_non_ascii: bool,
+    flatten_wrapper_dirs: bool,
+    registry: &Registry,
+    progress: &Progress,
+) -> Result<PathBuf>
+where
+    P: AsRef<Path> + Debug,
+{
+    info!("preparing base code {source:?}");
+    progress.set_phase("preparing base code");
+
+    let canonical_source =
+        fs::canonicalize(source).with_context(|| format!("unable to find base code {source:?}"))?;
+    for submissions in sources {
+        // A base code inside a dir of submissions would be extracted as a submission
+        if fs::canonicalize(submissions)
+            .is_ok_and(|submissions| canonical_source.starts_with(submissions))
+        {
+            bail!("the base code {source:?} is (inside) the source of submissions {submissions:?}");
+        }
+    }
+
+    let dir = dir(&tmp_dir)?;
+    debug!("removing {dir:?}");
+    let _ = fs::remove_dir_all(&dir);
+    let extracted = helper::extract_source(source, &dir, false, &Checkout::default(), registry)
+        .with_context(|| format!("unable to extract the base code {source:?} to {dir:?}"))?;
+    if let Some(e) = extracted.failed.into_iter().next() {
+        return Err(e.wrap_err(format!("unable to extract the base code {source:?}")));
+    }
+
+    helper::sanitize_submissions(&dir, keep_non_ascii, progress)
+        .with_context(|| format!("unable to sanitize the base code in {dir:?}"))?;
+    if flatten_wrapper_dirs {
+        helper::flatten_wrapper_dirs(&dir)
+            .with_context(|| format!("unable to flatten the base code in {dir:?}"))?;
+    }
+
+    helper::check_outside_roots(&dir, jplag_args)?;
+
+    info!("prepared base code in {dir:?}");
+
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prepare_in(
+        dir: &Path,
+        source: &Path,
+        sources: &[String],
+        jplag_args: &[String],
+    ) -> Result<PathBuf> {
+        prepare(
+            source.to_str().unwrap(),
+            dir.join("tmp"),
+            sources,
+            jplag_args,
+            false,
+            true,
+            &Registry::builtin(),
+            &Progress::start("testing"),
+        )
+    }
+
+    #[test]
+    fn prepares_next_to_the_tmp_dir() {
+        let dir = helper::scratch_dir("base_code_prepare");
+        let source = dir.join("template");
+        fs::create_dir_all(source.join("skeleton/.idea")).unwrap();
+        fs::write(source.join("skeleton/Main.java"), "class Main {}").unwrap();
+
+        let base_code = prepare_in(&dir, &source, &[], &[]).unwrap();
+
+        assert_eq!(base_code, dir.join("tmp_base_code"));
+        // Sanitized and flattened like a submission
+        assert!(base_code.join("Main.java").is_file());
+        assert!(!base_code.join(".idea").exists());
+        assert!(source.join("skeleton/Main.java").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_base_code_which_is_a_submission() {
+        let dir = helper::scratch_dir("base_code_rejected");
+        let source = dir.join("submissions");
+        fs::create_dir_all(source.join("alice")).unwrap();
+        let sources = [source.to_str().unwrap().to_owned()];
+
+        assert!(prepare_in(&dir, &source, &sources, &[]).is_err());
+        let err = prepare_in(&dir, &source.join("alice"), &sources, &[]).unwrap_err();
+        assert!(
+            format!("{err:?}").contains("inside) the source of submissions"),
+            "{err:?}"
+        );
+
+        let jplag_args = [String::from("--new"), dir.to_str().unwrap().to_owned()];
+        let err = prepare_in(&dir, &source, &[], &jplag_args).unwrap_err();
+        assert!(
+            format!("{err:?}").contains("jplag would compare it"),
+            "{err:?}"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+use crate::archive_handler::{ArchiveSource, Extraction};
+use crate::helper;
+use color_eyre::Result;
+use color_eyre::eyre::Context;
+use sha2::{Digest, Sha256};
+use std::fmt::{Debug, Write as _};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use tracing::{debug, instrument, trace};
+use walkdir::WalkDir;
+
+/// Persistent cache of extracted submissions, to skip the extraction on re-runs
+///
+/// An entry is keyed by the content of every file of the submission (archives and loose files)
+/// and the settings, which influence the extraction,
+/// so a changed archive or setting simply misses
+///
+/// Extracted submissions are cached before sanitization,
+/// which runs on restored submissions again, so its settings can change without invalidating the cache
+///
+/// Nothing is ever evicted, delete the cache dir to clear it
+#[derive(Debug)]
+pub struct Cache {
+    dir: PathBuf,
+    /// Hashed into every key
+    settings: String,
+}
+
+impl Cache {
+    pub fn new<P>(dir: P, settings: String) -> Result<Self>
+    where
+        P: AsRef<Path> + Debug,
+    {
+        let dir = dir.as_ref().to_owned();
+        fs::create_dir_all(&dir).with_context(|| format!("unable to create cache dir {dir:?}"))?;
+
+        Ok(Self { dir, settings })
+    }
+
+    /// Hashes the files of the submission and the archives of `extractions`,
+    /// which are not on disk (still inside the source zip)
+    ///
+    /// The latter are identified by the CRC32 and size stored in the source zip,
+    /// so they are not decompressed just to be hashed
+    #[instrument(skip(self, extractions))]
+    pub fn key(&self, student_name_dir_path: &Path, extractions: &[Extraction]) -> Result<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update([0]);
+        hasher.update(&self.settings);
+        hasher.update([0]);
+
+        let mut files = vec![];
+        for entry in WalkDir::new(student_name_dir_path).sort_by_file_name() {
+            let entry =
+                entry.with_context(|| format!("invalid entry in {student_name_dir_path:?}"))?;
+            if entry.file_type().is_file() {
+                files.push(entry.into_path());
+            }
+        }
+        for path in files {
+            let relative = path.strip_prefix(student_name_dir_path)?;
+            hasher.update(relative.as_os_str().as_encoded_bytes());
+            hasher.update([0]);
+            let mut file = File::open(&path).with_context(|| format!("unable to open {path:?}"))?;
+            io::copy(&mut file, &mut hasher).with_context(|| format!("unable to hash {path:?}"))?;
+            hasher.update([0]);
+        }
+
+        for extraction in extractions {
+            let ArchiveSource::Entry(entry) = &extraction.source else {
+                continue;
+            };
+            let relative = extraction.archive.strip_prefix(student_name_dir_path)?;
+            hasher.update(relative.as_os_str().as_encoded_bytes());
+            hasher.update([0]);
+            hasher.update(entry.crc32.to_le_bytes());
+            hasher.update(entry.size.to_le_bytes());
+            hasher.update([0]);
+        }
+
+        let mut key = String::new();
+        for byte in hasher.finalize() {
+            let _ = write!(key, "{byte:02x}");
+        }
+        trace!(key);
+
+        Ok(key)
+    }
+
+    /// Replaces the submission by the cached one, returns `false` on a miss
+    #[instrument(skip(self))]
+    pub fn restore(&self, key: &str, student_name_dir_path: &Path) -> Result<bool> {
+        let cached = self.dir.join(key);
+        if !cached.is_dir() {
+            trace!("cache miss");
+            return Ok(false);
+        }
+
+        debug!("restoring from {cached:?}");
+        fs::remove_dir_all(student_name_dir_path)
+            .with_context(|| format!("unable to remove {student_name_dir_path:?}"))?;
+        helper::link_tree(&cached, student_name_dir_path)?;
+
+        Ok(true)
+    }
+
+    /// Caches the extracted submission
+    ///
+    /// It is written next to the entry first, so a crash never leaves a partial entry behind
+    #[instrument(skip(self))]
+    pub fn store(&self, key: &str, student_name_dir_path: &Path) -> Result<()> {
+        let cached = self.dir.join(key);
+        if cached.is_dir() {
+            return Ok(());
+        }
+
+        let partial = self.dir.join(format!("{key}.{}.partial", process::id()));
+        let _ = fs::remove_dir_all(&partial);
+        helper::link_tree(student_name_dir_path, &partial)?;
+        fs::rename(&partial, &cached)
+            .with_context(|| format!("unable to move {partial:?} to {cached:?}"))?;
+        debug!("stored in {cached:?}");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive_handler::{Registry, SourceEntry};
+    use crate::detect::ArchiveKind;
+    use std::sync::Arc;
+
+    #[test]
+    fn key_changes_with_content_and_settings() {
+        let dir = helper::scratch_dir("cache_key");
+        let student_name_dir_path = dir.join("alice");
+        fs::create_dir(&student_name_dir_path).unwrap();
+        fs::write(student_name_dir_path.join("src.zip"), "v1").unwrap();
+        let cache = Cache::new(dir.join("cache"), String::from("depth 1")).unwrap();
+
+        let key = cache.key(&student_name_dir_path, &[]).unwrap();
+        assert_eq!(key, cache.key(&student_name_dir_path, &[]).unwrap());
+
+        let other_settings = Cache::new(dir.join("cache"), String::from("depth 2")).unwrap();
+        assert_ne!(
+            key,
+            other_settings.key(&student_name_dir_path, &[]).unwrap()
+        );
+
+        fs::write(student_name_dir_path.join("src.zip"), "v2").unwrap();
+        assert_ne!(key, cache.key(&student_name_dir_path, &[]).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn streamed_archives_are_keyed_without_reading_them() {
+        let dir = helper::scratch_dir("cache_key_streamed");
+        let student_name_dir_path = dir.join("alice");
+        fs::create_dir(&student_name_dir_path).unwrap();
+        let cache = Cache::new(dir.join("cache"), String::new()).unwrap();
+        let extraction = |name: &str, crc32| Extraction {
+            handler: Registry::builtin().find(ArchiveKind::Zip).unwrap(),
+            parent: dir.clone(),
+            dest: student_name_dir_path.clone(),
+            archive: student_name_dir_path.join(name),
+            // Reading it would fail, the source zip doesn't exist
+            source: ArchiveSource::Entry(SourceEntry {
+                source_zip: Arc::from(dir.join("missing.zip")),
+                index: 0,
+                size: 2,
+                crc32,
+                modified: None,
+            }),
+        };
+
+        let key = cache
+            .key(&student_name_dir_path, &[extraction("src.zip", 1)])
+            .unwrap();
+        assert_eq!(
+            key,
+            cache
+                .key(&student_name_dir_path, &[extraction("src.zip", 1)])
+                .unwrap()
+        );
+        assert_ne!(
+            key,
+            cache
+                .key(&student_name_dir_path, &[extraction("src.zip", 2)])
+                .unwrap()
+        );
+        assert_ne!(
+            key,
+            cache
+                .key(&student_name_dir_path, &[extraction("main.zip", 1)])
+                .unwrap()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn restores_stored_submissions() {
+        let dir = helper::scratch_dir("cache_restore");
+        let student_name_dir_path = dir.join("alice");
+        fs::create_dir_all(student_name_dir_path.join("src")).unwrap();
+        fs::write(student_name_dir_path.join("src/Main.java"), "class Main {}").unwrap();
+        let cache = Cache::new(dir.join("cache"), String::new()).unwrap();
+
+        assert!(!cache.restore("key", &student_name_dir_path).unwrap());
+        cache.store("key", &student_name_dir_path).unwrap();
+        fs::remove_dir_all(&student_name_dir_path).unwrap();
+        fs::create_dir(&student_name_dir_path).unwrap();
+
+        assert!(cache.restore("key", &student_name_dir_path).unwrap());
+        assert_eq!(
+            fs::read_to_string(student_name_dir_path.join("src/Main.java")).unwrap(),
+            "class Main {}"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
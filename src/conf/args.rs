@@ -1,5 +1,7 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use clap_complete::Shell;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
 
 #[cfg(debug_assertions)]
 const DEFAULT_LOG_LEVEL_STR: &str = "debug";
@@ -28,7 +30,9 @@ pub struct Args {
     /// will create (or override!) `config.toml` with all values
     /// and fill it with the defaults
     ///
-    /// Except `ignore_file`, because the default is `None`
+    /// Except `ignore_file`, `base_code`, `cache_dir`, `git_deadline`, `git_ref`,
+    /// `deadline` and `timestamps`,
+    /// because the default is `None`
     #[clap(long)]
     init: bool,
     /// Log Level to use
@@ -47,11 +51,17 @@ pub struct Args {
     /// Will panic, if file does not exist
     #[clap(short, long)]
     config: Option<String>,
-    /// Where the input file can be found
+    /// Where the input file can be found, can be repeated
+    ///
+    /// Either a dir of submissions, a dir of git repositories and bundles (one per student)
+    /// or an archive of submissions (zip, tar, compressed tar, 7z or rar)
+    ///
+    /// Multiple sources (e.g. one per tutorial group) are merged,
+    /// see `--duplicate-policy`, overrides the `source_zip` of the config
     ///
     /// Defaults to `submissions.zip`
-    #[clap(short, long)]
-    source_zip: Option<String>,
+    #[clap(short = 's', long = "source-zip")]
+    source_zips: Vec<String>,
     /// Where to put the results
     ///
     /// Defaults to `out/`
@@ -82,12 +92,179 @@ pub struct Args {
     /// Argument will be ignored if jplag args are manually set
     #[clap(short, long)]
     ignore_file: Option<String>,
+    /// Where to find the base code (the template handed out to the students),
+    /// either a dir or an archive
+    ///
+    /// It is extracted and sanitized like the submissions into `{{tmp_dir}}_base_code`
+    /// and passed to jplag as `--base-code`, so code of it isn't reported as plagiarism
+    ///
+    /// Defaults to None
+    ///
+    /// Passed even if jplag args are manually set, they must not contain `--base-code` then
+    #[clap(long)]
+    base_code: Option<String>,
     /// Set to ignore the output of jplag
     ///
     /// The program will still wait for the child process to exit
     /// and process the output, but it will just ignore it
     #[clap(long)]
     ignore_output: bool,
+    /// How deep archives inside of extracted submissions are extracted
+    ///
+    /// `0` disables nested extraction,
+    /// `1` extracts archives found in the submission archive, and so on
+    ///
+    /// Defaults to `3`
+    #[clap(long)]
+    max_nesting_depth: Option<usize>,
+    /// How many submissions are extracted in parallel
+    ///
+    /// Defaults to the number of CPUs
+    #[clap(long)]
+    jobs: Option<usize>,
+    /// Max uncompressed bytes a single submission may expand to
+    ///
+    /// Protects against decompression bombs, violators are reported as errors
+    ///
+    /// Defaults to `268435456` (256 MiB)
+    #[clap(long)]
+    max_submission_bytes: Option<u64>,
+    /// Max files and dirs a single submission may contain
+    ///
+    /// Defaults to `10000`
+    #[clap(long)]
+    max_entries: Option<u64>,
+    /// Max ratio between the uncompressed size of a submission and its archive
+    ///
+    /// Only checked once a submission expanded to more than 1 MiB
+    ///
+    /// Defaults to `200`
+    #[clap(long)]
+    max_compression_ratio: Option<u64>,
+    /// Max uncompressed bytes of all submissions together
+    ///
+    /// Defaults to `8589934592` (8 GiB)
+    #[clap(long)]
+    max_total_bytes: Option<u64>,
+    /// Password to try for encrypted zip, 7z and rar submissions, can be repeated
+    ///
+    /// Passwords are tried in the given order,
+    /// overrides the `passwords` of the config
+    #[clap(long = "password")]
+    passwords: Vec<String>,
+    /// Set to extract the archives of the submissions
+    /// directly from the `{{source_zip}}`, instead of unzipping them to `{{tmp_dir}}` first
+    ///
+    /// Only the extracted sources are written to disk, archives are read into memory
+    #[clap(long)]
+    stream: bool,
+    /// Set to keep files, which are no archives, instead of removing them
+    ///
+    /// A submission without any archive, but with loose files (e.g. `Main.java`)
+    /// is then passed through as is, only empty submissions are errors
+    #[clap(long)]
+    keep_loose_files: bool,
+    /// Set to keep non-ASCII characters (e.g. umlauts) in file names
+    ///
+    /// File names are always normalized to NFC,
+    /// without this, they are transliterated to ASCII as well (e.g. `Übung.java` -> `Ubung.java`)
+    #[clap(long)]
+    keep_non_ascii: bool,
+    /// Set to collapse directories, which only contain a single directory,
+    /// e.g. `alice/Assignment3/Assignment3/src` becomes `alice/src`
+    #[clap(long)]
+    flatten_wrapper_dirs: bool,
+    /// Set to keep the readable entries of corrupt or truncated zips and tars,
+    /// instead of dropping the submission
+    ///
+    /// A zip with a broken central directory is scanned for local file headers,
+    /// of a tar, everything before the corrupt block is kept
+    #[clap(long)]
+    salvage_corrupt: bool,
+    /// Where to cache extracted submissions, to restore unchanged ones on the next run
+    ///
+    /// Entries are keyed by the content of the submission and the extraction settings,
+    /// the cache is never cleaned up, just delete the directory
+    ///
+    /// Defaults to None (no cache)
+    #[clap(long)]
+    cache_dir: Option<String>,
+    /// What to do with a submission containing more than one archive
+    ///
+    /// Defaults to `reject`
+    #[clap(long, value_enum)]
+    multi_archive_policy: Option<MultiArchivePolicy>,
+    /// What to do, if a submission with the same name is in more than one source
+    ///
+    /// Defaults to `error`
+    #[clap(long, value_enum)]
+    duplicate_policy: Option<DuplicatePolicy>,
+    /// How the submissions are laid out in the `{{source_zip}}`
+    ///
+    /// `auto` recognizes exports of Moodle, Canvas, Blackboard and GitHub Classroom by their names,
+    /// their submissions are renamed to clean IDs (e.g. `Jane_Doe_123456`)
+    /// and the mapping back to the original names is written to
+    /// `{{target_dir}}/submission_mapping.toml`
+    ///
+    /// Defaults to `auto`
+    #[clap(long, value_enum)]
+    layout: Option<Layout>,
+    /// Check out the last commit before this deadline of each git repository,
+    /// in any format git understands (e.g. `2024-05-01 23:59`)
+    ///
+    /// The commit date is compared, repositories without such a commit are reported as errors
+    ///
+    /// Defaults to None (the latest commit)
+    #[clap(long)]
+    git_deadline: Option<String>,
+    /// Check out this tag or branch of each git repository
+    /// (combined with `--git-deadline`, the last commit of it before the deadline)
+    ///
+    /// Defaults to None (`HEAD`)
+    #[clap(long)]
+    git_ref: Option<String>,
+    /// Where old submissions (e.g. of previous semesters) can be found, can be repeated
+    ///
+    /// Like the `{{source_zip}}`, either a dir or an archive of submissions,
+    /// they are prepared the same way into `{{tmp_dir}}_old` and passed to jplag as `--old`,
+    /// so they are only compared against the new submissions, not against each other
+    ///
+    /// Overrides the `old_submissions` of the config
+    #[clap(long = "old-submission")]
+    old_submissions: Vec<String>,
+    /// Set to rename every submission to a pseudonym (e.g. `student_3f9a1c2b7d4e8a60`),
+    /// before jplag runs, so the results contain no names
+    ///
+    /// The pseudonym is a keyed hash of the name with the `anonymization_secret` of the config,
+    /// the mapping back to the names is written to `{{target_dir}}/pseudonym_mapping.toml`,
+    /// keep it away from the shared results and restore them with the `deanonymize` command
+    #[clap(long)]
+    anonymize: bool,
+    /// The deadline of the assignment, submissions after it are late
+    /// (e.g. `2024-05-01T23:59:00+02:00`, or `2024-05-01 23:59`, which is taken as UTC)
+    ///
+    /// The submission time is taken from `--timestamps`, then from the export
+    /// (e.g. the attempt of Blackboard), then from the newest modification time of its files
+    ///
+    /// Submissions marked late by the LMS (e.g. Canvas) are late without a deadline
+    ///
+    /// Defaults to None (only those marked late are late)
+    #[clap(long)]
+    deadline: Option<String>,
+    /// Set to only prepare the late submissions, the others are listed in the summary
+    #[clap(long, conflicts_with = "exclude_late")]
+    only_late: bool,
+    /// Set to only prepare the submissions in time, the late ones are listed in the summary
+    #[clap(long)]
+    exclude_late: bool,
+    /// A CSV with the submission time of each student, `student,timestamp` per line
+    ///
+    /// The student is the name of the submission dir or of the student in the mapping,
+    /// the timestamp is in the same format as `--deadline`
+    ///
+    /// Defaults to None
+    #[clap(long)]
+    timestamps: Option<String>,
     /// Where the jplag jar can be found
     ///
     /// Defaults to `jplag.jar`
@@ -101,6 +278,8 @@ pub struct Args {
     /// A list of additional submissions
     /// which will be treated exactly like normal submissions
     ///
+    /// To only compare them against the new submissions, use `--old-submission` instead
+    ///
     /// This means no validation will be performed,
     /// except for checking that each input exists and is a directory
     ///
@@ -124,6 +303,117 @@ pub enum Cmd {
         /// The shell to generate completions for
         shell: Shell,
     },
+    /// Restore the names in the results of an anonymized run,
+    /// only for staff, which may see them
+    Deanonymize {
+        /// The results zip of jplag or the dir of an extracted export
+        results: String,
+        /// The mapping of the pseudonyms, written by the anonymized run
+        ///
+        /// Defaults to `{{target_dir}}/pseudonym_mapping.toml`
+        #[clap(short, long)]
+        mapping: Option<String>,
+        /// Where to write the deanonymized results
+        ///
+        /// Defaults to the results with `_deanonymized` appended to the name
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+}
+
+/// What to do, if a student uploaded more than one archive (e.g. `v1.zip` and `v2.zip`)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MultiArchivePolicy {
+    /// Reject the submission and remove it
+    #[default]
+    Reject,
+    /// Extract all archives into the submission directory
+    Merge,
+    /// Extract each archive into its own numbered subdirectory (`1/`, `2/`, ...)
+    Numbered,
+    /// Only extract the archive with the newest modification time
+    Newest,
+    /// Only extract the largest archive
+    Largest,
+}
+
+impl Display for MultiArchivePolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Reject => "reject",
+            Self::Merge => "merge",
+            Self::Numbered => "numbered",
+            Self::Newest => "newest",
+            Self::Largest => "largest",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// What to do, if a submission with the same name is in more than one source
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    /// Abort the run
+    #[default]
+    Error,
+    /// Keep both, a counter is appended to the name of the later one (`alice_1`)
+    Suffix,
+    /// Keep the one with the newest file
+    Newest,
+}
+
+impl Display for DuplicatePolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Error => "error",
+            Self::Suffix => "suffix",
+            Self::Newest => "newest",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// How the submissions are laid out in the `{{source_zip}}`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layout {
+    /// Detect the layout by the names of the submissions, fall back to `plain`
+    #[default]
+    Auto,
+    /// One directory per student, its name is the submission ID
+    Plain,
+    /// Moodle assignment export, `Jane Doe_123456_assignsubmission_file_/`
+    /// (or `Jane Doe_123456_assignsubmission_file_Main.zip` without a folder per submission)
+    Moodle,
+    /// ILIAS exercise export, `jdoe/<attempt>/`, only the latest attempt is kept
+    ///
+    /// Never detected by `auto`
+    Ilias,
+    /// Canvas export, `lastfirst_12345_67890_Main.zip`
+    Canvas,
+    /// Blackboard export, `Assignment_jdoe_attempt_2024-01-31-23-59-59_Main.zip`,
+    /// only the latest attempt is kept
+    Blackboard,
+    /// GitHub Classroom, a dir of repositories named `assignment-username`,
+    /// their sources are kept as loose files, only detected by `auto`, if all entries are repositories
+    GithubClassroom,
+}
+
+impl Display for Layout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Auto => "auto",
+            Self::Plain => "plain",
+            Self::Moodle => "moodle",
+            Self::Ilias => "ilias",
+            Self::Canvas => "canvas",
+            Self::Blackboard => "blackboard",
+            Self::GithubClassroom => "github-classroom",
+        };
+        write!(f, "{name}")
+    }
 }
 
 #[allow(dead_code)]
@@ -148,12 +438,8 @@ impl Args {
         }
     }
 
-    pub const fn source_zip(&self) -> Option<&String> {
-        if let Some(ref zip) = self.source_zip {
-            Some(zip)
-        } else {
-            None
-        }
+    pub fn source_zips(&self) -> &[String] {
+        &self.source_zips
     }
 
     pub const fn target_dir(&self) -> Option<&String> {
@@ -184,10 +470,102 @@ impl Args {
         }
     }
 
+    pub const fn base_code(&self) -> Option<&String> {
+        if let Some(ref base_code) = self.base_code {
+            Some(base_code)
+        } else {
+            None
+        }
+    }
+
     pub const fn ignore_output(&self) -> bool {
         self.ignore_output
     }
 
+    pub const fn max_nesting_depth(&self) -> Option<usize> {
+        self.max_nesting_depth
+    }
+
+    pub const fn jobs(&self) -> Option<usize> {
+        self.jobs
+    }
+
+    pub const fn max_submission_bytes(&self) -> Option<u64> {
+        self.max_submission_bytes
+    }
+
+    pub const fn max_entries(&self) -> Option<u64> {
+        self.max_entries
+    }
+
+    pub const fn max_compression_ratio(&self) -> Option<u64> {
+        self.max_compression_ratio
+    }
+
+    pub const fn max_total_bytes(&self) -> Option<u64> {
+        self.max_total_bytes
+    }
+
+    pub fn passwords(&self) -> &[String] {
+        &self.passwords
+    }
+
+    pub const fn stream(&self) -> bool {
+        self.stream
+    }
+
+    pub const fn keep_loose_files(&self) -> bool {
+        self.keep_loose_files
+    }
+
+    pub const fn keep_non_ascii(&self) -> bool {
+        self.keep_non_ascii
+    }
+
+    pub const fn flatten_wrapper_dirs(&self) -> bool {
+        self.flatten_wrapper_dirs
+    }
+
+    pub const fn salvage_corrupt(&self) -> bool {
+        self.salvage_corrupt
+    }
+
+    pub const fn cache_dir(&self) -> Option<&String> {
+        if let Some(ref cache) = self.cache_dir {
+            Some(cache)
+        } else {
+            None
+        }
+    }
+
+    pub const fn multi_archive_policy(&self) -> Option<MultiArchivePolicy> {
+        self.multi_archive_policy
+    }
+
+    pub const fn duplicate_policy(&self) -> Option<DuplicatePolicy> {
+        self.duplicate_policy
+    }
+
+    pub const fn layout(&self) -> Option<Layout> {
+        self.layout
+    }
+
+    pub const fn git_deadline(&self) -> Option<&String> {
+        if let Some(ref deadline) = self.git_deadline {
+            Some(deadline)
+        } else {
+            None
+        }
+    }
+
+    pub const fn git_ref(&self) -> Option<&String> {
+        if let Some(ref reference) = self.git_ref {
+            Some(reference)
+        } else {
+            None
+        }
+    }
+
     pub const fn jplag_jar(&self) -> Option<&String> {
         if let Some(ref jar) = self.jplag_jar {
             Some(jar)
@@ -196,6 +574,38 @@ impl Args {
         }
     }
 
+    pub const fn anonymize(&self) -> bool {
+        self.anonymize
+    }
+
+    pub const fn deadline(&self) -> Option<&String> {
+        if let Some(ref deadline) = self.deadline {
+            Some(deadline)
+        } else {
+            None
+        }
+    }
+
+    pub const fn only_late(&self) -> bool {
+        self.only_late
+    }
+
+    pub const fn exclude_late(&self) -> bool {
+        self.exclude_late
+    }
+
+    pub const fn timestamps(&self) -> Option<&String> {
+        if let Some(ref timestamps) = self.timestamps {
+            Some(timestamps)
+        } else {
+            None
+        }
+    }
+
+    pub fn old_submissions(&self) -> &[String] {
+        &self.old_submissions
+    }
+
     pub fn add_sub_dirs(&self) -> &[String] {
         &self.add_sub_dirs
     }
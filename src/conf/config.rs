@@ -1,14 +1,21 @@
+use crate::anonymize;
 use crate::conf::args::{Args, Cmd};
+pub use crate::conf::args::{DuplicatePolicy, Layout, MultiArchivePolicy};
+use crate::deadline::{self, LateFilter};
+use crate::git::Checkout;
+use crate::safe_extract::Limits;
 use clap::{CommandFactory, Parser};
 use color_eyre::Result;
 use color_eyre::eyre::{Context, bail};
 use serde::{Deserialize, Serialize};
-use std::fmt::Debug;
+use std::fmt::{self, Debug, Formatter};
 use std::fs::OpenOptions;
 use std::io::{BufWriter, Write};
+use std::num::NonZeroUsize;
 use std::process::exit;
 use std::sync::LazyLock;
-use std::{fs, io};
+use std::{fs, io, thread};
+use time::OffsetDateTime;
 use tracing::{debug, info, instrument, warn};
 
 const DEFAULT_CONFIG_FILE: &str = "config.toml";
@@ -18,6 +25,19 @@ const DEFAULT_TARGET_DIR: &str = "out/";
 const DEFAULT_TMP_DIR: &str = "tmp/";
 const DEFAULT_RES_ZIP: &str = "results";
 const DEFAULT_JAVA_VERSION: &str = "java";
+const DEFAULT_MAX_NESTING_DEPTH: usize = 3;
+const DEFAULT_MAX_SUBMISSION_BYTES: u64 = 256 * 1024 * 1024;
+const DEFAULT_MAX_ENTRIES: u64 = 10_000;
+const DEFAULT_MAX_COMPRESSION_RATIO: u64 = 200;
+const DEFAULT_MAX_TOTAL_BYTES: u64 = 8 * 1024 * 1024 * 1024;
+const DEFAULT_STREAM: bool = false;
+const DEFAULT_KEEP_LOOSE_FILES: bool = false;
+const DEFAULT_KEEP_NON_ASCII: bool = false;
+const DEFAULT_FLATTEN_WRAPPER_DIRS: bool = false;
+const DEFAULT_SALVAGE_CORRUPT: bool = false;
+const DEFAULT_ANONYMIZE: bool = false;
+const DEFAULT_ONLY_LATE: bool = false;
+const DEFAULT_EXCLUDE_LATE: bool = false;
 
 pub static ARGS: LazyLock<Args> = LazyLock::new(Args::parse);
 static CONFIG: LazyLock<Config> = LazyLock::new(|| match parse_toml() {
@@ -25,25 +45,159 @@ static CONFIG: LazyLock<Config> = LazyLock::new(|| match parse_toml() {
     Err(e) => panic!("unable to parse config: {e:?}"),
 });
 
-#[derive(Debug)]
+// These are independent flags, not a state machine
+#[allow(clippy::struct_excessive_bools)]
 pub struct ParsedArgs {
-    pub source_file: String,
+    pub source_files: Vec<String>,
     pub tmp_dir: String,
     #[cfg(not(debug_assertions))]
     pub preserve_tmp_dir: bool,
     pub target_dir: String,
     pub abort_on_error: bool,
+    pub max_nesting_depth: usize,
+    pub jobs: usize,
+    pub limits: Limits,
+    pub passwords: Vec<String>,
+    pub stream: bool,
+    pub keep_loose_files: bool,
+    pub keep_non_ascii: bool,
+    pub flatten_wrapper_dirs: bool,
+    pub salvage_corrupt: bool,
+    pub cache_dir: Option<String>,
+    pub multi_archive_policy: MultiArchivePolicy,
+    pub duplicate_policy: DuplicatePolicy,
+    pub layout: Layout,
+    pub checkout: Checkout,
+    pub old_submissions: Vec<String>,
+    /// The secret to pseudonymize the submissions with, `None` if they aren't anonymized
+    pub anonymization_secret: Option<String>,
+    pub deadline: Option<OffsetDateTime>,
+    pub late_filter: LateFilter,
+    /// A CSV with the submission time of each student
+    pub timestamps: Option<String>,
     pub jplag_jar: String,
     pub jplag_args: Vec<String>,
+    pub base_code: Option<String>,
     pub additional_submission_dirs: Vec<String>,
 }
 
+/// Hides the `passwords` and the `anonymization_secret`, the args are logged
+impl Debug for ParsedArgs {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // Destructured, so a new field can't be forgotten
+        let Self {
+            source_files,
+            tmp_dir,
+            #[cfg(not(debug_assertions))]
+            preserve_tmp_dir,
+            target_dir,
+            abort_on_error,
+            max_nesting_depth,
+            jobs,
+            limits,
+            passwords,
+            stream,
+            keep_loose_files,
+            keep_non_ascii,
+            flatten_wrapper_dirs,
+            salvage_corrupt,
+            cache_dir,
+            multi_archive_policy,
+            duplicate_policy,
+            layout,
+            checkout,
+            old_submissions,
+            anonymization_secret,
+            deadline,
+            late_filter,
+            timestamps,
+            jplag_jar,
+            jplag_args,
+            base_code,
+            additional_submission_dirs,
+        } = self;
+
+        let mut debug = f.debug_struct("ParsedArgs");
+        debug
+            .field("source_files", source_files)
+            .field("tmp_dir", tmp_dir);
+        #[cfg(not(debug_assertions))]
+        debug.field("preserve_tmp_dir", preserve_tmp_dir);
+        debug
+            .field("target_dir", target_dir)
+            .field("abort_on_error", abort_on_error)
+            .field("max_nesting_depth", max_nesting_depth)
+            .field("jobs", jobs)
+            .field("limits", limits)
+            .field("passwords", &format_args!("<{} hidden>", passwords.len()))
+            .field("stream", stream)
+            .field("keep_loose_files", keep_loose_files)
+            .field("keep_non_ascii", keep_non_ascii)
+            .field("flatten_wrapper_dirs", flatten_wrapper_dirs)
+            .field("salvage_corrupt", salvage_corrupt)
+            .field("cache_dir", cache_dir)
+            .field("multi_archive_policy", multi_archive_policy)
+            .field("duplicate_policy", duplicate_policy)
+            .field("layout", layout)
+            .field("checkout", checkout)
+            .field("old_submissions", old_submissions)
+            .field(
+                "anonymization_secret",
+                &anonymization_secret
+                    .as_ref()
+                    .map(|_| format_args!("<hidden>")),
+            )
+            .field("deadline", deadline)
+            .field("late_filter", late_filter)
+            .field("timestamps", timestamps)
+            .field("jplag_jar", jplag_jar)
+            .field("jplag_args", jplag_args)
+            .field("base_code", base_code)
+            .field("additional_submission_dirs", additional_submission_dirs)
+            .finish()
+    }
+}
+
+/// A single source or a list of them
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Sources {
+    One(String),
+    Many(Vec<String>),
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 struct Config {
-    source_zip: Option<String>,
+    source_zip: Option<Sources>,
     target_dir: Option<String>,
     tmp_dir: Option<String>,
     ignore_file: Option<String>,
+    base_code: Option<String>,
+    max_nesting_depth: Option<usize>,
+    jobs: Option<usize>,
+    max_submission_bytes: Option<u64>,
+    max_entries: Option<u64>,
+    max_compression_ratio: Option<u64>,
+    max_total_bytes: Option<u64>,
+    passwords: Option<Vec<String>>,
+    stream: Option<bool>,
+    keep_loose_files: Option<bool>,
+    keep_non_ascii: Option<bool>,
+    flatten_wrapper_dirs: Option<bool>,
+    salvage_corrupt: Option<bool>,
+    cache_dir: Option<String>,
+    multi_archive_policy: Option<MultiArchivePolicy>,
+    duplicate_policy: Option<DuplicatePolicy>,
+    layout: Option<Layout>,
+    git_deadline: Option<String>,
+    git_ref: Option<String>,
+    old_submissions: Option<Vec<String>>,
+    anonymize: Option<bool>,
+    anonymization_secret: Option<String>,
+    deadline: Option<String>,
+    only_late: Option<bool>,
+    exclude_late: Option<bool>,
+    timestamps: Option<String>,
     jplag_jar: Option<String>,
     jplag_args: Option<Vec<String>>,
 }
@@ -72,17 +226,20 @@ pub fn parse_args() -> Result<ParsedArgs> {
 
     debug!("successfully parsed toml");
 
-    let source = ARGS.source_zip().map_or_else(
-        || {
-            CONFIG
-                .source_zip
-                .clone()
-                .unwrap_or_else(|| DEFAULT_SOURCE_FILE.to_string())
-        },
-        ToOwned::to_owned,
-    );
+    let source_files = if ARGS.source_zips().is_empty() {
+        match &CONFIG.source_zip {
+            Some(Sources::One(source)) => vec![source.clone()],
+            Some(Sources::Many(sources)) => sources.clone(),
+            None => vec![DEFAULT_SOURCE_FILE.to_string()],
+        }
+    } else {
+        ARGS.source_zips().to_vec()
+    };
+    if source_files.is_empty() {
+        bail!("source_zip has to contain at least one source");
+    }
 
-    debug!("set source to {source}");
+    debug!("set sources to {source_files:?}");
 
     let tmp_dir = ARGS.tmp_dir().map_or_else(
         || {
@@ -114,6 +271,193 @@ pub fn parse_args() -> Result<ParsedArgs> {
 
     debug!("set target dir to {target_dir}");
 
+    if let Some(Cmd::Deanonymize {
+        results,
+        mapping,
+        output,
+    }) = ARGS.cmd()
+    {
+        let mapping = mapping
+            .clone()
+            .unwrap_or_else(|| format!("{target_dir}/{}", anonymize::MAPPING_FILE_NAME));
+        anonymize::deanonymize(results, &mapping, output.as_deref())
+            .with_context(|| format!("unable to deanonymize {results:?}"))?;
+        exit(0);
+    }
+
+    let max_nesting_depth = ARGS
+        .max_nesting_depth()
+        .or(CONFIG.max_nesting_depth)
+        .unwrap_or(DEFAULT_MAX_NESTING_DEPTH);
+
+    debug!("set max_nesting_depth to {max_nesting_depth}");
+
+    let jobs = ARGS
+        .jobs()
+        .or(CONFIG.jobs)
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get));
+    if jobs == 0 {
+        bail!("jobs has to be at least 1");
+    }
+
+    debug!("set jobs to {jobs}");
+
+    let limits = Limits {
+        max_submission_bytes: ARGS
+            .max_submission_bytes()
+            .or(CONFIG.max_submission_bytes)
+            .unwrap_or(DEFAULT_MAX_SUBMISSION_BYTES),
+        max_entries: ARGS
+            .max_entries()
+            .or(CONFIG.max_entries)
+            .unwrap_or(DEFAULT_MAX_ENTRIES),
+        max_compression_ratio: ARGS
+            .max_compression_ratio()
+            .or(CONFIG.max_compression_ratio)
+            .unwrap_or(DEFAULT_MAX_COMPRESSION_RATIO),
+        max_total_bytes: ARGS
+            .max_total_bytes()
+            .or(CONFIG.max_total_bytes)
+            .unwrap_or(DEFAULT_MAX_TOTAL_BYTES),
+    };
+
+    debug!("set limits to {limits:?}");
+
+    let passwords = if ARGS.passwords().is_empty() {
+        CONFIG.passwords.clone().unwrap_or_default()
+    } else {
+        ARGS.passwords().to_vec()
+    };
+
+    debug!("set {} passwords", passwords.len());
+
+    // A flag can only enable these, so the config can't be overridden to `false` from the cli
+    let stream = ARGS.stream() || CONFIG.stream.unwrap_or(DEFAULT_STREAM);
+
+    debug!("set stream to {stream}");
+
+    let keep_loose_files =
+        ARGS.keep_loose_files() || CONFIG.keep_loose_files.unwrap_or(DEFAULT_KEEP_LOOSE_FILES);
+
+    debug!("set keep_loose_files to {keep_loose_files}");
+
+    let keep_non_ascii =
+        ARGS.keep_non_ascii() || CONFIG.keep_non_ascii.unwrap_or(DEFAULT_KEEP_NON_ASCII);
+
+    debug!("set keep_non_ascii to {keep_non_ascii}");
+
+    let flatten_wrapper_dirs = ARGS.flatten_wrapper_dirs()
+        || CONFIG
+            .flatten_wrapper_dirs
+            .unwrap_or(DEFAULT_FLATTEN_WRAPPER_DIRS);
+
+    debug!("set flatten_wrapper_dirs to {flatten_wrapper_dirs}");
+
+    let salvage_corrupt =
+        ARGS.salvage_corrupt() || CONFIG.salvage_corrupt.unwrap_or(DEFAULT_SALVAGE_CORRUPT);
+
+    debug!("set salvage_corrupt to {salvage_corrupt}");
+
+    let cache_dir = ARGS
+        .cache_dir()
+        .map(ToOwned::to_owned)
+        .or_else(|| CONFIG.cache_dir.clone());
+
+    debug!("set cache_dir to {cache_dir:?}");
+
+    let multi_archive_policy = ARGS
+        .multi_archive_policy()
+        .or(CONFIG.multi_archive_policy)
+        .unwrap_or_default();
+
+    debug!("set multi_archive_policy to {multi_archive_policy:?}");
+
+    let duplicate_policy = ARGS
+        .duplicate_policy()
+        .or(CONFIG.duplicate_policy)
+        .unwrap_or_default();
+
+    debug!("set duplicate_policy to {duplicate_policy:?}");
+
+    let layout = ARGS.layout().or(CONFIG.layout).unwrap_or_default();
+
+    debug!("set layout to {layout:?}");
+
+    let checkout = Checkout {
+        deadline: ARGS
+            .git_deadline()
+            .map(ToOwned::to_owned)
+            .or_else(|| CONFIG.git_deadline.clone()),
+        reference: ARGS
+            .git_ref()
+            .map(ToOwned::to_owned)
+            .or_else(|| CONFIG.git_ref.clone()),
+    };
+
+    debug!("set checkout to {checkout:?}");
+
+    let old_submissions = if ARGS.old_submissions().is_empty() {
+        CONFIG.old_submissions.clone().unwrap_or_default()
+    } else {
+        ARGS.old_submissions().to_vec()
+    };
+    for old in &old_submissions {
+        if !fs::exists(old).with_context(|| format!("unable to check if \"{old}\" exists"))? {
+            bail!("old submissions \"{old}\" not found");
+        }
+    }
+
+    debug!("set old_submissions to {old_submissions:?}");
+
+    let anonymize = ARGS.anonymize() || CONFIG.anonymize.unwrap_or(DEFAULT_ANONYMIZE);
+    let anonymization_secret = if anonymize {
+        match &CONFIG.anonymization_secret {
+            Some(secret) if !secret.is_empty() => Some(secret.clone()),
+            _ => bail!("anonymize is set, but the config has no anonymization_secret"),
+        }
+    } else {
+        None
+    };
+
+    debug!("set anonymize to {anonymize}");
+
+    let deadline = ARGS
+        .deadline()
+        .map(ToOwned::to_owned)
+        .or_else(|| CONFIG.deadline.clone())
+        .map(|deadline| deadline::parse_timestamp(&deadline))
+        .transpose()
+        .context("invalid deadline")?;
+
+    debug!("set deadline to {deadline:?}");
+
+    let only_late = ARGS.only_late() || CONFIG.only_late.unwrap_or(DEFAULT_ONLY_LATE);
+    let exclude_late = ARGS.exclude_late() || CONFIG.exclude_late.unwrap_or(DEFAULT_EXCLUDE_LATE);
+    let late_filter = match (only_late, exclude_late) {
+        (true, true) => bail!("only_late and exclude_late are both set"),
+        (true, false) => LateFilter::OnlyLate,
+        (false, true) => LateFilter::ExcludeLate,
+        (false, false) => LateFilter::All,
+    };
+    if late_filter != LateFilter::All && deadline.is_none() {
+        warn!("no deadline is set, only submissions marked late by the export are late");
+    }
+
+    debug!("set late_filter to {late_filter:?}");
+
+    let timestamps = ARGS
+        .timestamps()
+        .map(ToOwned::to_owned)
+        .or_else(|| CONFIG.timestamps.clone());
+    if let Some(timestamps) = &timestamps
+        && !fs::exists(timestamps)
+            .with_context(|| format!("unable to check if \"{timestamps}\" exists"))?
+    {
+        bail!("timestamps \"{timestamps}\" not found");
+    }
+
+    debug!("set timestamps to {timestamps:?}");
+
     let jplag_jar = ARGS.jplag_jar().map_or_else(
         || {
             CONFIG
@@ -171,6 +515,27 @@ pub fn parse_args() -> Result<ParsedArgs> {
 
     debug!("set jplag args to {jplag_args:?}");
 
+    let base_code = ARGS
+        .base_code()
+        .map(ToOwned::to_owned)
+        .or_else(|| CONFIG.base_code.clone());
+    if let Some(base_code) = &base_code {
+        if !fs::exists(base_code)
+            .with_context(|| format!("unable to check if \"{base_code}\" exists"))?
+        {
+            bail!("base code \"{base_code}\" not found");
+        }
+        if jplag_args.iter().any(|arg| {
+            ["-bc", "--bc", "--base-code"]
+                .iter()
+                .any(|flag| arg == flag || arg.starts_with(&format!("{flag}=")))
+        }) {
+            bail!("base code is set, so the jplag args must not contain --base-code");
+        }
+    }
+
+    debug!("set base_code to {base_code:?}");
+
     let additional_submission_dirs = ARGS.add_sub_dirs().to_vec();
 
     debug!("additional submission dirs: {additional_submission_dirs:?}");
@@ -178,14 +543,34 @@ pub fn parse_args() -> Result<ParsedArgs> {
     info!("successfully parsed config");
 
     let parsed_args = ParsedArgs {
-        source_file: source,
+        source_files,
         tmp_dir,
         #[cfg(not(debug_assertions))]
         preserve_tmp_dir,
         target_dir,
         abort_on_error: ARGS.abort_on_err(),
+        max_nesting_depth,
+        jobs,
+        limits,
+        passwords,
+        stream,
+        keep_loose_files,
+        keep_non_ascii,
+        flatten_wrapper_dirs,
+        salvage_corrupt,
+        cache_dir,
+        multi_archive_policy,
+        duplicate_policy,
+        layout,
+        checkout,
+        old_submissions,
+        anonymization_secret,
+        deadline,
+        late_filter,
+        timestamps,
         jplag_jar,
         jplag_args,
+        base_code,
         additional_submission_dirs,
     };
 
@@ -211,6 +596,32 @@ fn parse_toml() -> Result<Config> {
             target_dir: None,
             tmp_dir: None,
             ignore_file: None,
+            base_code: None,
+            max_nesting_depth: None,
+            jobs: None,
+            max_submission_bytes: None,
+            max_entries: None,
+            max_compression_ratio: None,
+            max_total_bytes: None,
+            passwords: None,
+            stream: None,
+            keep_loose_files: None,
+            keep_non_ascii: None,
+            flatten_wrapper_dirs: None,
+            salvage_corrupt: None,
+            cache_dir: None,
+            multi_archive_policy: None,
+            duplicate_policy: None,
+            layout: None,
+            git_deadline: None,
+            git_ref: None,
+            old_submissions: None,
+            anonymize: None,
+            anonymization_secret: None,
+            deadline: None,
+            only_late: None,
+            exclude_late: None,
+            timestamps: None,
             jplag_jar: None,
             jplag_args: None,
         });
@@ -219,15 +630,29 @@ fn parse_toml() -> Result<Config> {
     let toml = fs::read_to_string(&conf_file)
         .with_context(|| format!("failed to read from config file {conf_file}"))?;
 
-    debug!("parsing toml, raw: {toml}");
-    toml::from_str::<Config>(&toml).with_context(|| {
-        format!(
-            "unable to parse to Config, raw string:\
-            \n\"\"\"\n\
-            {toml}\
-            \"\"\""
-        )
-    })
+    parse_config(&conf_file, &toml)
+}
+
+/// Parses the `toml` read from `conf_file`
+///
+/// Neither the raw toml nor the line of an error is logged or reported,
+/// they may contain the passwords and the anonymization secret
+fn parse_config(conf_file: &str, toml: &str) -> Result<Config> {
+    match toml::from_str::<Config>(toml) {
+        Ok(config) => Ok(config),
+        Err(e) => {
+            let position = e.span().map_or_else(String::new, |span| {
+                let before = &toml[..span.start];
+                let line = before.matches('\n').count() + 1;
+                let column = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+                format!(" at line {line}, column {column}")
+            });
+            bail!(
+                "unable to parse config file {conf_file}{position}: {}",
+                e.message()
+            )
+        }
+    }
 }
 
 #[instrument]
@@ -247,10 +672,36 @@ fn dump_default_config() -> Result<()> {
     }
 
     let conf = Config {
-        source_zip: Some(String::from(DEFAULT_SOURCE_FILE)),
+        source_zip: Some(Sources::One(String::from(DEFAULT_SOURCE_FILE))),
         target_dir: Some(String::from(DEFAULT_TARGET_DIR)),
         tmp_dir: Some(String::from(DEFAULT_TMP_DIR)),
         ignore_file: None, // Don't like it, but if we set something, the next run might fail
+        base_code: None,   // Same as `ignore_file`
+        max_nesting_depth: Some(DEFAULT_MAX_NESTING_DEPTH),
+        jobs: None, // Defaults to the number of CPUs of the machine running it
+        max_submission_bytes: Some(DEFAULT_MAX_SUBMISSION_BYTES),
+        max_entries: Some(DEFAULT_MAX_ENTRIES),
+        max_compression_ratio: Some(DEFAULT_MAX_COMPRESSION_RATIO),
+        max_total_bytes: Some(DEFAULT_MAX_TOTAL_BYTES),
+        passwords: Some(vec![]),
+        stream: Some(DEFAULT_STREAM),
+        keep_loose_files: Some(DEFAULT_KEEP_LOOSE_FILES),
+        keep_non_ascii: Some(DEFAULT_KEEP_NON_ASCII),
+        flatten_wrapper_dirs: Some(DEFAULT_FLATTEN_WRAPPER_DIRS),
+        salvage_corrupt: Some(DEFAULT_SALVAGE_CORRUPT),
+        cache_dir: None, // Caching is opt-in, it is never cleaned up
+        multi_archive_policy: Some(MultiArchivePolicy::default()),
+        duplicate_policy: Some(DuplicatePolicy::default()),
+        layout: Some(Layout::default()),
+        git_deadline: None, // The latest commit is checked out
+        git_ref: None,      // `HEAD` is checked out
+        old_submissions: Some(vec![]),
+        anonymize: Some(DEFAULT_ANONYMIZE),
+        anonymization_secret: None, // Has to be set to anonymize, keep it secret
+        deadline: None,             // Only submissions marked late by the export are late
+        only_late: Some(DEFAULT_ONLY_LATE),
+        exclude_late: Some(DEFAULT_EXCLUDE_LATE),
+        timestamps: None, // Taken from the export or the files
         jplag_jar: Some(String::from(DEFAULT_JPLAG_FILE)),
         // If you change this, change the default args in in `parse_args()` too
         jplag_args: Some(vec![
@@ -297,3 +748,67 @@ fn dump_default_config() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_errors_hide_the_toml() {
+        let toml =
+            "jplag_jar = \"jplag.jar\"\npasswords = [\"course password\"\nanonymize = true\n";
+
+        let err = parse_config("config.toml", toml).unwrap_err();
+
+        let msg = format!("{err:?}");
+        assert!(msg.contains("config.toml at line 3"), "{msg}");
+        assert!(!msg.contains("course password"), "{msg}");
+        assert!(!msg.contains("jplag.jar"), "{msg}");
+    }
+
+    #[test]
+    fn debug_hides_the_secrets() {
+        let args = ParsedArgs {
+            source_files: vec!["submissions.zip".to_owned()],
+            tmp_dir: "tmp".to_owned(),
+            #[cfg(not(debug_assertions))]
+            preserve_tmp_dir: false,
+            target_dir: "out".to_owned(),
+            abort_on_error: false,
+            max_nesting_depth: 3,
+            jobs: 1,
+            limits: Limits::UNLIMITED,
+            passwords: vec!["course password".to_owned(), "other".to_owned()],
+            stream: false,
+            keep_loose_files: false,
+            keep_non_ascii: false,
+            flatten_wrapper_dirs: false,
+            salvage_corrupt: false,
+            cache_dir: None,
+            multi_archive_policy: MultiArchivePolicy::default(),
+            duplicate_policy: DuplicatePolicy::default(),
+            layout: Layout::default(),
+            checkout: Checkout::default(),
+            old_submissions: vec![],
+            anonymization_secret: Some("hmac secret".to_owned()),
+            deadline: None,
+            late_filter: LateFilter::default(),
+            timestamps: None,
+            jplag_jar: "jplag.jar".to_owned(),
+            jplag_args: vec![],
+            base_code: None,
+            additional_submission_dirs: vec![],
+        };
+
+        let debug = format!("{args:?}");
+
+        assert!(!debug.contains("course password"), "{debug}");
+        assert!(!debug.contains("hmac secret"), "{debug}");
+        assert!(debug.contains("passwords: <2 hidden>"), "{debug}");
+        assert!(
+            debug.contains("anonymization_secret: Some(<hidden>)"),
+            "{debug}"
+        );
+        assert!(debug.contains("submissions.zip"), "{debug}");
+    }
+}
@@ -0,0 +1,323 @@
+use crate::helper;
+use crate::layout::{Mapping, Streamed};
+use crate::sources;
+use color_eyre::Result;
+use color_eyre::eyre::{Context, ContextCompat, bail};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+use time::format_description::BorrowedFormatItem;
+use time::format_description::well_known::Rfc3339;
+use time::macros::format_description;
+use time::{OffsetDateTime, PrimitiveDateTime};
+use tracing::{debug, info, instrument, trace};
+
+/// Timestamps without an offset, they are taken as UTC
+const NAIVE_FORMATS: [&[BorrowedFormatItem<'_>]; 2] = [
+    format_description!("[year]-[month]-[day] [hour]:[minute]:[second]"),
+    format_description!("[year]-[month]-[day] [hour]:[minute]"),
+];
+
+/// When a submission was submitted, as far as the export tells
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Submitted {
+    #[serde(
+        rename = "submitted",
+        skip_serializing_if = "Option::is_none",
+        with = "time::serde::rfc3339::option"
+    )]
+    pub at: Option<OffsetDateTime>,
+    /// Marked late by the LMS itself (e.g. `LATE` in the names of a Canvas export)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub marked_late: bool,
+}
+
+impl Submitted {
+    /// Of a submission put together from several entries, the latest counts
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            at: self.at.max(other.at),
+            marked_late: self.marked_late || other.marked_late,
+        }
+    }
+}
+
+/// Which submissions are prepared, by whether they are late
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LateFilter {
+    #[default]
+    All,
+    OnlyLate,
+    ExcludeLate,
+}
+
+/// Parses an RFC 3339 timestamp (e.g. `2024-05-01T23:59:00+02:00`)
+/// or one without an offset (e.g. `2024-05-01 23:59`), which is taken as UTC
+pub fn parse_timestamp(timestamp: &str) -> Result<OffsetDateTime> {
+    let timestamp = timestamp.trim();
+    if let Ok(parsed) = OffsetDateTime::parse(timestamp, &Rfc3339) {
+        return Ok(parsed);
+    }
+
+    let naive = timestamp.replacen('T', " ", 1);
+    for format in NAIVE_FORMATS {
+        if let Ok(parsed) = PrimitiveDateTime::parse(&naive, format) {
+            return Ok(parsed.assume_utc());
+        }
+    }
+
+    bail!(
+        "unable to parse the timestamp {timestamp:?}, \
+        expected e.g. `2024-05-01T23:59:00+02:00` or `2024-05-01 23:59` (UTC)"
+    );
+}
+
+/// Reads the submission times of a CSV, `student,timestamp` per line (`;` works too),
+/// a header is skipped
+///
+/// The student is the last column but one, so a name like `Doe, Jane` doesn't need quotes
+#[instrument]
+pub fn read_csv(path: &str) -> Result<HashMap<String, OffsetDateTime>> {
+    let csv = fs::read_to_string(path).with_context(|| format!("unable to read {path:?}"))?;
+    let unquote = |s: &str| s.trim().trim_matches('"').to_owned();
+
+    let mut timestamps = HashMap::new();
+    for (i, line) in csv.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let (student, timestamp) = line
+            .rsplit_once([',', ';'])
+            .with_context(|| format!("line {} of {path:?} has only one column", i + 1))?;
+        match parse_timestamp(&unquote(timestamp)) {
+            Ok(timestamp) => {
+                timestamps.insert(unquote(student), timestamp);
+            }
+            Err(_) if i == 0 => trace!("skipping the header {line:?}"),
+            Err(e) => return Err(e.wrap_err(format!("invalid line {} of {path:?}", i + 1))),
+        }
+    }
+    debug!("read {} timestamps", timestamps.len());
+
+    Ok(timestamps)
+}
+
+/// The submissions, which the filter removed, and the late ones, which were kept
+#[derive(Debug, Default)]
+pub struct Filtered {
+    pub removed: Vec<String>,
+    pub notes: Vec<String>,
+}
+
+/// Removes the submissions in `tmp_dir`, which `late_filter` excludes,
+/// along with their `streamed` archives and their entries of the `mapping`
+///
+/// A submission is late, if it was marked late by the LMS or submitted after the `deadline`,
+/// the time is taken from the `timestamps` (by ID or name of the student),
+/// then from the export, then from the newest modification time of its files
+#[instrument(skip(timestamps, mapping, streamed))]
+pub fn filter<P>(
+    tmp_dir: P,
+    deadline: Option<OffsetDateTime>,
+    late_filter: LateFilter,
+    timestamps: &HashMap<String, OffsetDateTime>,
+    mapping: &mut Option<Mapping>,
+    streamed: &mut Streamed,
+) -> Result<Filtered>
+where
+    P: AsRef<Path> + Debug,
+{
+    let tmp_dir = tmp_dir.as_ref();
+
+    let mut submissions = fs::read_dir(tmp_dir)
+        .with_context(|| format!("unable to read {tmp_dir:?}"))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("unable to read a dir in {tmp_dir:?}"))?;
+    submissions.sort();
+
+    let mut filtered = Filtered::default();
+    for path in submissions {
+        let name = path
+            .file_name()
+            .with_context(|| format!("unable to get the name of {path:?}"))?
+            .to_string_lossy()
+            .into_owned();
+        let mapped = mapping.as_ref().and_then(|mapping| {
+            mapping
+                .submissions
+                .iter()
+                .find(|submission| submission.id == name)
+        });
+
+        let at = timestamps
+            .get(&name)
+            .or_else(|| mapped.and_then(|mapped| timestamps.get(&mapped.student)))
+            .copied()
+            .or_else(|| mapped.and_then(|mapped| mapped.submitted.at))
+            .or_else(|| sources::newest_modification(&path, streamed).map(OffsetDateTime::from));
+        let marked_late = mapped.is_some_and(|mapped| mapped.submitted.marked_late);
+        let late = marked_late || deadline.zip(at).is_some_and(|(deadline, at)| at > deadline);
+        trace!(name, ?at, marked_late, late);
+
+        let at_rfc3339 = at.map(|at| at.format(&Rfc3339).unwrap_or_else(|_| at.to_string()));
+        let description = match (at_rfc3339, marked_late) {
+            (Some(at), true) => format!("{name:?}, submitted at {at}, marked late"),
+            (Some(at), false) => format!("{name:?}, submitted at {at}"),
+            (None, true) => format!("{name:?}, marked late"),
+            (None, false) => format!("{name:?}, submission time unknown"),
+        };
+        let keep = match late_filter {
+            LateFilter::All => true,
+            LateFilter::OnlyLate => late,
+            LateFilter::ExcludeLate => !late,
+        };
+        if keep {
+            if late {
+                filtered.notes.push(format!("{description}, is late"));
+            }
+            continue;
+        }
+
+        trace!("removing {path:?}");
+        helper::remove_entry(&path)?;
+        streamed.retain(|(archive, _, _)| !archive.starts_with(&path));
+        if let Some(mapping) = mapping {
+            mapping
+                .submissions
+                .retain(|submission| submission.id != name);
+        }
+        filtered.removed.push(description);
+    }
+
+    match filtered.removed.len() {
+        0 => {}
+        1 => info!("filtered out one submission"),
+        n => info!("filtered out {n} submissions"),
+    }
+
+    Ok(filtered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use time::macros::datetime;
+
+    fn write_csv(name: &str, csv: &str) -> String {
+        let dir = helper::scratch_dir(name);
+        let path = dir.join("timestamps.csv");
+        fs::write(&path, csv).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn parse_timestamp_with_offset() {
+        assert_eq!(
+            parse_timestamp("2024-05-01T23:59:00+02:00").unwrap(),
+            datetime!(2024-05-01 23:59:00 +02:00)
+        );
+        assert_eq!(
+            parse_timestamp(" 2024-05-01T21:59:00Z ").unwrap(),
+            datetime!(2024-05-01 21:59:00 UTC)
+        );
+    }
+
+    #[test]
+    fn parse_timestamp_without_offset_is_utc() {
+        for timestamp in [
+            "2024-05-01 23:59:30",
+            "2024-05-01T23:59:30",
+            "2024-05-01 23:59",
+            "2024-05-01T23:59",
+        ] {
+            let parsed = parse_timestamp(timestamp).unwrap();
+            assert_eq!(parsed.offset(), time::UtcOffset::UTC, "{timestamp}");
+            assert_eq!(
+                parsed.replace_second(0).unwrap(),
+                datetime!(2024-05-01 23:59:00 UTC),
+                "{timestamp}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_timestamp_rejects_garbage() {
+        assert!(parse_timestamp("yesterday").is_err());
+        assert!(parse_timestamp("2024-13-01 12:00").is_err());
+        assert!(parse_timestamp("").is_err());
+    }
+
+    #[test]
+    fn read_csv_skips_the_header() {
+        let path = write_csv(
+            "read_csv_header",
+            "student,submitted\nalice,2024-05-01 12:00\n\nbob;2024-05-02T12:00:00Z\n",
+        );
+        let timestamps = read_csv(&path).unwrap();
+
+        assert_eq!(timestamps.len(), 2);
+        assert_eq!(timestamps["alice"], datetime!(2024-05-01 12:00 UTC));
+        assert_eq!(timestamps["bob"], datetime!(2024-05-02 12:00 UTC));
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn read_csv_keeps_commas_in_names() {
+        let path = write_csv(
+            "read_csv_commas",
+            "Doe, Jane,2024-05-01 12:00\n\"Roe, Richard\",\"2024-05-01 13:00\"\n",
+        );
+        let timestamps = read_csv(&path).unwrap();
+
+        assert_eq!(timestamps["Doe, Jane"], datetime!(2024-05-01 12:00 UTC));
+        assert_eq!(timestamps["Roe, Richard"], datetime!(2024-05-01 13:00 UTC));
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn read_csv_rejects_invalid_lines() {
+        let path = write_csv(
+            "read_csv_invalid",
+            "student,submitted\nalice,2024-05-01 12:00\nbob,soon\n",
+        );
+        let err = read_csv(&path).unwrap_err();
+        assert!(err.to_string().contains("invalid line 3"), "{err}");
+
+        fs::write(&path, "alice\n").unwrap();
+        assert!(read_csv(&path).is_err());
+        fs::remove_dir_all(Path::new(&path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn filter_by_deadline() {
+        let tmp_dir = helper::scratch_dir("filter_by_deadline");
+        for name in ["alice", "bob", "carol"] {
+            fs::create_dir(tmp_dir.join(name)).unwrap();
+        }
+        let timestamps = HashMap::from([
+            ("alice".to_owned(), datetime!(2024-05-01 12:00 UTC)),
+            ("bob".to_owned(), datetime!(2024-05-03 12:00 UTC)),
+        ]);
+
+        let filtered = filter(
+            &tmp_dir,
+            Some(datetime!(2024-05-02 00:00 UTC)),
+            LateFilter::ExcludeLate,
+            &timestamps,
+            &mut None,
+            &mut Streamed::new(),
+        )
+        .unwrap();
+
+        assert_eq!(filtered.removed.len(), 1);
+        assert!(filtered.removed[0].starts_with("\"bob\""));
+        assert!(tmp_dir.join("alice").exists());
+        assert!(!tmp_dir.join("bob").exists());
+        // Without files, its time is unknown, so it isn't late
+        assert!(tmp_dir.join("carol").exists());
+        fs::remove_dir_all(&tmp_dir).unwrap();
+    }
+}
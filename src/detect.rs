@@ -0,0 +1,418 @@
+use bzip2::read::MultiBzDecoder;
+use color_eyre::Result;
+use color_eyre::eyre::Context;
+use flate2::read::MultiGzDecoder;
+use lzma_rust2::XzReader;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use tracing::{instrument, trace};
+
+/// How many bytes we read to sniff the archive type,
+/// the tar magic is the furthest away (at offset 257)
+const SNIFF_LEN: u64 = 512;
+const TAR_MAGIC_OFFSET: usize = 257;
+
+/// Extensions of formats, which are zip files internally,
+/// but should never be treated as a submission archive
+const ZIP_CONTAINER_EXTENSIONS: &[&str] = &[
+    "jar", "war", "ear", "apk", "docx", "xlsx", "pptx", "odt", "ods", "odp", "epub",
+];
+
+/// A compression, which wraps a single stream (often a tar)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Display for Codec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Gzip => "gz",
+            Self::Bzip2 => "bz2",
+            Self::Xz => "xz",
+            Self::Zstd => "zst",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl Codec {
+    /// Wraps `reader` into a decoder for this codec
+    pub fn decoder<'a, R>(self, reader: R) -> Result<Box<dyn Read + Send + 'a>>
+    where
+        R: Read + Send + 'a,
+    {
+        let decoder: Box<dyn Read + Send> = match self {
+            Self::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            Self::Bzip2 => Box::new(MultiBzDecoder::new(reader)),
+            Self::Xz => Box::new(XzReader::new(reader, true)),
+            Self::Zstd => {
+                Box::new(zstd::Decoder::new(reader).context("unable to create zstd decoder")?)
+            }
+        };
+
+        Ok(decoder)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    Rar,
+    SevenZ,
+    Tar,
+    /// A tar, compressed with the codec (e.g. `.tar.gz`, `.tgz`)
+    CompressedTar(Codec),
+    /// A single compressed file (e.g. `Main.java.gz`)
+    Compressed(Codec),
+}
+
+impl Display for ArchiveKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Zip => write!(f, "zip"),
+            Self::Rar => write!(f, "rar"),
+            Self::SevenZ => write!(f, "7z"),
+            Self::Tar => write!(f, "tar"),
+            Self::CompressedTar(codec) => write!(f, "tar.{codec}"),
+            Self::Compressed(codec) => write!(f, "{codec}"),
+        }
+    }
+}
+
+impl ArchiveKind {
+    const fn codec(self) -> Option<Codec> {
+        match self {
+            Self::CompressedTar(codec) | Self::Compressed(codec) => Some(codec),
+            _ => None,
+        }
+    }
+
+    fn from_magic(header: &[u8]) -> Option<Self> {
+        let kind = if header.starts_with(b"PK\x03\x04")
+            || header.starts_with(b"PK\x05\x06")
+            || header.starts_with(b"PK\x07\x08")
+        {
+            Self::Zip
+        } else if header.starts_with(b"Rar!\x1a\x07\x00")
+            || header.starts_with(b"Rar!\x1a\x07\x01\x00")
+        {
+            Self::Rar
+        } else if header.starts_with(b"7z\xbc\xaf\x27\x1c") {
+            Self::SevenZ
+        } else if header.starts_with(b"\x1f\x8b") {
+            Self::Compressed(Codec::Gzip)
+        } else if header.starts_with(b"BZh") {
+            Self::Compressed(Codec::Bzip2)
+        } else if header.starts_with(b"\xfd7zXZ\x00") {
+            Self::Compressed(Codec::Xz)
+        } else if header.starts_with(b"\x28\xb5\x2f\xfd") {
+            Self::Compressed(Codec::Zstd)
+        } else if is_tar(header) {
+            Self::Tar
+        } else {
+            return None;
+        };
+
+        Some(kind)
+    }
+
+    /// Takes the whole lowercase file name, to be able to tell `.tar.gz` from `.gz`
+    fn from_file_name(file_name: &str) -> Option<Self> {
+        let (rest, extension) = file_name.rsplit_once('.')?;
+        let inner_tar = Path::new(rest)
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("tar"));
+
+        let compressed = |codec| {
+            if inner_tar {
+                Self::CompressedTar(codec)
+            } else {
+                Self::Compressed(codec)
+            }
+        };
+
+        let kind = match extension {
+            "zip" => Self::Zip,
+            "rar" => Self::Rar,
+            "7z" => Self::SevenZ,
+            "tar" => Self::Tar,
+            "tgz" => Self::CompressedTar(Codec::Gzip),
+            "tbz" | "tbz2" => Self::CompressedTar(Codec::Bzip2),
+            "txz" => Self::CompressedTar(Codec::Xz),
+            "tzst" => Self::CompressedTar(Codec::Zstd),
+            "gz" => compressed(Codec::Gzip),
+            "bz2" => compressed(Codec::Bzip2),
+            "xz" => compressed(Codec::Xz),
+            "zst" => compressed(Codec::Zstd),
+            _ => return None,
+        };
+
+        Some(kind)
+    }
+}
+
+fn is_tar(header: &[u8]) -> bool {
+    header
+        .get(TAR_MAGIC_OFFSET..)
+        .is_some_and(|magic| magic.starts_with(b"ustar"))
+}
+
+/// Result of inspecting a file, what the content says and what the extension says
+#[derive(Clone, Copy, Debug)]
+pub struct Detection {
+    sniffed: Option<ArchiveKind>,
+    by_extension: Option<ArchiveKind>,
+}
+
+impl Detection {
+    const NONE: Self = Self {
+        sniffed: None,
+        by_extension: None,
+    };
+
+    /// The kind we should treat the file as,
+    /// the content wins, the extension is only used if sniffing was inconclusive
+    pub const fn kind(self) -> Option<ArchiveKind> {
+        match self.sniffed {
+            Some(kind) => Some(kind),
+            None => self.by_extension,
+        }
+    }
+
+    /// Returns the kind suggested by the extension,
+    /// if it disagrees with the sniffed content
+    ///
+    /// A `.gz` containing a tar is not a mismatch, only a different codec or container is
+    pub fn mismatch(self) -> Option<ArchiveKind> {
+        match (self.sniffed, self.by_extension) {
+            (Some(sniffed), Some(ext))
+                if sniffed != ext
+                    && (sniffed.codec().is_none() || sniffed.codec() != ext.codec()) =>
+            {
+                Some(ext)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Detects the archive type of `path` by its magic bytes,
+/// falls back to the file extension if the content is inconclusive
+///
+/// For compressed files, the start of the decompressed stream is checked for a tar header,
+/// an extension like `.tar.gz` or `.tgz` also marks it as a tar
+#[instrument]
+pub fn detect<P>(path: P) -> Result<Detection>
+where
+    P: AsRef<Path> + Debug,
+{
+    let path = path.as_ref();
+    if is_zip_container(path) {
+        return Ok(Detection::NONE);
+    }
+
+    let mut header = vec![];
+    File::open(&path)
+        .with_context(|| format!("unable to open {path:?} to sniff archive type"))?
+        .take(SNIFF_LEN)
+        .read_to_end(&mut header)
+        .with_context(|| format!("unable to read header of {path:?}"))?;
+
+    Ok(detect_header(path, &header, |codec| {
+        File::open(path).is_ok_and(|file| is_compressed_tar(BufReader::new(file), codec))
+    }))
+}
+
+/// Like [`detect`], for an archive, which is not on disk
+///
+/// `path` is only used for the extension, `data` may be just the start of the archive
+pub fn detect_bytes(path: &Path, data: &[u8]) -> Detection {
+    if is_zip_container(path) {
+        return Detection::NONE;
+    }
+
+    detect_header(path, data, |codec| is_compressed_tar(data, codec))
+}
+
+fn is_zip_container(path: &Path) -> bool {
+    let is_container = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|ext| ZIP_CONTAINER_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+    if is_container {
+        trace!("{path:?} is a zip container format, not an archive");
+    }
+
+    is_container
+}
+
+fn detect_header<F>(path: &Path, header: &[u8], sniff_compressed_tar: F) -> Detection
+where
+    F: FnOnce(Codec) -> bool,
+{
+    let by_extension = path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .map(str::to_ascii_lowercase)
+        .as_deref()
+        .and_then(ArchiveKind::from_file_name);
+
+    let mut sniffed = ArchiveKind::from_magic(header);
+
+    if let Some(ArchiveKind::Compressed(codec)) = sniffed {
+        let tar_by_extension = matches!(by_extension, Some(ArchiveKind::CompressedTar(_)));
+        if tar_by_extension || sniff_compressed_tar(codec) {
+            sniffed = Some(ArchiveKind::CompressedTar(codec));
+        }
+    }
+
+    let detection = Detection {
+        sniffed,
+        by_extension,
+    };
+    trace!(?detection);
+
+    detection
+}
+
+/// Checks, if the decompressed start of `reader` looks like a tar header
+///
+/// Errors are treated as "not a tar", the extraction will report them properly
+fn is_compressed_tar<R>(reader: R, codec: Codec) -> bool
+where
+    R: Read + Send,
+{
+    let Ok(decoder) = codec.decoder(reader) else {
+        return false;
+    };
+
+    let mut header = vec![];
+    if decoder.take(SNIFF_LEN).read_to_end(&mut header).is_err() {
+        trace!("unable to decompress start of {codec} stream");
+        return false;
+    }
+
+    is_tar(&header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper;
+    use std::fs;
+    use std::io::Write;
+
+    /// A tar header with the magic at its offset
+    fn tar_header() -> Vec<u8> {
+        let mut header = vec![0; 512];
+        header[TAR_MAGIC_OFFSET..TAR_MAGIC_OFFSET + 6].copy_from_slice(b"ustar\0");
+        header
+    }
+
+    #[test]
+    fn sniffs_signatures() {
+        let signatures: [(&[u8], ArchiveKind); 10] = [
+            (b"PK\x03\x04rest", ArchiveKind::Zip),
+            (b"PK\x05\x06rest", ArchiveKind::Zip),
+            (b"Rar!\x1a\x07\x00rest", ArchiveKind::Rar),
+            (b"Rar!\x1a\x07\x01\x00rest", ArchiveKind::Rar),
+            (b"7z\xbc\xaf\x27\x1crest", ArchiveKind::SevenZ),
+            (b"\x1f\x8b\x08rest", ArchiveKind::Compressed(Codec::Gzip)),
+            (b"BZh91AY", ArchiveKind::Compressed(Codec::Bzip2)),
+            (b"\xfd7zXZ\x00rest", ArchiveKind::Compressed(Codec::Xz)),
+            (
+                b"\x28\xb5\x2f\xfdrest",
+                ArchiveKind::Compressed(Codec::Zstd),
+            ),
+            (&tar_header(), ArchiveKind::Tar),
+        ];
+        for (header, kind) in signatures {
+            assert_eq!(ArchiveKind::from_magic(header), Some(kind), "{kind}");
+        }
+    }
+
+    #[test]
+    fn sniffs_nothing_else() {
+        assert_eq!(ArchiveKind::from_magic(b""), None);
+        assert_eq!(ArchiveKind::from_magic(b"class Main {}"), None);
+        assert_eq!(ArchiveKind::from_magic(b"Rar!"), None);
+        // The tar magic only counts at its offset
+        assert_eq!(ArchiveKind::from_magic(b"ustar\0"), None);
+    }
+
+    #[test]
+    fn content_wins_over_the_extension() {
+        let dir = helper::scratch_dir("detect_content_wins");
+        let path = dir.join("renamed.zip");
+        fs::write(&path, b"Rar!\x1a\x07\x01\x00rest").unwrap();
+
+        let detection = detect(&path).unwrap();
+
+        assert_eq!(detection.kind(), Some(ArchiveKind::Rar));
+        assert_eq!(detection.mismatch(), Some(ArchiveKind::Zip));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn falls_back_to_the_extension() {
+        let dir = helper::scratch_dir("detect_extension_fallback");
+        let path = dir.join("Submission.7Z");
+        fs::write(&path, "inconclusive").unwrap();
+
+        let detection = detect(&path).unwrap();
+
+        assert_eq!(detection.kind(), Some(ArchiveKind::SevenZ));
+        assert_eq!(detection.mismatch(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tells_compressed_tars_from_single_files() {
+        let dir = helper::scratch_dir("detect_compressed_tars");
+        let mut tar = tar::Builder::new(vec![]);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(0);
+        tar.append_data(&mut header, "Main.java", [].as_slice())
+            .unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&tar.into_inner().unwrap()).unwrap();
+        let tar_gz = encoder.finish().unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(b"class Main {}").unwrap();
+        let gz = encoder.finish().unwrap();
+
+        for (name, data, kind) in [
+            ("src.gz", &tar_gz, ArchiveKind::CompressedTar(Codec::Gzip)),
+            (
+                "src.tar.gz",
+                &tar_gz,
+                ArchiveKind::CompressedTar(Codec::Gzip),
+            ),
+            ("Main.java.gz", &gz, ArchiveKind::Compressed(Codec::Gzip)),
+        ] {
+            let path = dir.join(name);
+            fs::write(&path, data).unwrap();
+            let detection = detect(&path).unwrap();
+            assert_eq!(detection.kind(), Some(kind), "{name}");
+            assert_eq!(detection.mismatch(), None, "{name}");
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn zip_containers_are_no_archives() {
+        let dir = helper::scratch_dir("detect_zip_containers");
+        for name in ["Tools.jar", "Report.DOCX"] {
+            let path = dir.join(name);
+            fs::write(&path, b"PK\x03\x04rest").unwrap();
+            assert_eq!(detect(&path).unwrap().kind(), None, "{name}");
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,393 @@
+use color_eyre::eyre::{Context, ContextCompat, bail};
+use color_eyre::{Report, Result};
+use std::ffi::OsStr;
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use time::OffsetDateTime;
+use tracing::{debug, info, instrument, trace};
+use walkdir::WalkDir;
+
+/// The first line of a bundle, `v2` and `v3` are written by current versions of git
+const BUNDLE_SIGNATURES: [&[u8]; 2] = [b"# v2 git bundle\n", b"# v3 git bundle\n"];
+
+/// Which commit of each repository is checked out
+#[derive(Clone, Debug, Default)]
+pub struct Checkout {
+    /// The last commit before it is checked out,
+    /// in any format git understands (e.g. `2024-05-01 23:59`)
+    pub deadline: Option<String>,
+    /// A tag or branch, `HEAD` if unset
+    pub reference: Option<String>,
+}
+
+/// Whether `dir` contains only git repositories (with a work tree or bare) and bundles
+pub fn contains_only_repos(dir: &Path) -> Result<bool> {
+    let mut found = false;
+    for entry in fs::read_dir(dir).with_context(|| format!("unable to read {dir:?}"))? {
+        let entry = entry.with_context(|| format!("unable to read an entry in {dir:?}"))?;
+        if !is_repo(&entry.path())? {
+            return Ok(false);
+        }
+        found = true;
+    }
+
+    Ok(found)
+}
+
+fn is_repo(path: &Path) -> Result<bool> {
+    if path.is_dir() {
+        let bare = path.join("HEAD").is_file()
+            && path.join("objects").is_dir()
+            && path.join("refs").is_dir();
+        return Ok(bare || path.join(".git").exists());
+    }
+    if path.extension() == Some(OsStr::new("bundle")) {
+        return Ok(true);
+    }
+
+    let mut start = [0; 16];
+    let mut file = File::open(path).with_context(|| format!("unable to open {path:?}"))?;
+    let len = file
+        .read(&mut start)
+        .with_context(|| format!("unable to read {path:?}"))?;
+    Ok(BUNDLE_SIGNATURES.contains(&&start[..len]))
+}
+
+/// Checks out every repository and bundle in `source` into its own dir in `dest`,
+/// named like the repository without `.git` or `.bundle` (e.g. `alice.bundle` -> `alice`)
+///
+/// Only the work tree is written, without `.git`
+///
+/// Everything is local, git is not allowed to use any other protocol than `file`,
+/// so neither submodules nor remotes are ever fetched
+///
+/// Symlinks are checked out as plain files containing the target,
+/// so no link in a submission points out of `dest`
+///
+/// The files are dated to the commit checked out, like the entries of an archive
+///
+/// A repository without the `checkout` reference or without a commit before the deadline
+/// fails like a broken one, as there is nothing to submit, the failures are returned
+#[instrument]
+pub fn check_out_all(source: &Path, dest: &Path, checkout: &Checkout) -> Result<Vec<Report>> {
+    let scratch_dir = dest.join(".git-scratch");
+    fs::create_dir_all(&scratch_dir)
+        .with_context(|| format!("unable to create {scratch_dir:?}"))?;
+
+    let mut repos = fs::read_dir(source)
+        .with_context(|| format!("unable to read {source:?}"))?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("unable to read an entry in {source:?}"))?;
+    repos.sort();
+
+    let mut checked_out_cnt = 0;
+    let mut failed = vec![];
+    for repo in repos {
+        let name = repo
+            .file_stem()
+            .filter(|_| matches!(repo.extension(), Some(ext) if ext == "git" || ext == "bundle"))
+            .or_else(|| repo.file_name())
+            .with_context(|| format!("unable to get the name of {repo:?}"))?;
+        let scratch = scratch_dir.join(name);
+        let submission = dest.join(name);
+        match check_out(&repo, &scratch, &submission, checkout) {
+            Ok(()) => checked_out_cnt += 1,
+            Err(e) => {
+                debug!(?e, "unable to check out {repo:?}");
+                let _ = fs::remove_dir_all(&submission);
+                // Wrapping instead of formatting keeps the cause, like for extraction errors
+                let msg = format!("unable to check out {repo:?}: {e}");
+                failed.push(e.wrap_err(msg));
+            }
+        }
+        let _ = fs::remove_dir_all(&scratch);
+    }
+    fs::remove_dir_all(&scratch_dir)
+        .with_context(|| format!("unable to remove {scratch_dir:?}"))?;
+
+    info!("checked out {checked_out_cnt} repositories of {source:?}");
+
+    Ok(failed)
+}
+
+/// Clones `repo` bare to `scratch` and writes the work tree of the commit to check out to `dest`
+///
+/// Fails, if there is no such commit
+fn check_out(repo: &Path, scratch: &Path, dest: &Path, checkout: &Checkout) -> Result<()> {
+    let _ = fs::remove_dir_all(scratch);
+    git(
+        None,
+        [
+            OsStr::new("clone"),
+            OsStr::new("--quiet"),
+            OsStr::new("--bare"),
+            repo.as_os_str(),
+            scratch.as_os_str(),
+        ],
+    )?;
+
+    let reference = checkout.reference.as_deref().unwrap_or("HEAD");
+    let Some(commit) = git(
+        Some(scratch),
+        [
+            "rev-parse",
+            "--verify",
+            "--quiet",
+            &format!("{reference}^{{commit}}"),
+        ],
+    )
+    .ok()
+    .filter(|commit| !commit.is_empty()) else {
+        bail!("{repo:?} has no {reference:?}");
+    };
+
+    let commit = match &checkout.deadline {
+        None => commit,
+        Some(deadline) => {
+            let commit = git(
+                Some(scratch),
+                ["rev-list", "-1", &format!("--before={deadline}"), &commit],
+            )?;
+            if commit.is_empty() {
+                bail!("{repo:?} has no commit before {deadline:?}");
+            }
+            commit
+        }
+    };
+
+    debug!("checking out {commit} of {repo:?} to {dest:?}");
+    fs::create_dir_all(dest).with_context(|| format!("unable to create {dest:?}"))?;
+    let mut work_tree = OsStr::new("--work-tree=").to_owned();
+    work_tree.push(dest);
+    git(
+        Some(scratch),
+        [
+            work_tree.as_os_str(),
+            OsStr::new("checkout"),
+            OsStr::new("--quiet"),
+            OsStr::new(&commit),
+            OsStr::new("--"),
+            OsStr::new("."),
+        ],
+    )?;
+    trace!("checked out {commit}");
+
+    // The checkout time says nothing about when it was submitted, the commit time does
+    let committed = git(Some(scratch), ["show", "-s", "--format=%ct", &commit])?;
+    let committed = committed
+        .parse()
+        .ok()
+        .and_then(|secs| OffsetDateTime::from_unix_timestamp(secs).ok())
+        .with_context(|| format!("invalid commit time {committed:?} of {commit}"))?;
+    for entry in WalkDir::new(dest) {
+        let entry = entry.with_context(|| format!("invalid entry in {dest:?}"))?;
+        if entry.file_type().is_file() {
+            File::options()
+                .write(true)
+                .open(entry.path())
+                .and_then(|f| f.set_modified(committed.into()))
+                .with_context(|| {
+                    format!("unable to set modification time of {:?}", entry.path())
+                })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs git (in the bare repository `git_dir`) and returns its trimmed stdout
+fn git<I, S>(git_dir: Option<&Path>, args: I) -> Result<String>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr> + Debug,
+{
+    let mut cmd = Command::new("git");
+    cmd.args([
+        "-c",
+        "protocol.allow=never",
+        "-c",
+        "protocol.file.allow=always",
+        "-c",
+        "core.symlinks=false",
+    ])
+    .env("GIT_TERMINAL_PROMPT", "0")
+    .stdin(Stdio::null());
+    if let Some(git_dir) = git_dir {
+        let mut arg = OsStr::new("--git-dir=").to_owned();
+        arg.push(git_dir);
+        cmd.arg(arg);
+    }
+    cmd.args(args);
+    trace!(?cmd, "running git");
+
+    let output = cmd
+        .output()
+        .with_context(|| format!("unable to run {cmd:?}, git is probably not installed"))?;
+    if !output.status.success() {
+        bail!(
+            "{cmd:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper;
+    use std::path::PathBuf;
+
+    /// Runs git in `dir` with a fixed identity and commit dates
+    fn run_git(dir: &Path, date: &str, args: &[&str]) {
+        let status = Command::new("git")
+            .args(["-c", "user.name=Jane", "-c", "user.email=jane@example.com"])
+            .arg("-C")
+            .arg(dir)
+            .args(args)
+            .env("GIT_AUTHOR_DATE", date)
+            .env("GIT_COMMITTER_DATE", date)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {args:?}");
+    }
+
+    /// A dir with the repository `alice` and the bundle `bob.bundle`, both with a commit
+    /// in January (tagged `v1`) and one in March
+    fn repos(test: &str) -> PathBuf {
+        let dir = helper::scratch_dir(test);
+        let source = dir.join("repos");
+        let alice = source.join("alice");
+        fs::create_dir_all(&alice).unwrap();
+        run_git(&alice, "2024-01-10T12:00:00Z", &["init", "--quiet"]);
+        for (date, version) in [
+            ("2024-01-10T12:00:00Z", "january"),
+            ("2024-03-10T12:00:00Z", "march"),
+        ] {
+            fs::write(alice.join("Main.java"), version).unwrap();
+            run_git(&alice, date, &["add", "."]);
+            run_git(&alice, date, &["commit", "--quiet", "-m", version]);
+            if version == "january" {
+                run_git(&alice, date, &["tag", "v1"]);
+            }
+        }
+        run_git(
+            &alice,
+            "2024-03-10T12:00:00Z",
+            &["bundle", "create", "--quiet", "../bob.bundle", "--all"],
+        );
+
+        dir
+    }
+
+    fn checked_out(dest: &Path, name: &str) -> String {
+        fs::read_to_string(dest.join(name).join("Main.java")).unwrap()
+    }
+
+    #[test]
+    fn checks_out_the_last_commit_before_the_deadline() {
+        let dir = repos("git_deadline");
+        let source = dir.join("repos");
+        assert!(contains_only_repos(&source).unwrap());
+
+        let latest = dir.join("latest");
+        assert!(
+            check_out_all(&source, &latest, &Checkout::default())
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(checked_out(&latest, "alice"), "march");
+        assert_eq!(checked_out(&latest, "bob"), "march");
+        assert!(!latest.join("alice/.git").exists());
+
+        let deadline = dir.join("deadline");
+        let checkout = Checkout {
+            deadline: Some(String::from("2024-02-01 00:00 +0000")),
+            reference: None,
+        };
+        assert!(
+            check_out_all(&source, &deadline, &checkout)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(checked_out(&deadline, "alice"), "january");
+        assert_eq!(checked_out(&deadline, "bob"), "january");
+        let modified = fs::metadata(deadline.join("alice/Main.java"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        assert_eq!(
+            modified,
+            std::time::SystemTime::from(time::macros::datetime!(2024-01-10 12:00:00 UTC))
+        );
+
+        let tagged = dir.join("tagged");
+        let checkout = Checkout {
+            deadline: None,
+            reference: Some(String::from("v1")),
+        };
+        assert!(
+            check_out_all(&source, &tagged, &checkout)
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(checked_out(&tagged, "alice"), "january");
+
+        let too_early = dir.join("too_early");
+        let checkout = Checkout {
+            deadline: Some(String::from("2023-12-01 00:00 +0000")),
+            reference: None,
+        };
+        let failed = check_out_all(&source, &too_early, &checkout).unwrap();
+        assert_eq!(failed.len(), 2);
+        assert!(
+            failed[0].to_string().contains("has no commit before"),
+            "{:?}",
+            failed[0]
+        );
+        assert!(!too_early.join("alice").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn other_files_are_no_repos() {
+        let dir = repos("git_no_repos");
+        let source = dir.join("repos");
+        fs::write(source.join("notes.txt"), "no repo").unwrap();
+
+        assert!(!contains_only_repos(&source).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_checked_out_as_plain_files() {
+        let dir = helper::scratch_dir("git_symlinks");
+        let source = dir.join("repos");
+        let alice = source.join("alice");
+        fs::create_dir_all(&alice).unwrap();
+        let date = "2024-01-10T12:00:00Z";
+        run_git(&alice, date, &["init", "--quiet"]);
+        std::os::unix::fs::symlink("/etc/passwd", alice.join("Main.java")).unwrap();
+        run_git(&alice, date, &["add", "."]);
+        run_git(&alice, date, &["commit", "--quiet", "-m", "link"]);
+
+        let dest = dir.join("dest");
+        assert!(
+            check_out_all(&source, &dest, &Checkout::default())
+                .unwrap()
+                .is_empty()
+        );
+
+        let checked_out = dest.join("alice/Main.java");
+        assert!(!checked_out.is_symlink());
+        assert_eq!(fs::read_to_string(checked_out).unwrap(), "/etc/passwd");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,14 +1,31 @@
-use color_eyre::Result;
+use crate::archive_handler::{ExtractCtx, NoMatchingPassword, Registry, Salvage, SourceEntry};
+use crate::detect::{self, ArchiveKind, Detection};
+use crate::git::{self, Checkout};
+use crate::progress::Progress;
+use crate::safe_extract::{Budget, LimitExceeded, LinkKind, SafeDest};
 use color_eyre::eyre::{Context, ContextCompat, bail};
+use color_eyre::{Report, Result};
+use deunicode::deunicode;
+use encoding_rs::WINDOWS_1252;
+use std::ffi::OsStr;
 use std::fmt::Debug;
+use std::fs;
 use std::fs::OpenOptions;
-use std::io::BufReader;
+use std::io::{self, BufReader, Read, Seek};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
-use std::{fs, io};
+use std::sync::Arc;
+use std::time::SystemTime;
+use time::OffsetDateTime;
 use tracing::{Level, debug, info_span, instrument, span, trace, warn};
+use unicode_normalization::UnicodeNormalization;
 use walkdir::WalkDir;
 use zip::ZipArchive;
+use zip::read::{ZipFile, read_zipfile_from_stream};
+
+/// How much of an entry of the source zip is read to decide, if it is an archive,
+/// more than [`detect`] reads, as the compressed start of a tar has to decompress to a full header
+const STREAM_SNIFF_LEN: u64 = 4096;
 
 #[instrument]
 pub fn check_java_executable() -> Result<()> {
@@ -35,8 +52,16 @@ pub fn check_java_executable() -> Result<()> {
     }
 }
 
-#[instrument]
-pub fn unzip_to<P, Q>(zip: P, dest: Q) -> Result<()>
+/// Encrypted entries are tried with every password of `passwords`,
+/// the password of the previous entry first, as usually all share the same one
+#[instrument(skip(budget, passwords, salvage))]
+pub fn unzip_to<P, Q>(
+    zip: P,
+    dest: Q,
+    budget: &Budget,
+    passwords: &[String],
+    salvage: &Salvage,
+) -> Result<()>
 where
     P: AsRef<Path> + Debug,
     Q: AsRef<Path> + Debug,
@@ -49,60 +74,441 @@ where
 
     trace!("opened zip");
 
-    let mut archive = ZipArchive::new(BufReader::new(src_file))
-        .with_context(|| format!("unable to parse {zip:?} to a zip archive"))?;
+    unzip_reader(
+        BufReader::new(src_file),
+        zip,
+        dest,
+        budget,
+        passwords,
+        salvage,
+    )
+}
+
+/// Like [`unzip_to`], for a zip, which is not on disk,
+/// `zip` is only used in messages
+///
+/// With `salvage` enabled, unreadable entries are recorded as lost and skipped,
+/// if the central directory is broken (e.g. the zip is truncated), the local file headers are scanned instead
+pub fn unzip_reader<R, P, Q>(
+    mut reader: R,
+    zip: P,
+    dest: Q,
+    budget: &Budget,
+    passwords: &[String],
+    salvage: &Salvage,
+) -> Result<()>
+where
+    R: Read + Seek,
+    P: AsRef<Path> + Debug,
+    Q: AsRef<Path> + Debug,
+{
+    let zip = zip.as_ref();
+    let dest = SafeDest::new(&dest, budget)?;
+    let mut archive = match ZipArchive::new(&mut reader) {
+        Ok(archive) => archive,
+        Err(e) if salvage.enabled() => {
+            debug!(
+                ?e,
+                "central directory of {zip:?} is broken, scanning local headers"
+            );
+            reader
+                .rewind()
+                .with_context(|| format!("unable to rewind {zip:?}"))?;
+            return salvage_zip(reader, zip, &dest, salvage);
+        }
+        Err(e) => {
+            return Err(Report::new(e))
+                .with_context(|| format!("unable to parse {zip:?} to a zip archive"));
+        }
+    };
 
     trace!("created zip archive");
 
     let archive_len = archive.len();
     trace!("archive len: {archive_len}");
 
+    let mut last_password = None;
     for i in 0..archive_len {
-        let mut file = archive.by_index(i).with_context(|| {
-            format!(
-                "unable to get file by index {i} \
-                (should be impossible, as we iterate over len, len = {archive_len})"
-            )
-        })?;
+        let Err(e) = unzip_index(&mut archive, i, &dest, passwords, &mut last_password) else {
+            continue;
+        };
+        if !salvage.enabled() || !is_salvageable(&e) {
+            return Err(e);
+        }
+
+        let name = archive.by_index_raw(i).map(|file| zip_entry_name(&file));
+        debug!(?e, "unable to unzip {name:?}");
+        if let Ok(name) = name {
+            remove_partial(&dest, &name);
+            salvage.record_lost(zip, &format!("{name:?}"));
+        } else {
+            salvage.record_lost(zip, &format!("entry {i}"));
+        }
+    }
+
+    Ok(())
+}
+
+fn unzip_index<R>(
+    archive: &mut ZipArchive<R>,
+    i: usize,
+    dest: &SafeDest,
+    passwords: &[String],
+    last_password: &mut Option<usize>,
+) -> Result<()>
+where
+    R: Read + Seek,
+{
+    let archive_len = archive.len();
+    let encrypted = archive
+        .by_index_raw(i)
+        .with_context(|| format!("unable to get raw file by index {i}"))?
+        .encrypted();
+
+    let file = if encrypted {
+        let password = find_zip_password(archive, i, dest.budget(), passwords, *last_password)?;
+        *last_password = Some(password);
+        archive.by_index_decrypt(i, passwords[password].as_bytes())
+    } else {
+        archive.by_index(i)
+    };
+    let mut file = file.with_context(|| {
+        format!(
+            "unable to get file by index {i} \
+            (should be impossible, as we iterate over len, len = {archive_len})"
+        )
+    })?;
+    let span = span!(Level::DEBUG, "processing_file", file_name = %file.name());
+    let _guard = span.enter();
+
+    unzip_entry(&mut file, dest)
+}
+
+/// Extracts every entry, which is still readable, by scanning the local file headers
+/// from the start of `reader`, instead of trusting the central directory
+///
+/// Entries after the first unreadable header can't be found, they are recorded as lost
+fn salvage_zip<R>(mut reader: R, zip: &Path, dest: &SafeDest, salvage: &Salvage) -> Result<()>
+where
+    R: Read,
+{
+    let mut recovered_cnt = 0;
+    let mut last_name = None;
+
+    loop {
+        let mut file = match read_zipfile_from_stream(&mut reader) {
+            Ok(Some(file)) => file,
+            Ok(None) => break,
+            Err(e) => {
+                let Some(last_name) = last_name else {
+                    return Err(Report::new(e))
+                        .with_context(|| format!("unable to find a local file header in {zip:?}"));
+                };
+                debug!(?e, "unable to read local file header");
+                salvage.record_lost(zip, &format!("everything after {last_name:?}"));
+                break;
+            }
+        };
+
+        let name = zip_entry_name(&file);
+        let res = unzip_entry(&mut file, dest);
+        drop(file);
+        match res {
+            Ok(()) => recovered_cnt += 1,
+            Err(e) if is_salvageable(&e) => {
+                debug!(?e, "unable to unzip {name:?}");
+                remove_partial(dest, &name);
+                salvage.record_lost(zip, &format!("{name:?}"));
+            }
+            Err(e) => return Err(e),
+        }
+        last_name = Some(name);
+    }
+
+    debug!("recovered {recovered_cnt} entries of {zip:?}");
+
+    Ok(())
+}
+
+/// A bomb or an encrypted entry without a matching password fails the whole submission
+fn is_salvageable(e: &Report) -> bool {
+    e.downcast_ref::<LimitExceeded>().is_none() && e.downcast_ref::<NoMatchingPassword>().is_none()
+}
+
+/// Removes what was written of an entry, which failed halfway
+fn remove_partial(dest: &SafeDest, name: &Path) {
+    if let Ok(path) = dest.resolve(name)
+        && path.is_file()
+    {
+        let _ = fs::remove_file(path);
+    }
+}
+
+/// A source extracted by [`extract_source`]
+#[derive(Debug, Default)]
+pub struct ExtractedSource {
+    /// The archives kept in a streamed zip source
+    pub streamed: Vec<(PathBuf, Detection, SourceEntry)>,
+    /// The repositories, which couldn't be checked out
+    pub failed: Vec<Report>,
+}
+
+/// Extracts the source of the submissions to `dest`
+///
+/// The source is either a directory, which is hard linked (or copied) to `dest`,
+/// a directory of only git repositories and bundles, of which the `checkout` is checked out,
+/// or an archive of any kind the `registry` has a handler for
+///
+/// With `stream`, the archives inside of a zip source are kept in the zip and returned,
+/// see [`unzip_streaming`], other sources are always extracted completely
+///
+/// A repository, which can't be checked out, doesn't fail the whole source,
+/// it is returned as failed, so it is reported like a broken submission
+#[instrument(skip(stream, checkout, registry))]
+pub fn extract_source<P, Q>(
+    source: P,
+    dest: Q,
+    stream: bool,
+    checkout: &Checkout,
+    registry: &Registry,
+) -> Result<ExtractedSource>
+where
+    P: AsRef<Path> + Debug,
+    Q: AsRef<Path> + Debug,
+{
+    let source = source.as_ref();
+    let dest = dest.as_ref();
+
+    if source.is_dir() {
+        if stream {
+            warn!("only a zip source can be streamed, using the dir {source:?} instead");
+        }
+        if git::contains_only_repos(source)? {
+            debug!("checking out the repositories of {source:?} to {dest:?}");
+            let failed = git::check_out_all(source, dest, checkout)
+                .with_context(|| format!("unable to check out the repositories of {source:?}"))?;
+            return Ok(ExtractedSource {
+                streamed: vec![],
+                failed,
+            });
+        }
+        debug!("linking {source:?} to {dest:?}");
+        link_tree(source, dest)
+            .with_context(|| format!("unable to link {source:?} to {dest:?}"))?;
+        return Ok(ExtractedSource::default());
+    }
+
+    let detection = detect::detect(source)
+        .with_context(|| format!("unable to detect archive type of {source:?}"))?;
+    let kind = detection
+        .kind()
+        .with_context(|| format!("{source:?} is neither a dir nor a supported archive"))?;
+    if let Some(ext_kind) = detection.mismatch() {
+        warn!(
+            "extension of {source:?} suggests {ext_kind}, \
+            but the content is {kind}, extracting as {kind}"
+        );
+    }
+
+    match kind {
+        ArchiveKind::Compressed(codec) => {
+            bail!("{source:?} is a single {codec} compressed file, not an archive of submissions")
+        }
+        ArchiveKind::Zip if stream => {
+            return Ok(ExtractedSource {
+                streamed: unzip_streaming(source, dest)?,
+                failed: vec![],
+            });
+        }
+        ArchiveKind::Zip => {
+            unzip_to(source, dest, &Budget::unlimited(), &[], &Salvage::default())?;
+            return Ok(ExtractedSource::default());
+        }
+        _ if stream => warn!("only a zip source can be streamed, extracting the {kind} completely"),
+        _ => {}
+    }
+
+    let handler = registry
+        .find(kind)
+        .with_context(|| format!("no handler for {kind} archives"))?;
+    let parent = dest
+        .parent()
+        .with_context(|| format!("unable to get parent of {dest:?}"))?;
+    let file_name = source
+        .file_name()
+        .with_context(|| format!("unable to get file name of {source:?}"))?;
+    fs::create_dir_all(dest).with_context(|| format!("unable to create {dest:?}"))?;
+
+    // The handlers remove the archive after extracting it, so they get a link to it
+    let archive = dest.join(file_name);
+    if fs::hard_link(source, &archive).is_err() {
+        fs::copy(source, &archive)
+            .with_context(|| format!("unable to copy {source:?} to {archive:?}"))?;
+    }
+
+    let budget = Budget::unlimited();
+    let salvage = Salvage::default();
+    let ctx = ExtractCtx {
+        registry,
+        budget: &budget,
+        passwords: &[],
+        salvage: &salvage,
+    };
+    handler.extract(parent, dest, &archive, &ctx)?;
+
+    Ok(ExtractedSource::default())
+}
+
+/// Unzips the source zip to `dest`, but keeps archives inside of submissions in the zip
+///
+/// Returns every kept archive with the path it would have been unzipped to,
+/// its parent directory is created, so every submission still has a directory
+#[instrument]
+pub fn unzip_streaming<P, Q>(zip: P, dest: Q) -> Result<Vec<(PathBuf, Detection, SourceEntry)>>
+where
+    P: AsRef<Path> + Debug,
+    Q: AsRef<Path> + Debug,
+{
+    let budget = Budget::unlimited();
+    let dest = SafeDest::new(&dest, &budget)?;
+    let source_zip: Arc<Path> = Arc::from(zip.as_ref());
+    let src_file = OpenOptions::new()
+        .read(true)
+        .open(&zip)
+        .with_context(|| format!("unable to open src_file: {zip:?}"))?;
+    let mut archive = ZipArchive::new(BufReader::new(src_file))
+        .with_context(|| format!("unable to parse {zip:?} to a zip archive"))?;
+
+    let mut streamed = vec![];
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .with_context(|| format!("unable to get file by index {i}"))?;
         let span = span!(Level::DEBUG, "processing_file", file_name = %file.name());
         let _guard = span.enter();
 
-        let out_path = dest.as_ref().join(file.enclosed_name().unwrap());
+        let name = zip_entry_name(&file);
+        let in_submission = name.components().count() > 1;
+        if !in_submission || file.is_dir() || file.is_symlink() {
+            unzip_entry(&mut file, &dest)?;
+            continue;
+        }
 
-        trace!("set out path: {out_path:?}");
+        let mut header = vec![];
+        file.by_ref()
+            .take(STREAM_SNIFF_LEN)
+            .read_to_end(&mut header)
+            .with_context(|| format!("unable to read header of {name:?}"))?;
+        let detection = detect::detect_bytes(&name, &header);
 
-        if file.is_dir() {
-            fs::create_dir_all(&out_path)
-                .with_context(|| format!("unable to create out dir: {out_path:?}"))?;
-            trace!("created out_path");
+        if detection.kind().is_some() {
+            trace!("keeping archive {name:?} in the source zip");
+            if let Some(parent) = name.parent() {
+                dest.create_dir(parent)?;
+            }
+            streamed.push((
+                dest.resolve(&name)?,
+                detection,
+                SourceEntry {
+                    source_zip: Arc::clone(&source_zip),
+                    index: i,
+                    size: file.size(),
+                    crc32: file.crc32(),
+                    modified: zip_modified(&file),
+                },
+            ));
         } else {
-            if let Some(parent) = out_path.parent()
-                && !parent.exists()
-            {
-                fs::create_dir_all(parent)
-                    .with_context(|| format!("unable to create parent dir: {parent:?}"))?;
-                trace!("created parent");
+            dest.write_file(&name, &mut header.as_slice().chain(&mut file))?;
+            if let Some(modified) = zip_modified(&file) {
+                dest.set_modified(&name, modified)?;
             }
+        }
+    }
+
+    debug!("kept {} archives in the source zip", streamed.len());
 
-            let mut out_file = OpenOptions::new()
-                .write(true)
-                .create(true)
-                .truncate(true)
-                .open(&out_path)
-                .with_context(|| format!("unable to open/create out_file: {out_path:?}"))?;
+    Ok(streamed)
+}
 
-            trace!("created/opened out_file {out_file:?}");
+fn unzip_entry<R>(file: &mut ZipFile<'_, R>, dest: &SafeDest) -> Result<()>
+where
+    R: Read,
+{
+    let name = zip_entry_name(file);
 
-            io::copy(&mut file, &mut out_file).with_context(|| {
-                format!("unable to io copy {src} to {out_file:?}", src = file.name())
-            })?;
-            trace!("io copied {src} to {out_file:?}", src = file.name());
+    if file.is_dir() {
+        dest.create_dir(&name)?;
+    } else if file.is_symlink() {
+        let mut target = String::new();
+        file.read_to_string(&mut target)
+            .with_context(|| format!("unable to read link target of {name:?}"))?;
+        dest.link(&name, &target, LinkKind::Symbolic)?;
+    } else {
+        dest.write_file(&name, file)?;
+        // Keeps the upload time, e.g. for the `newest` multi archive policy
+        if let Some(modified) = zip_modified(file) {
+            dest.set_modified(&name, modified)?;
         }
     }
 
     Ok(())
 }
 
+/// Names without the UTF-8 flag are CP437 by the spec (and decoded as such by `zip`),
+/// but a lot of tools write UTF-8 without setting the flag, so valid UTF-8 wins
+fn zip_entry_name<R>(file: &ZipFile<'_, R>) -> PathBuf
+where
+    R: Read,
+{
+    str::from_utf8(file.name_raw()).map_or_else(|_| PathBuf::from(file.name()), PathBuf::from)
+}
+
+fn zip_modified<R>(file: &ZipFile<'_, R>) -> Option<SystemTime>
+where
+    R: Read,
+{
+    file.last_modified()
+        .and_then(|t| OffsetDateTime::try_from(t).ok())
+        .map(SystemTime::from)
+}
+
+/// Returns the index of the first password in `passwords`, which decrypts entry `i`
+///
+/// `ZipCrypto` only checks a single byte of the password up front,
+/// so each candidate is verified by decompressing the whole entry (checking its CRC)
+fn find_zip_password<R>(
+    archive: &mut ZipArchive<R>,
+    i: usize,
+    budget: &Budget,
+    passwords: &[String],
+    last_password: Option<usize>,
+) -> Result<usize>
+where
+    R: Read + Seek,
+{
+    let size = archive
+        .by_index_raw(i)
+        .with_context(|| format!("unable to get raw file by index {i}"))?
+        .size();
+    // Verifying decompresses the entry, so a bomb should fail before
+    budget.check_declared(size)?;
+
+    let candidates = last_password
+        .into_iter()
+        .chain((0..passwords.len()).filter(|&p| Some(p) != last_password));
+    for password in candidates {
+        let Ok(mut file) = archive.by_index_decrypt(i, passwords[password].as_bytes()) else {
+            continue;
+        };
+        if io::copy(&mut file.by_ref().take(size + 1), &mut io::sink()).is_ok() {
+            trace!("password {password} matches");
+            return Ok(password);
+        }
+    }
+
+    Err(Report::new(NoMatchingPassword))
+}
+
 #[instrument]
 pub fn add_subs<P>(sub_dir_vec: &Vec<String>, tmp_dir: P) -> Result<()>
 where
@@ -144,9 +550,90 @@ where
     Ok(())
 }
 
+/// Recreates the tree of `src` at `dest`, hard linking the files,
+/// if that fails (e.g. different file systems), they are copied
+///
+/// Files are never modified in place afterward, only renamed or removed
+/// ([`SafeDest`] unlinks a file before writing it), so sharing them is fine
+///
+/// Symlinks in `src` (and anything else, that is neither a dir nor a file) are skipped,
+/// [`SafeDest`] relies on `dest` containing no links, `src` itself may be one
+pub fn link_tree(src: &Path, dest: &Path) -> Result<()> {
+    for entry in WalkDir::new(src) {
+        let entry = entry.with_context(|| format!("invalid entry in {src:?}"))?;
+        let target = dest.join(entry.path().strip_prefix(src)?);
+
+        if entry.depth() == 0 || entry.file_type().is_dir() {
+            fs::create_dir_all(&target).with_context(|| format!("unable to create {target:?}"))?;
+        } else if !entry.file_type().is_file() {
+            warn!("skipping {:?}, it is no regular file", entry.path());
+        } else if fs::hard_link(entry.path(), &target).is_err() {
+            fs::copy(entry.path(), &target)
+                .with_context(|| format!("unable to copy {:?} to {target:?}", entry.path()))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes a file or a dir with everything in it
+pub fn remove_entry(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+    .with_context(|| format!("unable to remove {path:?}"))
+}
+
+/// The dir next to `dir`, named like it with `suffix` appended (e.g. `tmp/` -> `tmp_old`)
+pub fn sibling_dir<P>(dir: P, suffix: &str) -> Result<PathBuf>
+where
+    P: AsRef<Path> + Debug,
+{
+    let dir = dir.as_ref();
+    let mut name = dir
+        .file_name()
+        .with_context(|| format!("unable to get the name of {dir:?}"))?
+        .to_owned();
+    name.push(suffix);
+
+    Ok(dir.with_file_name(name))
+}
+
+/// Fails, if `dir` is in one of the dirs of `jplag_args`,
+/// as every existing dir in them might be a root dir of new submissions
+pub fn check_outside_roots<P>(dir: P, jplag_args: &[String]) -> Result<()>
+where
+    P: AsRef<Path> + Debug,
+{
+    let dir = dir.as_ref();
+    let canonical_dir =
+        fs::canonicalize(dir).with_context(|| format!("unable to canonicalize {dir:?}"))?;
+    for arg in jplag_args {
+        let Ok(root) = fs::canonicalize(arg) else {
+            continue;
+        };
+        if root.is_dir() && canonical_dir.starts_with(&root) {
+            bail!("{dir:?} is in {arg:?}, jplag would compare it as a new submission");
+        }
+    }
+
+    Ok(())
+}
+
+/// An empty dir in the temp dir of the system, unique to the test `name` and this process
+#[cfg(test)]
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("jplag_wrapper_{}_{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("the temp dir should be writable");
+    dir
+}
+
 /// Fuck Apple
 #[instrument(skip_all)]
-pub fn sanitize_submissions<P>(path: P) -> Result<()>
+pub fn sanitize_submissions<P>(path: P, keep_non_ascii: bool, progress: &Progress) -> Result<()>
 where
     P: AsRef<Path> + Debug,
 {
@@ -182,6 +669,7 @@ where
     ];
 
     debug!("removing files");
+    progress.set_phase("sanitizing: removing junk files");
 
     'outer: for entry in WalkDir::new(&path) {
         let entry = entry.with_context(|| format!("invalid entry in {path:?}"))?;
@@ -201,10 +689,11 @@ where
         } else {
             for file in TO_REM_FILES {
                 // path.ends_with() only considers while parts, so we can't match extensions **and** file names with it
+                // Compared as bytes, names which are no valid UTF-8 are repaired afterward
                 if path
-                    .to_str()
-                    .with_context(|| format!("invalid file name: {path:?}"))?
-                    .ends_with(file)
+                    .as_os_str()
+                    .as_encoded_bytes()
+                    .ends_with(file.as_bytes())
                 {
                     trace!("found match to remove");
                     fs::remove_file(path).with_context(|| format!("unable to remove {path:?}"))?;
@@ -215,5 +704,385 @@ where
         trace!("no match found");
     }
 
+    progress.set_phase("sanitizing: normalizing file names");
+    normalize_file_names(&path, keep_non_ascii)
+        .with_context(|| format!("unable to normalize file names in {path:?}"))
+}
+
+/// Repairs the names of everything in the submissions in `path`
+///
+/// The names of the submission dirs themselves are kept,
+/// the origins, the layout mapping and the cache refer to them
+///
+/// - Names, which are no valid UTF-8, are decoded as Windows-1252
+///   (zip names are already decoded while unzipping)
+/// - Names are normalized to NFC, macOS writes decomposed (NFD) names
+/// - Unless `keep_non_ascii` is set, names are transliterated to ASCII (e.g. `ä` -> `a`)
+///
+/// If the repaired name is already taken, a counter is appended (`Main_1.java`)
+#[instrument(skip(keep_non_ascii))]
+pub fn normalize_file_names<P>(path: P, keep_non_ascii: bool) -> Result<()>
+where
+    P: AsRef<Path> + Debug,
+{
+    debug!("normalizing file names");
+
+    // Contents first, so renaming a dir doesn't invalidate the paths of its entries
+    for entry in WalkDir::new(&path).min_depth(2).contents_first(true) {
+        let entry = entry.with_context(|| format!("invalid entry in {path:?}"))?;
+        let entry_path = entry.path();
+        let name = entry.file_name();
+
+        let repaired = repair_name(name, keep_non_ascii);
+        if repaired.as_str() == name {
+            continue;
+        }
+
+        let mut new_path = entry_path.with_file_name(&repaired);
+        let mut suffix = 1;
+        while new_path.exists() {
+            new_path = entry_path.with_file_name(numbered_name(&repaired, suffix));
+            suffix += 1;
+        }
+
+        trace!("renaming {entry_path:?} to {new_path:?}");
+        fs::rename(entry_path, &new_path)
+            .with_context(|| format!("unable to rename {entry_path:?} to {new_path:?}"))?;
+    }
+
     Ok(())
 }
+
+/// Repairs a single name, like [`normalize_file_names`] does
+pub fn repair_name(name: &OsStr, keep_non_ascii: bool) -> String {
+    let decoded = name.to_str().map_or_else(
+        || {
+            let (decoded, _, _) = WINDOWS_1252.decode(name.as_encoded_bytes());
+            decoded.into_owned()
+        },
+        ToOwned::to_owned,
+    );
+
+    let normalized: String = decoded.nfc().collect();
+    if keep_non_ascii || normalized.is_ascii() {
+        return normalized;
+    }
+
+    let transliterated: String = deunicode(&normalized)
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '\0' => '_',
+            c => c,
+        })
+        .collect();
+    let transliterated = transliterated.trim();
+
+    if transliterated.is_empty() || transliterated == "." || transliterated == ".." {
+        String::from("_")
+    } else {
+        transliterated.to_owned()
+    }
+}
+
+/// `Main.java` -> `Main_1.java`
+fn numbered_name(name: &str, suffix: usize) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => format!("{stem}_{suffix}.{extension}"),
+        _ => format!("{name}_{suffix}"),
+    }
+}
+
+/// Collapses chains of directories with a single subdirectory as their only entry,
+/// e.g. `alice/Assignment3/Assignment3/src/...` becomes `alice/src/...`
+///
+/// The contents of the first directory with more than one entry or with a file
+/// are moved up into `submission_dir`
+#[instrument]
+pub fn flatten_wrapper_dirs<P>(submission_dir: P) -> Result<()>
+where
+    P: AsRef<Path> + Debug,
+{
+    let submission_dir = submission_dir.as_ref();
+
+    let mut content_dir = submission_dir.to_owned();
+    while let Some(only_child) = single_subdir(&content_dir)? {
+        content_dir = only_child;
+    }
+    if content_dir == submission_dir {
+        trace!("nothing to flatten");
+        return Ok(());
+    }
+    debug!("flattening {content_dir:?}");
+
+    // Move the content dir out of the wrappers first, an entry of it might have the same name as one of them
+    let outermost_wrapper = submission_dir.join(
+        content_dir
+            .strip_prefix(submission_dir)?
+            .components()
+            .next()
+            .context("content dir is no subdir")?,
+    );
+    let mut staging_dir = submission_dir.join(".flatten");
+    if staging_dir == outermost_wrapper {
+        staging_dir.set_file_name(".flatten_1");
+    }
+    fs::rename(&content_dir, &staging_dir)
+        .with_context(|| format!("unable to move {content_dir:?} to {staging_dir:?}"))?;
+    if outermost_wrapper != content_dir {
+        fs::remove_dir_all(&outermost_wrapper)
+            .with_context(|| format!("unable to remove wrapper dir {outermost_wrapper:?}"))?;
+    }
+
+    for entry in
+        fs::read_dir(&staging_dir).with_context(|| format!("unable to read {staging_dir:?}"))?
+    {
+        let entry = entry.with_context(|| format!("invalid entry in {staging_dir:?}"))?;
+        let target = submission_dir.join(entry.file_name());
+        fs::rename(entry.path(), &target)
+            .with_context(|| format!("unable to move {:?} to {target:?}", entry.path()))?;
+    }
+    fs::remove_dir(&staging_dir).with_context(|| format!("unable to remove {staging_dir:?}"))
+}
+
+/// Returns the subdirectory of `dir`, if it is its only entry
+fn single_subdir(dir: &Path) -> Result<Option<PathBuf>> {
+    let mut entries = fs::read_dir(dir).with_context(|| format!("unable to read {dir:?}"))?;
+    let (Some(first), None) = (entries.next(), entries.next()) else {
+        return Ok(None);
+    };
+
+    let first = first.with_context(|| format!("invalid entry in {dir:?}"))?;
+    let is_dir = first
+        .file_type()
+        .with_context(|| format!("unable to get file type of {:?}", first.path()))?
+        .is_dir();
+
+    Ok(is_dir.then(|| first.path()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repairs_names() {
+        let nfd = "Ba\u{308}r.java";
+        assert_eq!(repair_name(OsStr::new(nfd), true), "Bär.java");
+        assert_eq!(repair_name(OsStr::new(nfd), false), "Bar.java");
+        assert_eq!(repair_name(OsStr::new("Main.java"), false), "Main.java");
+        assert_eq!(repair_name(OsStr::new("日本"), false), "Ri Ben");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn decodes_windows_1252_names() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = OsStr::from_bytes(b"B\xe4r.java");
+        assert_eq!(repair_name(name, true), "Bär.java");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sanitizing_repairs_names_which_are_no_utf_8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = scratch_dir("sanitize_no_utf_8");
+        fs::create_dir(dir.join("alice")).unwrap();
+        fs::write(
+            dir.join("alice").join(OsStr::from_bytes(b"B\xe4r.java")),
+            "",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("alice").join(OsStr::from_bytes(b"B\xe4r.class")),
+            "",
+        )
+        .unwrap();
+
+        sanitize_submissions(&dir, true, &Progress::start("testing")).unwrap();
+
+        let names = fs::read_dir(dir.join("alice"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Bär.java"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn normalizing_resolves_collisions() {
+        let dir = scratch_dir("normalize_collisions");
+        fs::create_dir_all(dir.join("Jörg/Übung")).unwrap();
+        fs::write(dir.join("Jörg/Übung/Bär.java"), "nfc").unwrap();
+        fs::write(dir.join("Jörg/Übung/Ba\u{308}r.java"), "nfd").unwrap();
+
+        normalize_file_names(&dir, false).unwrap();
+
+        let mut names = WalkDir::new(&dir)
+            .min_depth(1)
+            .into_iter()
+            .map(|e| e.unwrap().path().strip_prefix(&dir).unwrap().to_owned())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            [
+                Path::new("Jörg"),
+                Path::new("Jörg/Ubung"),
+                Path::new("Jörg/Ubung/Bar.java"),
+                Path::new("Jörg/Ubung/Bar_1.java")
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flattens_wrapper_dirs() {
+        let dir = scratch_dir("flatten_wrappers");
+        let wrapped = dir.join("alice");
+        fs::create_dir_all(wrapped.join("Assignment3/Assignment3/src")).unwrap();
+        fs::write(wrapped.join("Assignment3/Assignment3/src/Main.java"), "").unwrap();
+        fs::write(wrapped.join("Assignment3/Assignment3/Util.java"), "").unwrap();
+        // A single dir next to a file is no wrapper
+        let not_wrapped = dir.join("bob");
+        fs::create_dir_all(not_wrapped.join("bob_final/src")).unwrap();
+        fs::write(not_wrapped.join("bob_final/src/Main.java"), "").unwrap();
+        fs::write(not_wrapped.join("Util.java"), "").unwrap();
+        // The content might be named like its wrapper
+        let same_name = dir.join("carol");
+        fs::create_dir_all(same_name.join("src/src")).unwrap();
+        fs::write(same_name.join("src/src/Main.java"), "").unwrap();
+        fs::write(same_name.join("src/Util.java"), "").unwrap();
+
+        for submission_dir in [&wrapped, &not_wrapped, &same_name] {
+            flatten_wrapper_dirs(submission_dir).unwrap();
+        }
+
+        assert!(wrapped.join("src/Main.java").is_file());
+        assert!(wrapped.join("Util.java").is_file());
+        assert!(!wrapped.join("Assignment3").exists());
+        assert!(not_wrapped.join("bob_final/src/Main.java").is_file());
+        assert!(not_wrapped.join("Util.java").is_file());
+        assert!(same_name.join("src/Main.java").is_file());
+        assert!(same_name.join("Util.java").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn extracts_dir_and_archive_sources() {
+        use std::io::Write;
+
+        let dir = scratch_dir("extract_source");
+        let registry = Registry::builtin();
+        let source_dir = dir.join("export");
+        fs::create_dir_all(source_dir.join("alice")).unwrap();
+        fs::write(source_dir.join("alice/src.zip"), "zip").unwrap();
+
+        extract_source(
+            &source_dir,
+            dir.join("from_dir"),
+            false,
+            &Checkout::default(),
+            &registry,
+        )
+        .unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("from_dir/alice/src.zip")).unwrap(),
+            "zip"
+        );
+        assert!(source_dir.join("alice/src.zip").is_file());
+
+        let mut tar = tar::Builder::new(vec![]);
+        tar.append_dir_all("", &source_dir).unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&tar.into_inner().unwrap()).unwrap();
+        let tar_gz = dir.join("export.tar.gz");
+        fs::write(&tar_gz, encoder.finish().unwrap()).unwrap();
+
+        // Streaming falls back to extracting everything
+        let streamed = extract_source(
+            &tar_gz,
+            dir.join("from_tar"),
+            true,
+            &Checkout::default(),
+            &registry,
+        )
+        .unwrap()
+        .streamed;
+        assert!(streamed.is_empty());
+        assert_eq!(
+            fs::read_to_string(dir.join("from_tar/alice/src.zip")).unwrap(),
+            "zip"
+        );
+        assert!(!dir.join("from_tar/export.tar.gz").exists());
+        assert!(tar_gz.is_file());
+
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(b"class Main {}").unwrap();
+        let gz = dir.join("Main.java.gz");
+        fs::write(&gz, encoder.finish().unwrap()).unwrap();
+        assert!(
+            extract_source(
+                &gz,
+                dir.join("from_gz"),
+                false,
+                &Checkout::default(),
+                &registry
+            )
+            .is_err()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn linking_skips_symlinks() {
+        let dir = scratch_dir("link_tree");
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("alice")).unwrap();
+        fs::write(src.join("alice/Main.java"), "class Main {}").unwrap();
+        std::os::unix::fs::symlink("/etc/passwd", src.join("alice/secret")).unwrap();
+        std::os::unix::fs::symlink(&src, dir.join("linked_src")).unwrap();
+
+        let dest = dir.join("dest");
+        link_tree(&dir.join("linked_src"), &dest).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(dest.join("alice/Main.java")).unwrap(),
+            "class Main {}"
+        );
+        assert!(!dest.join("alice/secret").exists() && !dest.join("alice/secret").is_symlink());
+        assert!(!dest.is_symlink());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sibling_dirs_get_the_suffix() {
+        assert_eq!(
+            sibling_dir("/work/tmp", "_old").unwrap(),
+            Path::new("/work/tmp_old")
+        );
+        assert!(sibling_dir("/", "_old").is_err());
+    }
+
+    #[test]
+    fn dirs_in_jplag_roots_are_rejected() {
+        let dir = scratch_dir("outside_roots");
+        let new_root = dir.join("new");
+        let old_dir = dir.join("tmp_old");
+        fs::create_dir_all(new_root.join("old")).unwrap();
+        fs::create_dir_all(&old_dir).unwrap();
+        let jplag_args = [
+            "--new".to_owned(),
+            new_root.to_string_lossy().into_owned(),
+            "-l".to_owned(),
+            "java".to_owned(),
+        ];
+
+        assert!(check_outside_roots(&old_dir, &jplag_args).is_ok());
+        let err = check_outside_roots(new_root.join("old"), &jplag_args).unwrap_err();
+        assert!(err.to_string().contains("jplag would compare it"), "{err}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
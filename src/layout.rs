@@ -0,0 +1,1159 @@
+use crate::archive_handler::SourceEntry;
+use crate::conf::config::Layout;
+use crate::deadline::Submitted;
+use crate::detect::Detection;
+use crate::helper;
+use color_eyre::Result;
+use color_eyre::eyre::{Context, ContextCompat, bail};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use time::PrimitiveDateTime;
+use time::format_description::BorrowedFormatItem;
+use time::macros::format_description;
+use tracing::{debug, info, instrument, trace, warn};
+
+/// Written to the target dir, next to the results of jplag
+pub const MAPPING_FILE_NAME: &str = "submission_mapping.toml";
+
+/// Separates the participant from the submission plugin in the names of a Moodle export
+const MOODLE_MARKER: &str = "_assignsubmission_";
+/// Separates the student from the attempt in the names of a Blackboard export
+const BLACKBOARD_MARKER: &str = "_attempt_";
+/// e.g. `2024-01-31-23-59-59`
+const BLACKBOARD_TIMESTAMP_LEN: usize = 19;
+/// The server time of Blackboard, without an offset, so it is taken as UTC
+const BLACKBOARD_TIMESTAMP_FORMAT: &[BorrowedFormatItem<'_>] =
+    format_description!("[year]-[month]-[day]-[hour]-[minute]-[second]");
+
+/// Archives kept in the source zip, see `init`
+pub type Streamed = Vec<(PathBuf, Detection, SourceEntry)>;
+
+/// Maps the clean IDs of the submissions back to the names in the source zip
+#[derive(Debug, Serialize)]
+pub struct Mapping {
+    pub submissions: Vec<MappedSubmission>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MappedSubmission {
+    /// Name of the submission dir, which jplag reports
+    pub id: String,
+    pub student: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub student_id: Option<String>,
+    pub layout: Layout,
+    #[serde(flatten)]
+    pub submitted: Submitted,
+    /// The source the submission came from, set when the sources are merged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// Every dir (or file) of the export, which was moved into this submission
+    pub original: Vec<String>,
+}
+
+impl Mapping {
+    #[instrument(skip(self))]
+    pub fn write<P>(&self, dir: P) -> Result<()>
+    where
+        P: AsRef<Path> + Debug,
+    {
+        let path = dir.as_ref().join(MAPPING_FILE_NAME);
+        let toml = toml::to_string_pretty(self)
+            .with_context(|| format!("unable to serialize the mapping {self:?}"))?;
+        fs::write(&path, toml).with_context(|| format!("unable to write {path:?}"))?;
+        info!("wrote the mapping of the submission IDs to {path:?}");
+
+        Ok(())
+    }
+}
+
+/// The student a submission belongs to, as far as the export tells
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Student {
+    pub name: String,
+    /// e.g. the participant ID of Moodle, if the export has one
+    pub id: Option<String>,
+}
+
+impl Student {
+    /// `Jane Doe`, `123456` -> `Jane_Doe_123456`
+    ///
+    /// Repaired like every other file name, so the sanitization doesn't rename it afterward
+    fn clean_id(&self, keep_non_ascii: bool) -> String {
+        let id = self
+            .id
+            .as_ref()
+            .map_or_else(|| self.name.clone(), |id| format!("{}_{id}", self.name));
+        let id = id.split_whitespace().collect::<Vec<_>>().join("_");
+        helper::repair_name(OsStr::new(&id), keep_non_ascii)
+    }
+}
+
+/// What a [`LayoutAdapter`] does with an entry of the export
+///
+/// Paths are relative to the tmp dir, entries without a placement are kept as they are
+#[derive(Debug, PartialEq, Eq)]
+pub enum Placement {
+    /// Moves `source` to `dest` inside the submission dir of `student`,
+    /// an empty `dest` merges the dir `source` into the submission dir
+    Move {
+        source: PathBuf,
+        student: Student,
+        dest: PathBuf,
+        /// When `source` was submitted, if the export tells
+        submitted: Submitted,
+    },
+    /// Removes `source` (e.g. an older attempt), `reason` is noted in the summary
+    Drop { source: PathBuf, reason: String },
+}
+
+/// Turns the export of an LMS into one directory per student
+///
+/// The adapter only decides where each entry goes, the moving is done by [`apply`],
+/// so a new layout only needs an implementation and an entry in [`builtin`]
+pub trait LayoutAdapter: Debug {
+    /// Which variant of [`Layout`] selects this adapter
+    fn layout(&self) -> Layout;
+
+    /// Whether the export looks like this layout, used by [`Layout::Auto`]
+    ///
+    /// `names` are the entries of `tmp_dir`, sorted
+    fn detect(&self, tmp_dir: &Path, names: &[String]) -> bool;
+
+    /// Decides where the entries of the export go
+    fn place(&self, tmp_dir: &Path, names: &[String]) -> Result<Vec<Placement>>;
+
+    /// Whether the submissions contain sources instead of archives (e.g. repositories),
+    /// so loose files have to be kept
+    fn loose_files(&self) -> bool {
+        false
+    }
+}
+
+/// Every builtin adapter, in the order [`Layout::Auto`] tries them
+fn builtin() -> Vec<Box<dyn LayoutAdapter>> {
+    vec![
+        Box::new(MoodleAdapter),
+        Box::new(CanvasAdapter),
+        Box::new(BlackboardAdapter),
+        Box::new(GithubClassroomAdapter),
+        Box::new(IliasAdapter),
+    ]
+}
+
+/// The export after applying its layout
+#[derive(Debug)]
+pub struct Applied {
+    /// The streamed archives, moved along with their submissions
+    pub streamed: Streamed,
+    /// From the clean IDs back to the original names, if the layout moved anything
+    pub mapping: Option<Mapping>,
+    /// Whether loose files have to be kept, see [`LayoutAdapter::loose_files`]
+    pub loose_files: bool,
+    /// What was removed and why, for the summary
+    pub notes: Vec<String>,
+}
+
+impl Applied {
+    const fn unchanged(streamed: Streamed) -> Self {
+        Self {
+            streamed,
+            mapping: None,
+            loose_files: false,
+            notes: vec![],
+        }
+    }
+}
+
+/// Turns the export in `tmp_dir` into one directory per student, named by a clean ID
+///
+/// With `Layout::Auto`, the first builtin adapter detecting the export is used,
+/// if none does, nothing is changed
+///
+/// Drops are applied before moves, dirs left empty by the moves are removed afterward
+#[instrument(skip(keep_non_ascii, streamed))]
+pub fn apply<P>(
+    tmp_dir: P,
+    layout: Layout,
+    keep_non_ascii: bool,
+    streamed: Streamed,
+) -> Result<Applied>
+where
+    P: AsRef<Path> + Debug,
+{
+    let tmp_dir = tmp_dir.as_ref();
+
+    let mut names = vec![];
+    for entry in fs::read_dir(tmp_dir).with_context(|| format!("unable to read {tmp_dir:?}"))? {
+        let entry = entry.with_context(|| format!("unable to read an entry in {tmp_dir:?}"))?;
+        // No export writes names, which are no valid UTF-8
+        if let Ok(name) = entry.file_name().into_string() {
+            names.push(name);
+        }
+    }
+    names.sort();
+
+    let adapters = builtin();
+    let adapter = match layout {
+        Layout::Plain => {
+            debug!("plain layout, keeping the submission names");
+            return Ok(Applied::unchanged(streamed));
+        }
+        Layout::Auto => {
+            let Some(adapter) = adapters
+                .into_iter()
+                .find(|adapter| adapter.detect(tmp_dir, &names))
+            else {
+                debug!("no known layout detected, keeping the submission names");
+                return Ok(Applied::unchanged(streamed));
+            };
+            info!("detected a {} export", adapter.layout());
+            adapter
+        }
+        layout => adapters
+            .into_iter()
+            .find(|adapter| adapter.layout() == layout)
+            .with_context(|| format!("no adapter for layout {layout}"))?,
+    };
+
+    let placements = adapter
+        .place(tmp_dir, &names)
+        .with_context(|| format!("unable to apply the {} layout", adapter.layout()))?;
+    let (drops, relocations): (Vec<_>, Vec<_>) = placements
+        .into_iter()
+        .partition(|placement| matches!(placement, Placement::Drop { .. }));
+
+    let mut notes = vec![];
+    let mut dropped = vec![];
+    for placement in drops {
+        let Placement::Drop { source, reason } = placement else {
+            continue;
+        };
+        let path = tmp_dir.join(&source);
+        trace!("removing {path:?}, {reason}");
+        helper::remove_entry(&path)?;
+        notes.push(format!("removed {source:?}, {reason}"));
+        dropped.push(path);
+    }
+
+    let mut submissions: BTreeMap<String, MappedSubmission> = BTreeMap::new();
+    let mut moved = vec![];
+    for placement in relocations {
+        let Placement::Move {
+            source,
+            student,
+            dest,
+            submitted,
+        } = placement
+        else {
+            continue;
+        };
+        let id = student.clean_id(keep_non_ascii);
+        let path = tmp_dir.join(&source);
+        let target = tmp_dir.join(&id).join(&dest);
+
+        move_to(tmp_dir, &path, &target)?;
+        moved.push((path, target));
+
+        let submission = submissions
+            .entry(id.clone())
+            .or_insert_with(|| MappedSubmission {
+                id,
+                student: student.name,
+                student_id: student.id,
+                layout: adapter.layout(),
+                submitted: Submitted::default(),
+                origin: None,
+                original: vec![],
+            });
+        submission.submitted = submission.submitted.merge(submitted);
+        submission
+            .original
+            .push(source.to_string_lossy().into_owned());
+    }
+    for (path, _) in &moved {
+        remove_empty_parents(tmp_dir, path);
+    }
+
+    match submissions.len() {
+        0 => warn!(
+            "layout is {}, but nothing in {tmp_dir:?} matches it",
+            adapter.layout()
+        ),
+        1 => info!("moved one {} submission", adapter.layout()),
+        n => info!("moved {n} {} submissions", adapter.layout()),
+    }
+
+    let streamed = streamed
+        .into_iter()
+        .filter(|(path, _, _)| !dropped.iter().any(|dropped| path.starts_with(dropped)))
+        .map(|(path, detection, entry)| {
+            let path = moved
+                .iter()
+                .find_map(|(source, target)| {
+                    let relative = path.strip_prefix(source).ok()?;
+                    Some(target.join(relative))
+                })
+                .unwrap_or(path);
+            (path, detection, entry)
+        })
+        .collect();
+
+    let mapping = (!submissions.is_empty()).then(|| Mapping {
+        submissions: submissions.into_values().collect(),
+    });
+
+    Ok(Applied {
+        streamed,
+        mapping,
+        loose_files: adapter.loose_files(),
+        notes,
+    })
+}
+
+/// Moves `path` to `target`, if both are dirs, the content of `path` is merged into `target`
+fn move_to(tmp_dir: &Path, path: &Path, target: &Path) -> Result<()> {
+    if path == target {
+        return Ok(());
+    }
+
+    let mut path = path.to_owned();
+    if path.is_dir() && target.is_dir() {
+        if path.starts_with(target) {
+            // Moved out of the way first, so its content can't collide with itself
+            let mut parked = tmp_dir.join(".layout");
+            let mut suffix = 1;
+            while parked.exists() {
+                parked = tmp_dir.join(format!(".layout_{suffix}"));
+                suffix += 1;
+            }
+            move_entry(&path, &parked)?;
+            path = parked;
+        }
+
+        trace!("merging {path:?} into {target:?}");
+        for child in fs::read_dir(&path).with_context(|| format!("unable to read {path:?}"))? {
+            let child = child.with_context(|| format!("unable to read an entry in {path:?}"))?;
+            move_entry(&child.path(), &target.join(child.file_name()))?;
+        }
+        return fs::remove_dir(&path).with_context(|| format!("unable to remove {path:?}"));
+    }
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).with_context(|| format!("unable to create {parent:?}"))?;
+    }
+    trace!("moving {path:?} to {target:?}");
+    move_entry(&path, target)
+}
+
+fn move_entry(src: &Path, dest: &Path) -> Result<()> {
+    if dest.exists() {
+        bail!("unable to move {src:?} to {dest:?}, it exists already");
+    }
+    fs::rename(src, dest).with_context(|| format!("unable to move {src:?} to {dest:?}"))
+}
+
+/// Removes the parents of a moved `path`, which are empty now (e.g. the wrapper dir of an export)
+fn remove_empty_parents(tmp_dir: &Path, path: &Path) {
+    let mut parent = path.parent();
+    while let Some(dir) = parent {
+        if dir == tmp_dir || !dir.starts_with(tmp_dir) || fs::remove_dir(dir).is_err() {
+            return;
+        }
+        trace!("removed empty {dir:?}");
+        parent = dir.parent();
+    }
+}
+
+/// Moodle assignment export, a dir per student and plugin
+/// (e.g. `Jane Doe_123456_assignsubmission_file_/`) or,
+/// without a folder per submission, the files with that prefix
+/// (e.g. `Jane Doe_123456_assignsubmission_file_Main.zip`)
+///
+/// The dirs of the same student (e.g. `file` and `onlinetext`) are merged
+#[derive(Debug)]
+struct MoodleAdapter;
+
+/// e.g. `Jane Doe_123456_assignsubmission_file_`
+#[derive(Debug, PartialEq, Eq)]
+struct MoodleName<'a> {
+    student: &'a str,
+    participant_id: &'a str,
+    /// e.g. `file` or `onlinetext`
+    plugin: &'a str,
+    /// Name of the submitted file, only set in exports without a folder per submission
+    file_name: &'a str,
+}
+
+impl<'a> MoodleName<'a> {
+    fn parse(name: &'a str) -> Option<Self> {
+        // The submitted file name is arbitrary, the student name never contains the marker
+        let (participant, rest) = name.split_once(MOODLE_MARKER)?;
+        let (student, participant_id) = participant.rsplit_once('_')?;
+        if student.is_empty() || !is_number(participant_id) {
+            return None;
+        }
+        let (plugin, file_name) = rest.split_once('_').unwrap_or((rest, ""));
+        if plugin.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            student,
+            participant_id,
+            plugin,
+            file_name,
+        })
+    }
+}
+
+impl LayoutAdapter for MoodleAdapter {
+    fn layout(&self) -> Layout {
+        Layout::Moodle
+    }
+
+    fn detect(&self, _tmp_dir: &Path, names: &[String]) -> bool {
+        names.iter().any(|name| MoodleName::parse(name).is_some())
+    }
+
+    fn place(&self, tmp_dir: &Path, names: &[String]) -> Result<Vec<Placement>> {
+        let mut placements = vec![];
+        for name in names {
+            let Some(moodle) = MoodleName::parse(name) else {
+                debug!("{name:?} is not part of the moodle export, keeping it");
+                continue;
+            };
+            let dest = if tmp_dir.join(name).is_dir() {
+                PathBuf::new()
+            } else if moodle.file_name.is_empty() {
+                PathBuf::from(moodle.plugin)
+            } else {
+                PathBuf::from(moodle.file_name)
+            };
+
+            placements.push(Placement::Move {
+                source: PathBuf::from(name),
+                student: Student {
+                    name: moodle.student.to_owned(),
+                    id: Some(moodle.participant_id.to_owned()),
+                },
+                dest,
+                submitted: Submitted::default(),
+            });
+        }
+
+        Ok(placements)
+    }
+}
+
+/// Canvas export, flat files `lastfirst_12345_67890_Main.zip`
+/// (user ID, then submission ID), late ones are marked `lastfirst_LATE_12345_67890_Main.zip`
+#[derive(Debug)]
+struct CanvasAdapter;
+
+#[derive(Debug, PartialEq, Eq)]
+struct CanvasName<'a> {
+    student: &'a str,
+    user_id: &'a str,
+    file_name: &'a str,
+    late: bool,
+}
+
+impl<'a> CanvasName<'a> {
+    fn parse(name: &'a str) -> Option<Self> {
+        let (student, rest) = name.split_once('_')?;
+        let late_rest = rest
+            .strip_prefix("LATE_")
+            .or_else(|| rest.strip_prefix("late_"));
+        let late = late_rest.is_some();
+        let rest = late_rest.unwrap_or(rest);
+        let (user_id, rest) = rest.split_once('_')?;
+        let (submission_id, file_name) = rest.split_once('_')?;
+        if student.is_empty()
+            || !is_number(user_id)
+            || !is_number(submission_id)
+            || file_name.is_empty()
+        {
+            return None;
+        }
+
+        Some(Self {
+            student,
+            user_id,
+            file_name,
+            late,
+        })
+    }
+}
+
+impl LayoutAdapter for CanvasAdapter {
+    fn layout(&self) -> Layout {
+        Layout::Canvas
+    }
+
+    fn detect(&self, tmp_dir: &Path, names: &[String]) -> bool {
+        names
+            .iter()
+            .any(|name| tmp_dir.join(name).is_file() && CanvasName::parse(name).is_some())
+    }
+
+    fn place(&self, tmp_dir: &Path, names: &[String]) -> Result<Vec<Placement>> {
+        let mut placements = vec![];
+        for name in names {
+            let canvas = CanvasName::parse(name).filter(|_| tmp_dir.join(name).is_file());
+            let Some(canvas) = canvas else {
+                debug!("{name:?} is not part of the canvas export, keeping it");
+                continue;
+            };
+
+            placements.push(Placement::Move {
+                source: PathBuf::from(name),
+                student: Student {
+                    name: canvas.student.to_owned(),
+                    id: Some(canvas.user_id.to_owned()),
+                },
+                dest: PathBuf::from(canvas.file_name),
+                submitted: Submitted {
+                    at: None,
+                    marked_late: canvas.late,
+                },
+            });
+        }
+
+        Ok(placements)
+    }
+}
+
+/// Blackboard export, flat files `Assignment_jdoe_attempt_2024-01-31-23-59-59_Main.zip`
+/// and a receipt per attempt (`Assignment_jdoe_attempt_2024-01-31-23-59-59.txt`)
+///
+/// Only the files of the latest attempt of each student are kept, the receipts are removed
+#[derive(Debug)]
+struct BlackboardAdapter;
+
+#[derive(Debug, PartialEq, Eq)]
+struct BlackboardName<'a> {
+    /// The assignment and the username, joined by `_`, both may contain `_` themselves
+    assignment_user: &'a str,
+    /// The timestamp, it sorts chronologically
+    attempt: &'a str,
+    /// `None` for the receipt
+    file_name: Option<&'a str>,
+}
+
+impl<'a> BlackboardName<'a> {
+    fn parse(name: &'a str) -> Option<Self> {
+        // The marker may be part of the username or the file name, only the one followed by a timestamp counts
+        let (marker, attempt) = name
+            .match_indices(BLACKBOARD_MARKER)
+            .find_map(|(marker, _)| {
+                let start = marker + BLACKBOARD_MARKER.len();
+                let attempt = name.get(start..start + BLACKBOARD_TIMESTAMP_LEN)?;
+                let is_timestamp = attempt.bytes().enumerate().all(|(i, b)| match i {
+                    4 | 7 | 10 | 13 | 16 => b == b'-',
+                    _ => b.is_ascii_digit(),
+                });
+                is_timestamp.then_some((marker, attempt))
+            })?;
+        let assignment_user = &name[..marker];
+        if !assignment_user.contains('_') || assignment_user.ends_with('_') {
+            return None;
+        }
+
+        let file_name = match &name[marker + BLACKBOARD_MARKER.len() + BLACKBOARD_TIMESTAMP_LEN..] {
+            ".txt" => None,
+            rest => Some(rest.strip_prefix('_').filter(|f| !f.is_empty())?),
+        };
+
+        Some(Self {
+            assignment_user,
+            attempt,
+            file_name,
+        })
+    }
+}
+
+/// The usernames of the Blackboard `names` keyed by their `assignment_user`
+///
+/// The assignment is the common prefix up to the last `_`,
+/// if all names are of one student, it ends at the first `_` instead
+fn blackboard_usernames<'a>(names: &[&'a str]) -> HashMap<&'a str, &'a str> {
+    let mut distinct = names.to_vec();
+    distinct.sort_unstable();
+    distinct.dedup();
+    let prefix_len = if distinct.len() > 1 {
+        assignment_prefix(&distinct, b'_').len()
+    } else {
+        distinct
+            .first()
+            .and_then(|name| name.find('_'))
+            .map_or(0, |i| i + 1)
+    };
+
+    distinct
+        .into_iter()
+        .map(|name| {
+            (
+                name,
+                name.get(prefix_len..)
+                    .filter(|u| !u.is_empty())
+                    .unwrap_or(name),
+            )
+        })
+        .collect()
+}
+
+impl LayoutAdapter for BlackboardAdapter {
+    fn layout(&self) -> Layout {
+        Layout::Blackboard
+    }
+
+    fn detect(&self, tmp_dir: &Path, names: &[String]) -> bool {
+        names
+            .iter()
+            .any(|name| tmp_dir.join(name).is_file() && BlackboardName::parse(name).is_some())
+    }
+
+    fn place(&self, tmp_dir: &Path, names: &[String]) -> Result<Vec<Placement>> {
+        let parsed = names
+            .iter()
+            .filter(|name| tmp_dir.join(name).is_file())
+            .filter_map(|name| Some((name, BlackboardName::parse(name)?)))
+            .collect::<Vec<_>>();
+        let usernames = blackboard_usernames(
+            &parsed
+                .iter()
+                .map(|(_, blackboard)| blackboard.assignment_user)
+                .collect::<Vec<_>>(),
+        );
+
+        let mut latest: HashMap<&str, &str> = HashMap::new();
+        for (_, blackboard) in &parsed {
+            let attempt = latest.entry(blackboard.assignment_user).or_default();
+            *attempt = blackboard.attempt.max(*attempt);
+        }
+
+        let mut placements = vec![];
+        for (name, blackboard) in parsed {
+            let source = PathBuf::from(name);
+            let username = usernames[blackboard.assignment_user];
+            let latest = latest[blackboard.assignment_user];
+            if blackboard.attempt != latest {
+                placements.push(Placement::Drop {
+                    source,
+                    reason: format!("older attempt of {username}, the latest is from {latest}"),
+                });
+                continue;
+            }
+            let Some(file_name) = blackboard.file_name else {
+                placements.push(Placement::Drop {
+                    source,
+                    reason: format!("receipt of {username}"),
+                });
+                continue;
+            };
+
+            placements.push(Placement::Move {
+                source,
+                student: Student {
+                    name: username.to_owned(),
+                    id: None,
+                },
+                dest: PathBuf::from(file_name),
+                submitted: Submitted {
+                    at: PrimitiveDateTime::parse(blackboard.attempt, BLACKBOARD_TIMESTAMP_FORMAT)
+                        .ok()
+                        .map(PrimitiveDateTime::assume_utc),
+                    marked_late: false,
+                },
+            });
+        }
+
+        Ok(placements)
+    }
+}
+
+/// GitHub Classroom, a dir of repositories named `assignment-username`
+///
+/// The common prefix of the repository names is cut off, their sources are kept as loose files
+///
+/// Only detected, if all entries are repositories, like [`crate::git::contains_only_repos`]
+#[derive(Debug)]
+struct GithubClassroomAdapter;
+
+impl GithubClassroomAdapter {
+    fn is_repo(tmp_dir: &Path, name: &str) -> bool {
+        tmp_dir.join(name).join(".git").exists()
+    }
+}
+
+impl LayoutAdapter for GithubClassroomAdapter {
+    fn layout(&self) -> Layout {
+        Layout::GithubClassroom
+    }
+
+    fn detect(&self, tmp_dir: &Path, names: &[String]) -> bool {
+        !names.is_empty() && names.iter().all(|name| Self::is_repo(tmp_dir, name))
+    }
+
+    fn place(&self, tmp_dir: &Path, names: &[String]) -> Result<Vec<Placement>> {
+        let repos = names
+            .iter()
+            .filter(|name| Self::is_repo(tmp_dir, name))
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        // A single repository has nothing to compare with, so it keeps its name
+        let prefix = if repos.len() > 1 {
+            assignment_prefix(&repos, b'-')
+        } else {
+            ""
+        };
+        debug!("assignment prefix is {prefix:?}");
+
+        let placements = repos
+            .into_iter()
+            .map(|repo| {
+                let username = repo.strip_prefix(prefix).filter(|u| !u.is_empty());
+                Placement::Move {
+                    source: PathBuf::from(repo),
+                    student: Student {
+                        name: username.unwrap_or(repo).to_owned(),
+                        id: None,
+                    },
+                    dest: PathBuf::new(),
+                    submitted: Submitted::default(),
+                }
+            })
+            .collect();
+
+        Ok(placements)
+    }
+
+    fn loose_files(&self) -> bool {
+        true
+    }
+}
+
+/// The longest common prefix of `names` up to and including its last `separator`,
+/// e.g. `assignment-1-` of `assignment-1-alice` and `assignment-1-bob`
+///
+/// `separator` has to be ASCII
+fn assignment_prefix<'a>(names: &[&'a str], separator: u8) -> &'a str {
+    let Some((first, rest)) = names.split_first() else {
+        return "";
+    };
+    let common = rest.iter().fold(first.len(), |len, name| {
+        first
+            .bytes()
+            .zip(name.bytes())
+            .take(len)
+            .take_while(|(a, b)| a == b)
+            .count()
+    });
+
+    // `separator` is ASCII, so the slice ends on a char boundary
+    first.as_bytes()[..common]
+        .iter()
+        .rposition(|&b| b == separator)
+        .map_or("", |end| &first[..=end])
+}
+
+/// ILIAS exercise export, a dir per user with a dir per attempt,
+/// optionally wrapped in a single dir named after the exercise
+///
+/// Only the latest attempt of each user is kept, a user dir without attempt dirs is kept as is
+///
+/// The names don't tell an ILIAS export apart, so it is never detected by [`Layout::Auto`]
+#[derive(Debug)]
+struct IliasAdapter;
+
+impl LayoutAdapter for IliasAdapter {
+    fn layout(&self) -> Layout {
+        Layout::Ilias
+    }
+
+    fn detect(&self, _tmp_dir: &Path, _names: &[String]) -> bool {
+        false
+    }
+
+    fn place(&self, tmp_dir: &Path, names: &[String]) -> Result<Vec<Placement>> {
+        let (root, users) = match names {
+            [only] if tmp_dir.join(only).is_dir() => {
+                let children = sorted_children(&tmp_dir.join(only))?;
+                if children.iter().all(|(_, is_dir)| *is_dir) {
+                    debug!("{only:?} wraps the export");
+                    let users = children.into_iter().map(|(name, _)| name).collect();
+                    (PathBuf::from(only), users)
+                } else {
+                    (PathBuf::new(), names.to_vec())
+                }
+            }
+            _ => (PathBuf::new(), names.to_vec()),
+        };
+
+        let mut placements = vec![];
+        for user in users {
+            let user_dir = root.join(&user);
+            if !tmp_dir.join(&user_dir).is_dir() {
+                debug!("{user_dir:?} is no user dir, keeping it");
+                continue;
+            }
+
+            let children = sorted_children(&tmp_dir.join(&user_dir))?;
+            let has_attempts = !children.is_empty() && children.iter().all(|(_, is_dir)| *is_dir);
+            let source = if has_attempts {
+                let (latest, _) = children
+                    .iter()
+                    .max_by(|(a, _), (b, _)| attempt_key(a).cmp(&attempt_key(b)))
+                    .with_context(|| format!("no attempt in {user_dir:?}"))?;
+                for (attempt, _) in &children {
+                    if attempt != latest {
+                        placements.push(Placement::Drop {
+                            source: user_dir.join(attempt),
+                            reason: format!("older attempt of {user}, the latest is {latest}"),
+                        });
+                    }
+                }
+                user_dir.join(latest)
+            } else {
+                user_dir
+            };
+
+            placements.push(Placement::Move {
+                source,
+                student: Student {
+                    name: user,
+                    id: None,
+                },
+                dest: PathBuf::new(),
+                submitted: Submitted::default(),
+            });
+        }
+
+        Ok(placements)
+    }
+}
+
+/// Numbered attempts are compared as numbers, so `10` comes after `9`,
+/// others (e.g. timestamps) by name
+fn attempt_key(name: &str) -> (Option<u64>, &str) {
+    (name.parse().ok(), name)
+}
+
+/// Names of the entries of `dir` and whether they are dirs
+fn sorted_children(dir: &Path) -> Result<Vec<(String, bool)>> {
+    let mut children = vec![];
+    for child in fs::read_dir(dir).with_context(|| format!("unable to read {dir:?}"))? {
+        let child = child.with_context(|| format!("unable to read an entry in {dir:?}"))?;
+        if let Ok(name) = child.file_name().into_string() {
+            children.push((name, child.path().is_dir()));
+        }
+    }
+    children.sort();
+
+    Ok(children)
+}
+
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moodle_name_of_a_dir() {
+        assert_eq!(
+            MoodleName::parse("Jane Doe_123456_assignsubmission_file_"),
+            Some(MoodleName {
+                student: "Jane Doe",
+                participant_id: "123456",
+                plugin: "file",
+                file_name: "",
+            })
+        );
+        assert_eq!(
+            MoodleName::parse("Jane Doe_123456_assignsubmission_onlinetext")
+                .map(|moodle| moodle.plugin),
+            Some("onlinetext")
+        );
+    }
+
+    #[test]
+    fn moodle_name_of_a_file() {
+        assert_eq!(
+            MoodleName::parse("Doe_Jane_42_assignsubmission_file_My_Main.zip"),
+            Some(MoodleName {
+                student: "Doe_Jane",
+                participant_id: "42",
+                plugin: "file",
+                file_name: "My_Main.zip",
+            })
+        );
+    }
+
+    #[test]
+    fn moodle_name_rejects_other_names() {
+        for name in [
+            "Main.zip",
+            "Jane Doe_assignsubmission_file_",
+            "Jane Doe_12a_assignsubmission_file_",
+            "_123456_assignsubmission_file_",
+            "Jane Doe_123456_assignsubmission_",
+        ] {
+            assert_eq!(MoodleName::parse(name), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn clean_id_of_a_student() {
+        let student = Student {
+            name: "Jane  Doe".to_owned(),
+            id: Some("123456".to_owned()),
+        };
+        assert_eq!(student.clean_id(false), "Jane_Doe_123456");
+
+        let student = Student {
+            name: "jdoe".to_owned(),
+            id: None,
+        };
+        assert_eq!(student.clean_id(false), "jdoe");
+    }
+
+    #[test]
+    fn canvas_name() {
+        assert_eq!(
+            CanvasName::parse("doejane_12345_67890_Main_v2.zip"),
+            Some(CanvasName {
+                student: "doejane",
+                user_id: "12345",
+                file_name: "Main_v2.zip",
+                late: false,
+            })
+        );
+        for name in [
+            "doejane_LATE_12345_67890_Main.zip",
+            "doejane_late_12345_67890_Main.zip",
+        ] {
+            let canvas = CanvasName::parse(name).unwrap();
+            assert!(canvas.late, "{name}");
+            assert_eq!(canvas.user_id, "12345");
+            assert_eq!(canvas.file_name, "Main.zip");
+        }
+    }
+
+    #[test]
+    fn canvas_name_rejects_other_names() {
+        for name in [
+            "Main.zip",
+            "doejane_12345_Main.zip",
+            "doejane_12345_67890_",
+            "_12345_67890_Main.zip",
+            "doejane_LATE_x_67890_Main.zip",
+        ] {
+            assert_eq!(CanvasName::parse(name), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn blackboard_name() {
+        assert_eq!(
+            BlackboardName::parse("Assignment 1_jdoe_attempt_2024-01-31-23-59-59_Main.zip"),
+            Some(BlackboardName {
+                assignment_user: "Assignment 1_jdoe",
+                attempt: "2024-01-31-23-59-59",
+                file_name: Some("Main.zip"),
+            })
+        );
+        assert_eq!(
+            BlackboardName::parse("Assignment_jdoe_attempt_2024-01-31-23-59-59.txt")
+                .map(|blackboard| blackboard.file_name),
+            Some(None)
+        );
+        assert_eq!(
+            BlackboardName::parse("A_jane_attempt_x_attempt_2024-01-31-23-59-59_my_attempt_1.zip"),
+            Some(BlackboardName {
+                assignment_user: "A_jane_attempt_x",
+                attempt: "2024-01-31-23-59-59",
+                file_name: Some("my_attempt_1.zip"),
+            })
+        );
+        for name in [
+            "Main.zip",
+            "jdoe_attempt_2024-01-31-23-59-59_Main.zip",
+            "Assignment_jdoe_attempt_2024-01-31_Main.zip",
+            "Assignment_jdoe_attempt_2024-01-31-23-59-59_",
+            "Assignment__attempt_2024-01-31-23-59-59_Main.zip",
+        ] {
+            assert_eq!(BlackboardName::parse(name), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn blackboard_usernames_may_contain_underscores() {
+        let usernames = blackboard_usernames(&["Lab_1_jane_doe", "Lab_1_bob", "Lab_1_jane_doe"]);
+        assert_eq!(usernames.len(), 2);
+        assert_eq!(usernames["Lab_1_jane_doe"], "jane_doe");
+        assert_eq!(usernames["Lab_1_bob"], "bob");
+
+        assert_eq!(
+            blackboard_usernames(&["Lab 1_jane_doe"])["Lab 1_jane_doe"],
+            "jane_doe"
+        );
+    }
+
+    #[test]
+    fn blackboard_keeps_the_latest_attempt() {
+        let tmp_dir = helper::scratch_dir("blackboard_latest");
+        let names = [
+            "A_jdoe_attempt_2024-01-30-10-00-00.txt",
+            "A_jdoe_attempt_2024-01-30-10-00-00_Main.zip",
+            "A_jdoe_attempt_2024-01-31-23-59-59.txt",
+            "A_jdoe_attempt_2024-01-31-23-59-59_Main.zip",
+        ]
+        .map(ToOwned::to_owned);
+        for name in &names {
+            fs::write(tmp_dir.join(name), "").unwrap();
+        }
+
+        let placements = BlackboardAdapter.place(&tmp_dir, &names).unwrap();
+
+        assert_eq!(placements.len(), 4);
+        let moved = placements
+            .iter()
+            .filter_map(|placement| match placement {
+                Placement::Move {
+                    source, submitted, ..
+                } => Some((source, submitted)),
+                Placement::Drop { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            moved,
+            [(
+                &PathBuf::from(&names[3]),
+                &Submitted {
+                    at: Some(time::macros::datetime!(2024-01-31 23:59:59 UTC)),
+                    marked_late: false,
+                }
+            )]
+        );
+        fs::remove_dir_all(&tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn assignment_prefix_ends_with_a_dash() {
+        assert_eq!(
+            assignment_prefix(&["assignment-1-alice", "assignment-1-alex"], b'-'),
+            "assignment-1-"
+        );
+        assert_eq!(assignment_prefix(&["hw-alice", "lab-bob"], b'-'), "");
+        assert_eq!(assignment_prefix(&["A_1_alice", "A_1_bob"], b'_'), "A_1_");
+        assert_eq!(assignment_prefix(&[], b'-'), "");
+    }
+
+    #[test]
+    fn github_classroom_usernames() {
+        let tmp_dir = helper::scratch_dir("github_classroom_usernames");
+        let names = ["hw-1-alice", "hw-1-bob", "notes.txt"].map(ToOwned::to_owned);
+        for repo in &names[..2] {
+            fs::create_dir_all(tmp_dir.join(repo).join(".git")).unwrap();
+        }
+        fs::write(tmp_dir.join(&names[2]), "").unwrap();
+
+        let students = GithubClassroomAdapter
+            .place(&tmp_dir, &names)
+            .unwrap()
+            .into_iter()
+            .filter_map(|placement| match placement {
+                Placement::Move { student, .. } => Some(student.name),
+                Placement::Drop { .. } => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(students, ["alice", "bob"]);
+        fs::remove_dir_all(&tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn github_classroom_is_only_detected_with_only_repos() {
+        let tmp_dir = helper::scratch_dir("github_classroom_detect");
+        let names = ["hw-1-alice", "hw-1-bob"].map(ToOwned::to_owned);
+        for repo in &names {
+            fs::create_dir_all(tmp_dir.join(repo).join(".git")).unwrap();
+        }
+        assert!(GithubClassroomAdapter.detect(&tmp_dir, &names));
+
+        // A student, who handed in a repository with its `.git`, doesn't make it a GitHub Classroom export
+        fs::create_dir(tmp_dir.join("carol")).unwrap();
+        let names = ["carol", "hw-1-alice", "hw-1-bob"].map(ToOwned::to_owned);
+        assert!(!GithubClassroomAdapter.detect(&tmp_dir, &names));
+        assert!(!GithubClassroomAdapter.detect(&tmp_dir, &[]));
+        fs::remove_dir_all(&tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn ilias_keeps_the_latest_attempt() {
+        let tmp_dir = helper::scratch_dir("ilias_latest");
+        for attempt in ["exercise/jdoe/9", "exercise/jdoe/10", "exercise/asmith"] {
+            fs::create_dir_all(tmp_dir.join(attempt)).unwrap();
+        }
+        fs::write(tmp_dir.join("exercise/asmith/Main.java"), "").unwrap();
+
+        let placements = IliasAdapter
+            .place(&tmp_dir, &["exercise".to_owned()])
+            .unwrap();
+
+        assert!(placements.contains(&Placement::Drop {
+            source: PathBuf::from("exercise/jdoe/9"),
+            reason: "older attempt of jdoe, the latest is 10".to_owned(),
+        }));
+        let moved = placements
+            .iter()
+            .filter_map(|placement| match placement {
+                Placement::Move {
+                    source, student, ..
+                } => Some((source.to_str().unwrap(), student.name.as_str())),
+                Placement::Drop { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            moved,
+            [("exercise/asmith", "asmith"), ("exercise/jdoe/10", "jdoe")]
+        );
+        fs::remove_dir_all(&tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn moodle_export_is_renamed() {
+        let tmp_dir = helper::scratch_dir("layout_moodle");
+        let file_dir = tmp_dir.join("Jane Doe_123456_assignsubmission_file_");
+        fs::create_dir(&file_dir).unwrap();
+        fs::write(file_dir.join("src.zip"), "").unwrap();
+        let text_dir = tmp_dir.join("Jane Doe_123456_assignsubmission_onlinetext_");
+        fs::create_dir(&text_dir).unwrap();
+        fs::write(text_dir.join("onlinetext.html"), "").unwrap();
+        fs::write(tmp_dir.join("Max_7_assignsubmission_file_Main.java"), "").unwrap();
+        fs::create_dir(tmp_dir.join("additional")).unwrap();
+
+        let Applied { mapping, .. } = apply(&tmp_dir, Layout::Auto, false, vec![]).unwrap();
+
+        assert!(tmp_dir.join("Jane_Doe_123456/src.zip").is_file());
+        assert!(tmp_dir.join("Jane_Doe_123456/onlinetext.html").is_file());
+        assert!(tmp_dir.join("Max_7/Main.java").is_file());
+        assert!(tmp_dir.join("additional").is_dir());
+        let mapping = mapping.unwrap();
+        let ids = mapping
+            .submissions
+            .iter()
+            .map(|s| (s.id.as_str(), s.original.len()))
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [("Jane_Doe_123456", 2), ("Max_7", 1)]);
+        fs::remove_dir_all(&tmp_dir).unwrap();
+    }
+}
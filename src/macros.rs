@@ -1,10 +1,10 @@
 macro_rules! handle_sub_err {
-    ($err_msg:expr, $to_execute:expr, $errs:expr, $abort_on_err:expr) => {
+    ($err_msg:expr, $to_execute:expr, $errs:expr, $submission:expr, $abort_on_err:expr) => {
         let _ = $to_execute;
         let err = color_eyre::eyre::anyhow!($err_msg);
         if $abort_on_err {
             return core::result::Result::Err(err);
         }
-        $errs.push(err);
+        $errs.push(($submission.to_owned(), err));
     };
 }
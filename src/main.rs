@@ -8,20 +8,45 @@
     clippy::cognitive_complexity,
     clippy::too_many_lines
 )]
+mod anonymize;
 mod archive_handler;
+mod base_code;
+mod cache;
 mod conf;
+mod deadline;
+mod detect;
+mod git;
 mod helper;
+mod layout;
 #[macro_use]
 mod macros;
+mod pool;
+mod progress;
+mod safe_extract;
+mod sources;
 
+use crate::archive_handler::{
+    ArchiveSource, ExtractCtx, NoMatchingPassword, Registry, Salvage, SourceEntry,
+};
+use crate::cache::Cache;
 use crate::conf::config::ARGS;
+use crate::conf::config::MultiArchivePolicy;
+use crate::deadline::LateFilter;
+use crate::detect::{ArchiveKind, Detection};
+use crate::git::Checkout;
+use crate::progress::{Progress, StatusWriter};
+use crate::safe_extract::{Budget, Limits};
+use crate::sources::{Failed, Origins, Staged};
 use color_eyre::eyre::{Context, ContextCompat, anyhow, bail};
 use color_eyre::{Report, Result};
 use conf::config;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
 use std::fs;
-use std::path::Path;
-use std::process::Command;
+use std::path::{Component, Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
 use std::time::Instant;
 use std::{env, thread};
 use tracing::{Level, debug_span, instrument, span, trace};
@@ -32,6 +57,9 @@ use walkdir::WalkDir;
 const PROGRAM_NAME: &str = "JPlag-rs";
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// Errors of submissions, with the dir of the submission they belong to
+type SubmissionErrs = Vec<(PathBuf, Report)>;
+
 fn main() -> Result<()> {
     color_eyre::install().context("failed to install :(")?;
     let start = Instant::now();
@@ -42,7 +70,10 @@ fn main() -> Result<()> {
             .parse::<Level>()
             .context("unable to parse log level")?;
 
-        let subscriber = FmtSubscriber::builder().with_max_level(log_level).finish();
+        let subscriber = FmtSubscriber::builder()
+            .with_max_level(log_level)
+            .with_writer(StatusWriter)
+            .finish();
         tracing::subscriber::set_global_default(subscriber)
             .context("setting default subscriber failed")?;
     }
@@ -60,36 +91,208 @@ fn main() -> Result<()> {
     info!("check successful");
 
     info!("initializing project");
-    init(
-        &parsed_args.source_file,
+    let progress = Progress::start("initializing");
+    let registry = Arc::new(Registry::builtin());
+    let (staged, origins) = init(
+        &parsed_args.source_files,
         &parsed_args.target_dir,
         &parsed_args.tmp_dir,
         &parsed_args.jplag_jar,
         &parsed_args.additional_submission_dirs,
+        parsed_args.stream,
+        &parsed_args.checkout,
+        &registry,
     )
     .context("initialization failed")?;
 
-    let (errs, processed_cnt) = prepare(&parsed_args.tmp_dir, parsed_args.abort_on_error)
-        .context("preparing submissions failed")?;
+    progress.set_phase("merging sources");
+    let merged = sources::merge(
+        &parsed_args.tmp_dir,
+        staged,
+        origins,
+        parsed_args.layout,
+        parsed_args.keep_non_ascii,
+        parsed_args.duplicate_policy,
+    )
+    .context("unable to merge the sources")?;
+    let mut failed = check_failed(merged.failed, parsed_args.abort_on_error)?;
+    let mut streamed = merged.streamed;
+    let mut mapping = merged.mapping;
+    let mut notes = merged.notes;
+
+    let filtered_out =
+        if parsed_args.deadline.is_some() || parsed_args.late_filter != LateFilter::All {
+            progress.set_phase("filtering late submissions");
+            let timestamps = parsed_args
+                .timestamps
+                .as_deref()
+                .map(deadline::read_csv)
+                .transpose()
+                .context("unable to read the timestamps")?
+                .unwrap_or_default();
+            let filtered = deadline::filter(
+                &parsed_args.tmp_dir,
+                parsed_args.deadline,
+                parsed_args.late_filter,
+                &timestamps,
+                &mut mapping,
+                &mut streamed,
+            )
+            .context("unable to filter the late submissions")?;
+            notes.extend(filtered.notes);
+            filtered.removed
+        } else {
+            vec![]
+        };
+
+    // The old submissions are prepared the same way, in their own root
+    let prepare_root = |root: &Path, loose_files: bool, streamed| {
+        prepare(
+            root,
+            parsed_args.abort_on_error,
+            parsed_args.max_nesting_depth,
+            parsed_args.limits,
+            &parsed_args.passwords,
+            parsed_args.keep_loose_files || loose_files,
+            parsed_args.keep_non_ascii,
+            parsed_args.flatten_wrapper_dirs,
+            parsed_args.salvage_corrupt,
+            parsed_args.cache_dir.as_deref(),
+            parsed_args.jobs,
+            parsed_args.multi_archive_policy,
+            &registry,
+            streamed,
+            &progress,
+        )
+    };
+
+    let (mut errs, prepare_notes, mut processed_cnt) = prepare_root(
+        Path::new(&parsed_args.tmp_dir),
+        merged.loose_files,
+        streamed,
+    )
+    .context("preparing submissions failed")?;
+    notes.extend(prepare_notes);
+    processed_cnt += failed.len();
+
+    let mut jplag_args = parsed_args.jplag_args.clone();
+    let old_dir = sources::old_dir(&parsed_args.tmp_dir)?;
+    let mut old_origins = Origins::new();
+    if !parsed_args.old_submissions.is_empty() {
+        info!("preparing old submissions");
+        progress.set_phase("extracting old sources");
+        debug!(?old_dir, "removing old submissions dir");
+        let _ = fs::remove_dir_all(&old_dir);
+        let staged = sources::stage(
+            &parsed_args.old_submissions,
+            &old_dir,
+            parsed_args.stream,
+            &parsed_args.checkout,
+            &registry,
+        )
+        .context("unable to extract the old submissions")?;
+
+        progress.set_phase("merging old sources");
+        let old = sources::merge(
+            &old_dir,
+            staged,
+            Origins::new(),
+            parsed_args.layout,
+            parsed_args.keep_non_ascii,
+            parsed_args.duplicate_policy,
+        )
+        .context("unable to merge the old submissions")?;
+        let old_failed = check_failed(old.failed, parsed_args.abort_on_error)?;
+
+        let (old_errs, old_notes, old_processed_cnt) =
+            prepare_root(&old_dir, old.loose_files, old.streamed)
+                .context("preparing old submissions failed")?;
+        errs.extend(old_errs);
+        processed_cnt += old_failed.len();
+        failed.extend(old_failed);
+        notes.extend(old.notes);
+        notes.extend(old_notes);
+        processed_cnt += old_processed_cnt;
+        mapping = match (mapping, old.mapping) {
+            (Some(mut mapping), Some(old_mapping)) => {
+                mapping.submissions.extend(old_mapping.submissions);
+                Some(mapping)
+            }
+            (mapping, old_mapping) => mapping.or(old_mapping),
+        };
+        old_origins = old.origins;
+
+        helper::check_outside_roots(&old_dir, &jplag_args)
+            .context("the old submissions would be compared against each other")?;
+        jplag_args.push(String::from("--old"));
+        jplag_args.push(old_dir.to_string_lossy().into_owned());
+    }
+
+    if let Some(mapping) = &mapping {
+        mapping
+            .write(&parsed_args.target_dir)
+            .context("unable to write the mapping of the submission IDs")?;
+    }
+
+    if let Some(base_code) = &parsed_args.base_code {
+        let base_code_dir = base_code::prepare(
+            base_code,
+            &parsed_args.tmp_dir,
+            &[
+                parsed_args.source_files.as_slice(),
+                &parsed_args.old_submissions,
+            ]
+            .concat(),
+            &jplag_args,
+            parsed_args.keep_non_ascii,
+            parsed_args.flatten_wrapper_dirs,
+            &registry,
+            &progress,
+        )
+        .context("preparing the base code failed")?;
+        jplag_args.push(String::from("--base-code"));
+        jplag_args.push(base_code_dir.to_string_lossy().into_owned());
+    }
+
+    if let Some(secret) = &parsed_args.anonymization_secret {
+        progress.set_phase("pseudonymizing submissions");
+        let mut roots = vec![PathBuf::from(&parsed_args.tmp_dir)];
+        if !parsed_args.old_submissions.is_empty() {
+            roots.push(old_dir.clone());
+        }
+        anonymize::pseudonymize(&roots, secret, &parsed_args.target_dir)
+            .context("unable to pseudonymize the submissions")?;
+    }
 
     let runtime = start.elapsed();
 
     run(
         &parsed_args.target_dir,
         &parsed_args.jplag_jar,
-        &parsed_args.jplag_args,
+        &jplag_args,
+        &progress,
     )
     .context("running jplag failed")?;
+    drop(progress);
 
-    let err_cnt = errs.len();
+    let err_cnt = errs.len() + failed.len();
 
     println!();
+    // Listed first, as filtering out everything leaves zero entries
+    match filtered_out.len() {
+        0 => {}
+        1 => info!("filtered out one submission:"),
+        n => info!("filtered out {n} submissions:"),
+    }
+    for submission in &filtered_out {
+        info!("  {submission}");
+    }
     match processed_cnt {
         0 => bail!("processed zero entries"),
         1 => info!("processed one entry"),
         n => info!("processed {n} entries"),
     }
-    match processed_cnt - err_cnt {
+    match processed_cnt.saturating_sub(err_cnt) {
         0 => bail!("no successful preparations"),
         1 => info!("successfully prepared one submission"),
         n => info!("successfully prepared {n} submissions"),
@@ -99,11 +302,68 @@ fn main() -> Result<()> {
         1 => warn!("there was 1 error"),
         n => warn!("there were {n} errors"),
     }
+    let encrypted_cnt = errs
+        .iter()
+        .filter(|(_, e)| e.downcast_ref::<NoMatchingPassword>().is_some())
+        .count();
+    match encrypted_cnt {
+        0 => {}
+        1 => warn!("1 submission was encrypted, no matching password"),
+        n => warn!("{n} submissions were encrypted, no matching password"),
+    }
+    for note in &notes {
+        info!("{note}");
+    }
     println!();
 
-    for err in &errs {
-        warn!(%err);
-        println!();
+    // With several sources (new or old), the errors are grouped by the source of their submission
+    let origin_cnt = merged
+        .origins
+        .values()
+        .chain(old_origins.values())
+        .chain(failed.iter().map(|failed| &failed.origin))
+        .collect::<HashSet<_>>()
+        .len();
+    if origin_cnt > 1 {
+        let mut by_origin: BTreeMap<&str, Vec<&Report>> = BTreeMap::new();
+        for (submission, err) in &errs {
+            let origins = if submission.starts_with(&old_dir) {
+                &old_origins
+            } else {
+                &merged.origins
+            };
+            let origin = submission
+                .file_name()
+                .and_then(|name| origins.get(name))
+                .map_or("unknown source", String::as_str);
+            by_origin.entry(origin).or_default().push(err);
+        }
+        for failed in &failed {
+            by_origin
+                .entry(failed.origin.as_str())
+                .or_default()
+                .push(&failed.err);
+        }
+        for (origin, errs) in by_origin {
+            match errs.len() {
+                1 => warn!("1 error in {origin:?}"),
+                n => warn!("{n} errors in {origin:?}"),
+            }
+            println!();
+            for err in errs {
+                warn!(%err);
+                println!();
+            }
+        }
+    } else {
+        for err in errs
+            .iter()
+            .map(|(_, err)| err)
+            .chain(failed.iter().map(|failed| &failed.err))
+        {
+            warn!(%err);
+            println!();
+        }
     }
 
     #[cfg(not(debug_assertions))]
@@ -118,6 +378,15 @@ fn main() -> Result<()> {
             let tmp_dir = &parsed_args.tmp_dir;
             fs::remove_dir_all(&tmp_dir)
                 .with_context(|| format!("removing tmp dir {tmp_dir:?} failed"))?;
+            if !parsed_args.old_submissions.is_empty() {
+                fs::remove_dir_all(&old_dir)
+                    .with_context(|| format!("removing old submissions dir {old_dir:?} failed"))?;
+            }
+            if parsed_args.base_code.is_some() {
+                let base_code_dir = base_code::dir(tmp_dir)?;
+                fs::remove_dir_all(&base_code_dir)
+                    .with_context(|| format!("removing base code dir {base_code_dir:?} failed"))?;
+            }
             info!(
                 "finished cleanup, goodbye! ({ms} ms)",
                 ms = runtime.as_millis()
@@ -134,49 +403,79 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Returns the submissions, which failed before they could be prepared (e.g. a broken repository),
+/// or the first failure, if `abort_on_error` is set
+fn check_failed(mut failed: Vec<Failed>, abort_on_error: bool) -> Result<Vec<Failed>> {
+    if abort_on_error && !failed.is_empty() {
+        return Err(failed.swap_remove(0).err);
+    }
+
+    Ok(failed)
+}
+
 /// Initializes the file structure and prerequisite setup for the program to execute.
 ///
 /// This function performs the following steps:
-/// 1. Verifies the existence of the source zip file and the `JPlag` JAR file.
+/// 1. Verifies the existence of the sources and the `JPlag` JAR file.
 /// 2. Removes and recreates the result directory.
 /// 3. Removes the temporary directory if it exists.
-/// 4. Unzips the source file into the temporary directory.
+/// 4. Extracts each source into its own staging dir in the temporary directory,
+///    they are merged by [`sources::merge`] afterward.
+///    A source is a directory, which is hard linked (or copied),
+///    a directory of git repositories and bundles, of which the `checkout` is checked out
+///    (their files are kept like loose files),
+///    or an archive of any kind the `registry` handles (e.g. `.zip`, `.tar.gz`, `.7z`).
+///    With `stream` and a zip source, archives inside of submissions stay in the source file
+///    and are returned, to be extracted by `prepare` directly from there.
 /// 5. Adds additional submissions from specified directories to the temporary directory.
 ///
 /// # Parameters
-/// - `source_file`: The path to the zip file containing the source submissions.
+/// - `source_files`: The paths to the directories or archives containing the source submissions.
 /// - `result_dir`: The directory path where the results will be stored.
-/// - `tmp_dir`: The temporary directory path where the contents of the source file
+/// - `tmp_dir`: The temporary directory path where the contents of the source files
 ///              will be unzipped and processed.
 /// - `jplag_jar`: The path to the `JPlag` JAR file
 /// - `additional_submission_dirs`: A vector of directory paths containing additional
 ///                                 submission files to be incorporated.
+/// - `stream`: Whether archives of submissions are kept in the source file.
+/// - `checkout`: Which commit of git repositories is checked out.
+/// - `registry`: The archive handlers, to extract a source, which is no zip.
+///
+/// # Returns
+/// The staged sources and the origins of the additional submissions.
 ///
 /// # Errors
 /// - Returns an error if:
-///   - The `source_file` does not exist or cannot be verified to exist.
+///   - A source does not exist or cannot be verified to exist.
+///   - A source is neither a directory nor a supported archive.
 ///   - The `jplag_jar` file does not exist or cannot be verified to exist.
 ///   - The `result_dir` cannot be created.
 ///   - The `tmp_dir` cannot be removed or unzipped to.
 ///   - Adding additional submissions to the temporary directory fails.
 #[instrument(skip_all)]
-fn init<P, Q, R>(
-    source_file: &P,
+// Takes the options one by one, like `prepare`
+#[allow(clippy::too_many_arguments)]
+fn init<Q, R>(
+    source_files: &[String],
     result_dir: Q,
     tmp_dir: R,
     jplag_jar: &str,
     additional_submission_dirs: &Vec<String>,
-) -> Result<()>
+    stream: bool,
+    checkout: &Checkout,
+    registry: &Registry,
+) -> Result<(Vec<Staged>, Origins)>
 where
-    P: AsRef<Path> + Debug + Into<String>,
     Q: AsRef<Path> + Debug,
     R: AsRef<Path> + Debug,
 {
-    debug!(?source_file, "checking if source zip file exist");
-    if !fs::exists(&source_file)
-        .with_context(|| format!("unable to confirm if {source_file:?} exists"))?
-    {
-        bail!("unable to find source zip file {source_file:?}");
+    for source_file in source_files {
+        debug!(?source_file, "checking if source exists");
+        if !fs::exists(source_file)
+            .with_context(|| format!("unable to confirm if {source_file:?} exists"))?
+        {
+            bail!("unable to find source {source_file:?}");
+        }
     }
 
     debug!(?jplag_jar, "checking if jplag jar file exists");
@@ -193,9 +492,7 @@ where
     debug!(?tmp_dir, "removing tmp dir");
     let _ = fs::remove_dir_all(&tmp_dir);
 
-    debug!("unzipping {source_file:?} to {tmp_dir:?}");
-    helper::unzip_to(&source_file, &tmp_dir)
-        .with_context(|| format!("unable to extract {source_file:?} to {tmp_dir:?}"))?;
+    let staged = sources::stage(source_files, &tmp_dir, stream, checkout, registry)?;
 
     helper::add_subs(&additional_submission_dirs, &tmp_dir).with_context(|| {
         format!(
@@ -203,10 +500,19 @@ where
             {additional_submission_dirs:?} to {tmp_dir:?}"
         )
     })?;
+    // Each additional submission dir is its own origin
+    let origins = additional_submission_dirs
+        .iter()
+        .filter_map(|dir| {
+            let name = Path::new(dir).components().find_map(|c| match c {
+                Component::Normal(name) => Some(name.to_owned()),
+                _ => None,
+            })?;
+            Some((name, dir.clone()))
+        })
+        .collect();
 
-    info!("unzipped {source_file:?} to {tmp_dir:?}");
-
-    Ok(())
+    Ok((staged, origins))
 }
 
 /// Prepares a given temporary directory by processing and extracting student submissions.
@@ -217,32 +523,64 @@ where
 /// Any errors encountered during this process are collected and returned.
 ///
 /// # Returns
-/// - `Ok(Vec<color_eyre::eyre::Error>)`: A vector of errors encountered during the processing, if no critical errors occurred.
+/// - `Ok((Vec<(PathBuf, color_eyre::eyre::Error)>, Vec<String>, usize))`: The errors encountered during the processing
+///   with the submission dir they belong to,
+///   notes for the summary and the count of processed archives, if no critical errors occurred.
 /// - `Err(color_eyre::eyre::Error)`: A critical error that stops the process entirely, such as being unable to read the provided directory.
 ///
 /// # Workflow
 /// The function undertakes the following operations:
-/// 1. Reads and iterates over the directories representing individual student submissions.
+/// 1. Reads and iterates over the directories representing individual student submissions, sorted by name.
 /// 2. For each student directory:
 ///     - Validates that the entry is a directory.
 ///     - Identifies the archive file within the directory.
-///         - Archives are recognized by their file extensions (e.g., `.zip`, `.rar`, `.7z`, `.tar`, `.gz`).
-///         - Non-archive files are removed.
-///         - If multiple archived files are found, the submission is rejected, and the directory is removed.
-///         - Submissions without any archive file are also rejected.
+///         - Archives are recognized by their magic bytes (e.g., zip, rar, 7z, tar, gzip),
+///           the file extension is only used if the content is inconclusive.
+///         - If the extension and the content disagree, the archive is extracted as what its content is
+///           and the mismatch is noted, the submission is not counted as failed.
+///         - Archives `streamed` from the source file (see `init`) belong to the submission as well,
+///           they are read into memory and never written to disk.
+///         - Non-archive files are removed, unless `keep_loose_files` is set.
+///           Then a submission without an archive, but with loose files, is passed through as is.
+///         - If multiple archived files are found, the `multi_archive_policy` decides:
+///           reject the submission and remove the directory, extract all into the same
+///           or numbered subdirectories, or only extract the newest or largest archive.
+///           Which archives were extracted is noted in the returned notes.
+///         - Submissions without any archive file (and without loose files) are also rejected.
 ///     - Extracts the contents of the archive if valid.
-///         - Supports archive handling using specific functions for `.zip`, `.rar`, `.7z`, `.tar`, and `.gz` file types.
+///         - Archives are extracted by the handler of the `registry` for their kind,
+///           the builtin handlers support `.zip`, `.rar`, `.7z`, `.tar`,
+///           compressed tars (`.tar.gz`, `.tgz`, `.tar.bz2`, `.tar.xz`, `.tar.zst`)
+///           and single compressed files (`.gz`, `.bz2`, `.xz`, `.zst`).
 ///         - Cleans up the directory if extraction fails.
+///     - Extracts archives inside the extracted submission, up to `max_nesting_depth` levels deep.
+///     - If `cache_dir` is set, unchanged submissions are restored from the cache instead of extracted,
+///       newly extracted ones are cached (before sanitization, which always runs).
+///     - The extractions run on a pool of `jobs` worker threads,
+///       their results are collected in the order of the submissions.
+///     - Reports how many submissions are extracted and how many bytes were written to `progress`.
+///     - Enforces the `limits` against decompression bombs while extracting,
+///       a submission exceeding them is reported and its partial output removed.
+///     - Tries the `passwords` in order for encrypted zip, 7z and rar archives,
+///       if none matches, the submission is reported as encrypted.
+///     - If `salvage_corrupt` is set, corrupt or truncated zips and tars are extracted as far as possible,
+///       the submission is noted as partially recovered with the lost entries.
 /// 3. Sanitizes the extracted submission files:
-///     - Removes or replaces invalid/diacritic characters in filenames.
-///     - Optionally cleans non-ASCII characters based on the `keep_non_ascii` flag.
+///     - Decodes file names, which are no valid UTF-8, as Windows-1252,
+///       zip entry names without the UTF-8 flag are already decoded as CP437 while extracting.
+///     - Normalizes file names to NFC (macOS writes them decomposed).
+///     - Transliterates non-ASCII characters to ASCII (e.g. `ä` -> `a`), unless `keep_non_ascii` is set.
+///     - Appends a counter, if a repaired name collides with an existing one.
+///     - The names of the student directories themselves are kept, they identify the submissions.
+///     - If `flatten_wrapper_dirs` is set, collapses chains of directories with a single subdirectory
+///       (e.g. `alice/Assignment3/Assignment3/src`) down to the first directory with more than one entry or a file.
 /// 4. Logs the total errors and processes all submissions or halts early if `abort_on_err` is set to `true`.
 ///
 /// # Error Handling
 /// - Errors can occur in the following scenarios:
 ///     - Unable to read or access the provided `tmp_dir` directory.
 ///     - Submission directories containing invalid or non-archive files.
-///     - Multiple archive files found within a directory.
+///     - Multiple archive files found within a directory, if the policy is to reject them.
 ///     - Failure during archive extraction.
 ///     - Failure during sanitization or non-ASCII cleaning.
 /// - All such errors are either logged or included in the returned error list (`Ok(Vec<color_eyre::eyre::Error>)`).
@@ -256,24 +594,83 @@ where
 ///
 /// # Note
 /// - The function assumes that all valid archive files are correctly formatted and extractable.
-/// - Submission directories must only contain one valid archive file. Multiple archives are not supported.
-#[instrument(skip(abort_on_err))]
-fn prepare<P>(tmp_dir: P, abort_on_err: bool) -> Result<(Vec<Report>, usize)>
+/// - Submission directories with multiple archive files are handled according to `multi_archive_policy`.
+#[instrument(skip(
+    abort_on_err,
+    max_nesting_depth,
+    limits,
+    passwords,
+    registry,
+    streamed,
+    progress
+))]
+// Takes the options one by one, like `init`
+#[allow(clippy::too_many_arguments, clippy::fn_params_excessive_bools)]
+fn prepare<P>(
+    tmp_dir: P,
+    abort_on_err: bool,
+    max_nesting_depth: usize,
+    limits: Limits,
+    passwords: &[String],
+    keep_loose_files: bool,
+    keep_non_ascii: bool,
+    flatten_wrapper_dirs: bool,
+    salvage_corrupt: bool,
+    cache_dir: Option<&str>,
+    jobs: usize,
+    multi_archive_policy: MultiArchivePolicy,
+    registry: &Arc<Registry>,
+    streamed: Vec<(PathBuf, Detection, SourceEntry)>,
+    progress: &Progress,
+) -> Result<(SubmissionErrs, Vec<String>, usize)>
 where
     P: AsRef<Path> + Debug,
 {
     info!("extracting individual submissions");
+    progress.set_phase("extracting submissions");
     let tmp_dir = tmp_dir.as_ref();
 
     let mut processed_cnt = 0;
     let mut errs = vec![];
-    let mut workers = vec![];
+    let mut notes = vec![];
+    let mut extraction_jobs = vec![];
+    let total_written = Arc::new(AtomicU64::new(0));
+    let cache = cache_dir
+        .map(|cache_dir| {
+            let settings = format!(
+                "{max_nesting_depth}|{limits:?}|{passwords:?}|{keep_loose_files}|\
+                {multi_archive_policy}|{salvage_corrupt}"
+            );
+            Cache::new(cache_dir, settings)
+        })
+        .transpose()
+        .context("unable to open the cache")?;
+    let cache = cache.as_ref();
+    let passwords: Arc<[String]> = Arc::from(passwords);
 
-    'outer: for dir in
-        fs::read_dir(tmp_dir).with_context(|| format!("unable to read {tmp_dir:?}"))?
-    {
-        let dir = dir.with_context(|| format!("unable to read a dir in {tmp_dir:?}"))?;
-        let student_name_dir_path = dir.path();
+    let mut streamed_by_student: HashMap<PathBuf, Vec<_>> = HashMap::new();
+    for (archive_file_path, detection, entry) in streamed {
+        let student_name_dir_path = archive_file_path
+            .strip_prefix(tmp_dir)
+            .ok()
+            .and_then(|p| p.components().next())
+            .map(|c| tmp_dir.join(c))
+            .with_context(|| format!("{archive_file_path:?} is not in a submission"))?;
+        streamed_by_student
+            .entry(student_name_dir_path)
+            .or_default()
+            .push((archive_file_path, detection, entry));
+    }
+
+    // Sorted, so errors and notes are reported in the same order every run
+    let mut student_name_dir_paths = fs::read_dir(tmp_dir)
+        .with_context(|| format!("unable to read {tmp_dir:?}"))?
+        .map(|dir| dir.map(|dir| dir.path()))
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("unable to read a dir in {tmp_dir:?}"))?;
+    student_name_dir_paths.sort();
+
+    for student_name_dir_path in student_name_dir_paths {
         let span =
             span!(Level::INFO, "processing submissions", submission = ?student_name_dir_path);
         let _guard = span.enter();
@@ -285,13 +682,14 @@ where
                 "everything in {tmp_dir:?} should be a dir, found {student_name_dir_path:?}",
                 fs::remove_file(&student_name_dir_path),
                 errs,
+                &student_name_dir_path,
                 abort_on_err
             );
             continue;
         }
 
-        let mut archive_file = None;
-        let mut fun: fn(_, _, _) -> Result<()> = archive_handler::dummy;
+        let mut archives = vec![];
+        let mut loose_file_cnt = 0;
         for archive in WalkDir::new(&student_name_dir_path) {
             let archive =
                 archive.with_context(|| format!("invalid archive in {student_name_dir_path:?}"))?;
@@ -306,92 +704,235 @@ where
                 continue;
             }
 
-            let archive_extension = archive_file_path
-                .extension()
-                .and_then(|e| e.to_str())
-                .map(str::to_ascii_lowercase);
-
-            fun = match archive_extension {
-                Some(ref s) if s == "zip" => archive_handler::zip,
-                Some(ref s) if s == "rar" => archive_handler::rar,
-                Some(ref s) if s == "7z" => archive_handler::sz,
-                Some(ref s) if s == "tar" => archive_handler::tar,
-                Some(ref s) if s == "gz" => archive_handler::gz, // NOTE We assume, that all files ending in `.gz` are `.tar.gz` files
-                _ => {
-                    trace!("found non archive file {archive:?}, removing");
-                    fs::remove_file(&archive_file_path).with_context(|| {
-                        format!(
-                            "unable to remove non archive file \
-                            {archive:?}"
-                        )
-                    })?;
+            let detection = detect::detect(&archive_file_path).with_context(|| {
+                format!("unable to detect archive type of {archive_file_path:?}")
+            })?;
+
+            let Some(kind) = detection.kind() else {
+                if keep_loose_files {
+                    trace!("found loose file {archive:?}, keeping");
+                    loose_file_cnt += 1;
                     continue;
                 }
+                trace!("found non archive file {archive:?}, removing");
+                fs::remove_file(&archive_file_path).with_context(|| {
+                    format!(
+                        "unable to remove non archive file \
+                        {archive:?}"
+                    )
+                })?;
+                continue;
             };
             processed_cnt += 1;
-            if let Some(file) = archive_file {
-                debug!("multiple archives found");
-                handle_sub_err!(
-                    "found at least two archive files for student {student_name_dir_path:?}, \
-                        expected one:\n\
-                        \tfirst: {file:?}\n\
-                        \tsecond: {archive_file_path:?}",
-                    fs::remove_dir_all(&student_name_dir_path),
-                    errs,
-                    abort_on_err
-                );
-                continue 'outer;
-            }
-            archive_file = Some(archive_file_path.to_owned());
+            check_mismatch(detection, kind, archive_file_path, &mut notes);
+
+            archives.push((archive_file_path.to_owned(), kind, ArchiveSource::File));
         }
 
-        let Some(archive_file) = archive_file else {
+        for (archive_file_path, detection, entry) in streamed_by_student
+            .remove(&student_name_dir_path)
+            .unwrap_or_default()
+        {
+            let Some(kind) = detection.kind() else {
+                continue;
+            };
+            processed_cnt += 1;
+            check_mismatch(detection, kind, &archive_file_path, &mut notes);
+
+            archives.push((archive_file_path, kind, ArchiveSource::Entry(entry)));
+        }
+
+        if archives.is_empty() && loose_file_cnt > 0 {
+            debug!("no archive found, passing loose files through");
+            processed_cnt += 1;
+            notes.push(format!(
+                "{student_name_dir_path:?} contained no archive, \
+                passed {loose_file_cnt} loose files through"
+            ));
+            continue;
+        }
+
+        if archives.is_empty() {
             debug!("no archive found");
             handle_sub_err!(
                 "no archive for student {student_name_dir_path:?}",
                 fs::remove_dir_all(&student_name_dir_path),
                 errs,
+                &student_name_dir_path,
                 abort_on_err
             );
             continue;
-        };
+        }
 
-        // CONSIDER Add sender receiver to send errors. Every thread gets sender, later we collect after joining
-        let tmp_dir = tmp_dir.to_owned();
-        let handle = thread::spawn(move || {
-            // Fuck it, don't want to fight the compiler because it picks a lifetime for references, this will not be the bottleneck
-            // Btw. I was right, the multithreading as is cut the time of `prepare` from 11.6 to 4.5 seconds
-            let res = fun(tmp_dir, student_name_dir_path.clone(), archive_file.clone());
-            (res, student_name_dir_path, archive_file)
+        let archive_cnt = archives.len();
+        if archive_cnt > 1 {
+            debug!("multiple archives found");
+            if multi_archive_policy == MultiArchivePolicy::Reject {
+                let (first, second) = (&archives[0].0, &archives[1].0);
+                handle_sub_err!(
+                    "found at least two archive files for student {student_name_dir_path:?}, \
+                        expected one:\n\
+                        \tfirst: {first:?}\n\
+                        \tsecond: {second:?}",
+                    fs::remove_dir_all(&student_name_dir_path),
+                    errs,
+                    &student_name_dir_path,
+                    abort_on_err
+                );
+                continue;
+            }
+        }
+
+        let extractions = archive_handler::select(
+            &student_name_dir_path,
+            archives,
+            multi_archive_policy,
+            registry,
+        )
+        .with_context(|| {
+            format!("unable to apply multi archive policy to {student_name_dir_path:?}")
+        })?;
+        if archive_cnt > 1 {
+            notes.push(format!(
+                "{student_name_dir_path:?} contained {archive_cnt} archives, \
+                {multi_archive_policy} extracted {:?}",
+                extractions.iter().map(|e| &e.archive).collect::<Vec<_>>()
+            ));
+        }
+
+        let mut archive_bytes = 0;
+        for extraction in &extractions {
+            archive_bytes += extraction.archive_bytes()?;
+        }
+        let budget = Budget::new(limits, archive_bytes, Arc::clone(&total_written));
+        let passwords = Arc::clone(&passwords);
+        let registry = Arc::clone(registry);
+
+        // Btw. the multithreading cut the time of `prepare` from 11.6 to 4.5 seconds
+        extraction_jobs.push(move || {
+            let cache_key =
+                cache.and_then(
+                    |cache| match cache.key(&student_name_dir_path, &extractions) {
+                        Ok(key) => Some((cache, key)),
+                        Err(e) => {
+                            warn!(
+                                ?e,
+                                "unable to hash {student_name_dir_path:?}, not caching it"
+                            );
+                            None
+                        }
+                    },
+                );
+            let salvage = Salvage::new(salvage_corrupt);
+            let ctx = ExtractCtx {
+                registry: &registry,
+                budget: &budget,
+                passwords: &passwords,
+                salvage: &salvage,
+            };
+            // `Ok(true)`, if the submission was restored from the cache
+            let res = cache_key
+                .as_ref()
+                .map_or(Ok(false), |(cache, key)| {
+                    cache.restore(key, &student_name_dir_path)
+                })
+                .and_then(|restored| {
+                    if !restored {
+                        extractions.iter().try_for_each(|e| e.run(&ctx))?;
+                        archive_handler::nested(&student_name_dir_path, max_nesting_depth, &ctx)?;
+                    }
+                    Ok(restored)
+                });
+            let lost = salvage.into_lost();
+            // A partially recovered submission isn't cached, so it is reported on every run
+            if let (Ok(false), Some((cache, key))) = (&res, &cache_key)
+                && lost.is_empty()
+                && let Err(e) = cache.store(key, &student_name_dir_path)
+            {
+                warn!(?e, "unable to cache {student_name_dir_path:?}");
+            }
+            let archive_files = extractions
+                .into_iter()
+                .map(|e| e.archive)
+                .collect::<Vec<_>>();
+            let result = (res, student_name_dir_path, archive_files, lost);
+            progress.inc();
+            result
         });
-        workers.push(handle);
     }
 
-    for worker in workers {
-        let (res, student_name_dir_path, archive_file) = worker
-            .join()
-            .map_err(|e| anyhow!("unable to join worker: {e:?}"))?;
+    let mut restored_cnt = 0;
+    progress.set_total(extraction_jobs.len() as u64);
+    progress.track_bytes(Arc::clone(&total_written));
+    for result in pool::run(extraction_jobs, jobs) {
+        let (res, student_name_dir_path, archive_files, lost) =
+            result.map_err(|e| anyhow!("extraction worker panicked: {e:?}"))?;
+        if matches!(res, Ok(true)) {
+            restored_cnt += 1;
+        }
+        if res.is_ok() && !lost.is_empty() {
+            notes.push(format!(
+                "{student_name_dir_path:?} was partially recovered, lost:\n\t{}",
+                lost.join("\n\t")
+            ));
+        }
         if let Err(e) = res {
-            debug!(?e, "error extracting {archive_file:?}");
-            handle_sub_err!(
-                "error extracting {archive_file:?} \
-                         for {student_name_dir_path:?}: {e:?}",
-                fs::remove_file(&student_name_dir_path),
-                errs,
-                abort_on_err
-            );
+            debug!(?e, "error extracting {archive_files:?}");
+            let _ = fs::remove_dir_all(&student_name_dir_path);
+            // Wrapping instead of formatting keeps the cause, so the summary can categorize it
+            let msg =
+                format!("error extracting {archive_files:?} for {student_name_dir_path:?}: {e:?}");
+            let err = e.wrap_err(msg);
+            if abort_on_err {
+                return Err(err);
+            }
+            errs.push((student_name_dir_path, err));
         }
     }
 
+    match (cache, restored_cnt) {
+        (None, _) => {}
+        (Some(_), 1) => info!("restored one submission from the cache"),
+        (Some(_), n) => info!("restored {n} submissions from the cache"),
+    }
     info!("unzipped all submissions, Sanitizing output files");
-    helper::sanitize_submissions(&tmp_dir).with_context(|| "unable to sanitize output files")?;
+    helper::sanitize_submissions(&tmp_dir, keep_non_ascii, progress)
+        .with_context(|| "unable to sanitize output files")?;
 
-    Ok((errs, processed_cnt))
+    if flatten_wrapper_dirs {
+        info!("flattening wrapper dirs");
+        progress.set_phase("flattening wrapper dirs");
+        for dir in fs::read_dir(tmp_dir).with_context(|| format!("unable to read {tmp_dir:?}"))? {
+            let dir = dir.with_context(|| format!("unable to read a dir in {tmp_dir:?}"))?;
+            helper::flatten_wrapper_dirs(dir.path())
+                .with_context(|| format!("unable to flatten {:?}", dir.path()))?;
+        }
+    }
+
+    Ok((errs, notes, processed_cnt))
+}
+
+/// Notes, if the extension of an archive disagrees with its content
+///
+/// Only a note, not an error, the archive is extracted as what it is
+fn check_mismatch(detection: Detection, kind: ArchiveKind, path: &Path, notes: &mut Vec<String>) {
+    if let Some(ext_kind) = detection.mismatch() {
+        debug!(%kind, %ext_kind, "extension does not match content");
+        notes.push(format!(
+            "extension of {path:?} suggests {ext_kind}, \
+            but the content is {kind}, extracted as {kind}"
+        ));
+    }
 }
 
 /// Runs `JPlag` with the specified arguments and logs the results.
-#[instrument(skip(jplag_jar, jplag_args))]
-fn run(result_dir: &str, jplag_jar: &str, jplag_args: &Vec<String>) -> Result<()> {
+#[instrument(skip(jplag_jar, jplag_args, progress))]
+fn run(
+    result_dir: &str,
+    jplag_jar: &str,
+    jplag_args: &Vec<String>,
+    progress: &Progress,
+) -> Result<()> {
     let mut jplag_cmd = format!("java -jar {jplag_jar}");
 
     for str in jplag_args {
@@ -407,14 +948,29 @@ fn run(result_dir: &str, jplag_jar: &str, jplag_args: &Vec<String>) -> Result<()
         .arg("-jar")
         .arg(&jplag_jar)
         .args(jplag_args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
         .spawn()
         .with_context(|| format!("unable to run jplag command {jplag_cmd}"))?;
+    progress.set_phase("running jplag");
 
-    info!("finished running jplag");
+    // Piped through us, so the output doesn't collide with the status line
+    let stdout = child
+        .stdout
+        .take()
+        .context("stdout of jplag is not piped")?;
+    let stderr = child
+        .stderr
+        .take()
+        .context("stderr of jplag is not piped")?;
+    let status = thread::scope(|scope| {
+        scope.spawn(|| progress::forward_lines(stdout, false));
+        scope.spawn(|| progress::forward_lines(stderr, true));
+        child.wait()
+    })
+    .with_context(|| format!("unable to wait for child process {jplag_cmd:?}"))?;
 
-    let status = child
-        .wait()
-        .with_context(|| format!("unable to wait for child process {jplag_cmd:?}"))?;
+    info!("finished running jplag");
 
     if status.success() {
         debug!("{status}");
@@ -423,11 +979,17 @@ fn run(result_dir: &str, jplag_jar: &str, jplag_args: &Vec<String>) -> Result<()
 
         let mut result_file = None;
 
-        // This dir should only contain exactly one file
+        // This dir should only contain exactly one file, besides the mappings of the layout and pseudonyms
         for file in fs::read_dir(&result_dir)
             .with_context(|| format!("unable to read result dir {result_dir:?}"))?
         {
             let file = file.with_context(|| format!("invalid file in {result_dir:?}"))?;
+            if matches!(
+                file.file_name().to_str(),
+                Some(layout::MAPPING_FILE_NAME | anonymize::MAPPING_FILE_NAME)
+            ) {
+                continue;
+            }
             if let Some(prev) = result_file {
                 bail!("more than one file in {result_dir:?}: first = {prev:?}, second = {file:?}");
             }
@@ -446,3 +1008,220 @@ fn run(result_dir: &str, jplag_jar: &str, jplag_args: &Vec<String>) -> Result<()
         bail!("java jplag command failed, {status}");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use std::path::PathBuf;
+    use zip::ZipWriter;
+    use zip::write::SimpleFileOptions;
+
+    fn zip_bytes(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, data) in entries {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn write_zip(path: &Path, name: &str) {
+        fs::write(path, zip_bytes(&[(name, b"class Main {}")])).unwrap();
+    }
+
+    /// Relative path and content of every file in `dir`
+    fn files(dir: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        let mut files = WalkDir::new(dir)
+            .into_iter()
+            .map(Result::unwrap)
+            .filter(|e| e.file_type().is_file())
+            .map(|e| {
+                let path = e.path().strip_prefix(dir).unwrap().to_owned();
+                (path, fs::read(e.path()).unwrap())
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+        files
+    }
+
+    /// A tmp dir, where `alice` handed in two archives and `bob` a single one
+    fn two_archive_submissions(test: &str) -> PathBuf {
+        let tmp_dir = helper::scratch_dir(test);
+        for student in ["alice", "bob"] {
+            fs::create_dir(tmp_dir.join(student)).unwrap();
+        }
+        write_zip(&tmp_dir.join("alice/v1.zip"), "Main.java");
+        write_zip(&tmp_dir.join("alice/v2.zip"), "Util.java");
+        write_zip(&tmp_dir.join("bob/v1.zip"), "Main.java");
+        tmp_dir
+    }
+
+    fn prepare_with(
+        tmp_dir: &Path,
+        keep_loose_files: bool,
+        multi_archive_policy: MultiArchivePolicy,
+        streamed: Vec<(PathBuf, Detection, SourceEntry)>,
+    ) -> Result<(SubmissionErrs, Vec<String>, usize)> {
+        prepare(
+            tmp_dir,
+            false,
+            1,
+            Limits::UNLIMITED,
+            &[],
+            keep_loose_files,
+            true,
+            false,
+            false,
+            None,
+            2,
+            multi_archive_policy,
+            &Arc::new(Registry::builtin()),
+            streamed,
+            &Progress::start("testing"),
+        )
+    }
+
+    #[test]
+    fn multiple_archives_are_rejected() {
+        let tmp_dir = two_archive_submissions("prepare_reject");
+
+        let (errs, notes, _) =
+            prepare_with(&tmp_dir, false, MultiArchivePolicy::Reject, vec![]).unwrap();
+
+        assert_eq!(errs.len(), 1, "{errs:?}");
+        assert!(format!("{:?}", errs[0].1).contains("at least two archive files"));
+        assert!(notes.is_empty());
+        assert!(!tmp_dir.join("alice").exists());
+        assert!(tmp_dir.join("bob/Main.java").is_file());
+        fs::remove_dir_all(&tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn multiple_archives_are_merged() {
+        let tmp_dir = two_archive_submissions("prepare_merge");
+
+        let (errs, notes, _) =
+            prepare_with(&tmp_dir, false, MultiArchivePolicy::Merge, vec![]).unwrap();
+
+        assert!(errs.is_empty(), "{errs:?}");
+        assert_eq!(notes.len(), 1, "{notes:?}");
+        assert!(tmp_dir.join("alice/Main.java").is_file());
+        assert!(tmp_dir.join("alice/Util.java").is_file());
+        assert!(tmp_dir.join("bob/Main.java").is_file());
+        fs::remove_dir_all(&tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn mismatching_extensions_are_noted() {
+        let tmp_dir = helper::scratch_dir("prepare_mismatch");
+        fs::create_dir(tmp_dir.join("alice")).unwrap();
+        write_zip(&tmp_dir.join("alice/src.rar"), "Main.java");
+
+        let (errs, notes, processed_cnt) =
+            prepare_with(&tmp_dir, false, MultiArchivePolicy::Reject, vec![]).unwrap();
+
+        assert!(errs.is_empty(), "{errs:?}");
+        assert_eq!(processed_cnt, 1);
+        assert_eq!(notes.len(), 1, "{notes:?}");
+        assert!(notes[0].contains("suggests rar"), "{notes:?}");
+        assert!(tmp_dir.join("alice/Main.java").is_file());
+
+        // The same for an archive streamed from the source zip
+        let source_zip = tmp_dir.join("source.zip");
+        let inner = zip_bytes(&[("Main.java", b"class Main {}")]);
+        fs::write(&source_zip, zip_bytes(&[("bob/src.rar", &inner)])).unwrap();
+        let streamed_dir = tmp_dir.join("streamed");
+        let streamed = helper::unzip_streaming(&source_zip, &streamed_dir).unwrap();
+
+        let (errs, notes, processed_cnt) =
+            prepare_with(&streamed_dir, false, MultiArchivePolicy::Reject, streamed).unwrap();
+
+        assert!(errs.is_empty(), "{errs:?}");
+        assert_eq!(processed_cnt, 1);
+        assert_eq!(notes.len(), 1, "{notes:?}");
+        assert!(streamed_dir.join("bob/Main.java").is_file());
+        fs::remove_dir_all(&tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn loose_files_are_kept() {
+        for keep_loose_files in [true, false] {
+            let tmp_dir = helper::scratch_dir("prepare_loose_files");
+            for student in ["alice", "bob"] {
+                fs::create_dir(tmp_dir.join(student)).unwrap();
+            }
+            fs::write(tmp_dir.join("alice/Main.java"), "class Main {}").unwrap();
+            fs::write(tmp_dir.join("alice/Util.java"), "class Util {}").unwrap();
+
+            let (errs, notes, processed_cnt) = prepare_with(
+                &tmp_dir,
+                keep_loose_files,
+                MultiArchivePolicy::Reject,
+                vec![],
+            )
+            .unwrap();
+
+            // Without any file, bob is always rejected
+            assert!(!tmp_dir.join("bob").exists());
+            if keep_loose_files {
+                assert_eq!(errs.len(), 1, "{errs:?}");
+                assert_eq!(notes.len(), 1, "{notes:?}");
+                assert_eq!(processed_cnt, 1);
+                assert!(tmp_dir.join("alice/Main.java").is_file());
+                assert!(tmp_dir.join("alice/Util.java").is_file());
+            } else {
+                assert_eq!(errs.len(), 2, "{errs:?}");
+                assert!(!tmp_dir.join("alice").exists());
+            }
+            fs::remove_dir_all(&tmp_dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn streaming_matches_unzipping() {
+        let dir = helper::scratch_dir("prepare_streaming");
+        let inner = zip_bytes(&[("src/Main.java", b"class Main {}")]);
+        let nested = zip_bytes(&[("lib.zip", &inner), ("Util.java", b"class Util {}")]);
+        let source_zip = dir.join("source.zip");
+        fs::write(
+            &source_zip,
+            zip_bytes(&[
+                ("alice/v1.zip", &inner),
+                ("bob/project.zip", &nested),
+                ("bob/readme.txt", b"no archive"),
+            ]),
+        )
+        .unwrap();
+
+        let unzipped = dir.join("unzipped");
+        helper::unzip_to(
+            &source_zip,
+            &unzipped,
+            &Budget::unlimited(),
+            &[],
+            &Salvage::default(),
+        )
+        .unwrap();
+        let (errs, ..) =
+            prepare_with(&unzipped, false, MultiArchivePolicy::Reject, vec![]).unwrap();
+        assert!(errs.is_empty(), "{errs:?}");
+
+        let streamed_dir = dir.join("streamed");
+        let streamed = helper::unzip_streaming(&source_zip, &streamed_dir).unwrap();
+        assert_eq!(streamed.len(), 2);
+        let (errs, ..) =
+            prepare_with(&streamed_dir, false, MultiArchivePolicy::Reject, streamed).unwrap();
+        assert!(errs.is_empty(), "{errs:?}");
+
+        let files = files(&unzipped);
+        assert!(
+            files.iter().any(|(p, _)| p.ends_with("lib/src/Main.java")),
+            "{files:?}"
+        );
+        assert_eq!(files, self::files(&streamed_dir));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use tracing::{Span, debug, trace};
+
+/// Runs `jobs` on at most `workers` threads
+///
+/// The workers take the jobs from a shared queue and send the results back over a channel,
+/// the results are returned in the order of `jobs`, independent of which job finished first
+///
+/// A panicking job doesn't take the others down, its payload is returned instead
+pub fn run<T, F>(jobs: Vec<F>, workers: usize) -> Vec<Result<T, Box<dyn Any + Send>>>
+where
+    T: Send,
+    F: FnOnce() -> T + Send,
+{
+    let job_cnt = jobs.len();
+    let workers = workers.clamp(1, job_cnt.max(1));
+    debug!("running {job_cnt} jobs on {workers} workers");
+
+    let queue = Mutex::new(jobs.into_iter().enumerate());
+    let (sender, receiver) = mpsc::channel();
+    // Workers log in the span of the caller, like the jobs did before running on a pool
+    let span = Span::current();
+
+    thread::scope(|scope| {
+        for worker in 0..workers {
+            let sender = sender.clone();
+            let queue = &queue;
+            let span = span.clone();
+            scope.spawn(move || {
+                let _guard = span.enter();
+                loop {
+                    // The lock has to be released before running the job
+                    let next = queue.lock().unwrap_or_else(PoisonError::into_inner).next();
+                    let Some((i, job)) = next else {
+                        break;
+                    };
+                    trace!("worker {worker} runs job {i}");
+                    let res = panic::catch_unwind(AssertUnwindSafe(job));
+                    if sender.send((i, res)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    });
+    drop(sender);
+
+    let mut results: Vec<_> = (0..job_cnt).map(|_| None).collect();
+    for (i, res) in receiver {
+        results[i] = Some(res);
+    }
+
+    results
+        .into_iter()
+        .map(|res| res.expect("every job sends exactly one result"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn results_keep_the_order_of_the_jobs() {
+        let jobs = (0..20_u64)
+            .map(|i| {
+                move || {
+                    // Later jobs finish first
+                    thread::sleep(Duration::from_millis(20 - i));
+                    i
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let results = run(jobs, 4)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+
+        assert_eq!(results, (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn panics_are_returned() {
+        let jobs: Vec<Box<dyn FnOnce() -> u32 + Send>> = vec![
+            Box::new(|| 1),
+            Box::new(|| panic!("broken submission")),
+            Box::new(|| 3),
+        ];
+
+        let results = run(jobs, 1);
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(*results[0].as_ref().unwrap(), 1);
+        let payload = results[1].as_ref().unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"broken submission"));
+        assert_eq!(*results[2].as_ref().unwrap(), 3);
+    }
+
+    #[test]
+    fn no_jobs() {
+        let jobs: Vec<fn() -> u32> = vec![];
+        assert!(run(jobs, 0).is_empty());
+    }
+}
@@ -0,0 +1,311 @@
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, StdoutLock, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// How often the status line is redrawn on a terminal
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+/// How often a progress line is logged, if stdout is no terminal (e.g. in CI)
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The status line currently drawn at the bottom of the terminal, if any
+///
+/// Everything printing to stdout has to go through [`StatusWriter`] or [`print_line`],
+/// so the line is cleared before and redrawn after
+static STATUS: Mutex<Option<String>> = Mutex::new(None);
+
+fn lock_status() -> MutexGuard<'static, Option<String>> {
+    STATUS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Reports the progress of the current phase (e.g. extracting submissions)
+///
+/// On a terminal, a status line is redrawn in place,
+/// otherwise a plain log line is written every [`LOG_INTERVAL`]
+#[derive(Debug)]
+pub struct Progress {
+    state: Arc<State>,
+    ticker: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+struct State {
+    phase: Mutex<Phase>,
+    done: AtomicU64,
+    finished: Mutex<bool>,
+    wake: Condvar,
+}
+
+#[derive(Debug)]
+struct Phase {
+    name: String,
+    started: Instant,
+    total: Option<u64>,
+    bytes: Option<Arc<AtomicU64>>,
+}
+
+impl Progress {
+    /// Starts reporting, the first phase is `phase`
+    pub fn start(phase: &str) -> Self {
+        let state = Arc::new(State {
+            phase: Mutex::new(Phase::new(phase)),
+            done: AtomicU64::new(0),
+            finished: Mutex::new(false),
+            wake: Condvar::new(),
+        });
+
+        let ticker_state = Arc::clone(&state);
+        let ticker = thread::spawn(move || ticker_state.tick(io::stdout().is_terminal()));
+
+        Self {
+            state,
+            ticker: Some(ticker),
+        }
+    }
+
+    /// Switches to the next phase, its counter starts at zero
+    pub fn set_phase(&self, phase: &str) {
+        *self.state.lock_phase() = Phase::new(phase);
+        self.state.done.store(0, Ordering::Relaxed);
+        self.state.redraw(false);
+    }
+
+    /// How many steps the current phase has, shown as `done/total`
+    pub fn set_total(&self, total: u64) {
+        self.state.lock_phase().total = Some(total);
+    }
+
+    /// Shows `bytes` as the amount processed in the current phase
+    pub fn track_bytes(&self, bytes: Arc<AtomicU64>) {
+        self.state.lock_phase().bytes = Some(bytes);
+    }
+
+    /// Marks one step of the current phase as done
+    pub fn inc(&self) {
+        self.state.done.fetch_add(1, Ordering::Relaxed);
+        self.state.redraw(false);
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        *self
+            .state
+            .finished
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = true;
+        self.state.wake.notify_all();
+        if let Some(ticker) = self.ticker.take() {
+            let _ = ticker.join();
+        }
+
+        let mut status = lock_status();
+        if status.take().is_some() {
+            let mut stdout = io::stdout().lock();
+            let _ = write!(stdout, "\r\x1b[2K");
+            let _ = stdout.flush();
+        }
+    }
+}
+
+impl Phase {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            started: Instant::now(),
+            total: None,
+            bytes: None,
+        }
+    }
+}
+
+impl State {
+    fn lock_phase(&self) -> MutexGuard<'_, Phase> {
+        self.phase.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn tick(&self, is_terminal: bool) {
+        let interval = if is_terminal {
+            REDRAW_INTERVAL
+        } else {
+            LOG_INTERVAL
+        };
+
+        loop {
+            let finished = self
+                .wake
+                .wait_timeout_while(
+                    self.finished.lock().unwrap_or_else(PoisonError::into_inner),
+                    interval,
+                    |finished| !*finished,
+                )
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+            if *finished {
+                return;
+            }
+            drop(finished);
+
+            if is_terminal {
+                self.redraw(true);
+            } else {
+                info!("{}", self.render());
+            }
+        }
+    }
+
+    /// Draws the status line, if there is one already or `force` is set
+    fn redraw(&self, force: bool) {
+        let mut status = lock_status();
+        if status.is_none() && !force {
+            return;
+        }
+
+        let line = self.render();
+        if status.as_ref() == Some(&line) {
+            return;
+        }
+        let mut stdout = io::stdout().lock();
+        let _ = write!(stdout, "\r\x1b[2K{line}");
+        let _ = stdout.flush();
+        *status = Some(line);
+    }
+
+    /// e.g. `[00:42] extracting submissions 120/600, 1.3 GiB`
+    fn render(&self) -> String {
+        let phase = self.lock_phase();
+        let elapsed = phase.started.elapsed().as_secs();
+        let mut line = format!("[{:02}:{:02}] {}", elapsed / 60, elapsed % 60, phase.name);
+
+        if let Some(total) = phase.total {
+            let _ = write!(line, " {}/{total}", self.done.load(Ordering::Relaxed));
+        }
+        if let Some(bytes) = &phase.bytes {
+            let _ = write!(line, ", {}", format_bytes(bytes.load(Ordering::Relaxed)));
+        }
+        drop(phase);
+
+        line
+    }
+}
+
+// Only for display, the precision is plenty
+#[allow(clippy::cast_precision_loss)]
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// Prints `line` to stdout (or stderr), without mangling the status line
+pub fn print_line(line: &str, to_stderr: bool) {
+    let status = lock_status();
+    let mut stdout = io::stdout().lock();
+    if status.is_some() {
+        let _ = write!(stdout, "\r\x1b[2K");
+        let _ = stdout.flush();
+    }
+
+    if to_stderr {
+        let _ = writeln!(io::stderr(), "{line}");
+    } else {
+        let _ = writeln!(stdout, "{line}");
+    }
+
+    if let Some(status) = status.as_deref() {
+        let _ = write!(stdout, "{status}");
+    }
+    drop(status);
+    let _ = stdout.flush();
+}
+
+/// Writer for the log, clears the status line before each event and redraws it after
+#[derive(Clone, Copy, Debug)]
+pub struct StatusWriter;
+
+impl<'a> MakeWriter<'a> for StatusWriter {
+    type Writer = StatusGuard;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        let status = lock_status();
+        let stdout = io::stdout().lock();
+        StatusGuard {
+            status,
+            stdout,
+            cleared: false,
+        }
+    }
+}
+
+pub struct StatusGuard {
+    status: MutexGuard<'static, Option<String>>,
+    stdout: StdoutLock<'static>,
+    cleared: bool,
+}
+
+impl Write for StatusGuard {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !self.cleared && self.status.is_some() {
+            self.stdout.write_all(b"\r\x1b[2K")?;
+            self.cleared = true;
+        }
+        self.stdout.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+}
+
+impl Drop for StatusGuard {
+    fn drop(&mut self) {
+        if self.cleared
+            && let Some(status) = self.status.as_deref()
+        {
+            let _ = self.stdout.write_all(status.as_bytes());
+        }
+        let _ = self.stdout.flush();
+    }
+}
+
+/// Forwards the output of a child process line by line through [`print_line`]
+///
+/// Of a line redrawn with `\r` (e.g. a progress bar of the child), only the last state is printed
+pub fn forward_lines<R>(reader: R, to_stderr: bool)
+where
+    R: Read,
+{
+    let mut reader = BufReader::new(reader);
+    let mut line = vec![];
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => return,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim_end_matches(['\n', '\r']);
+                let visible = line.rsplit('\r').next().unwrap_or(line);
+                print_line(visible, to_stderr);
+            }
+            Err(e) => {
+                debug!(?e, "unable to read output of child");
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,533 @@
+use color_eyre::eyre::{Context, bail};
+use color_eyre::{Report, Result};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use tracing::{debug, instrument, trace};
+use walkdir::WalkDir;
+
+/// Below this, we don't check the compression ratio,
+/// tiny archives of text files easily have a high ratio
+const RATIO_GRACE_BYTES: u64 = 1024 * 1024;
+
+/// Limits against decompression bombs
+#[derive(Clone, Copy, Debug)]
+// Complains that all fields start with `max`
+#[allow(clippy::struct_field_names)]
+pub struct Limits {
+    /// Max uncompressed bytes of a single submission
+    pub max_submission_bytes: u64,
+    /// Max files, dirs and links of a single submission
+    pub max_entries: u64,
+    /// Max ratio between uncompressed bytes and the size of the submission archive
+    pub max_compression_ratio: u64,
+    /// Max uncompressed bytes of all submissions together
+    pub max_total_bytes: u64,
+}
+
+impl Limits {
+    pub const UNLIMITED: Self = Self {
+        max_submission_bytes: u64::MAX,
+        max_entries: u64::MAX,
+        max_compression_ratio: u64::MAX,
+        max_total_bytes: u64::MAX,
+    };
+}
+
+#[derive(Debug)]
+pub enum LimitExceeded {
+    SubmissionBytes(u64),
+    Entries(u64),
+    CompressionRatio(u64),
+    TotalBytes(u64),
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::SubmissionBytes(max) => {
+                write!(f, "submission expands to more than {max} bytes")
+            }
+            Self::Entries(max) => write!(f, "submission has more than {max} entries"),
+            Self::CompressionRatio(max) => write!(f, "compression ratio exceeds {max}:1"),
+            Self::TotalBytes(max) => {
+                write!(
+                    f,
+                    "all submissions together expand to more than {max} bytes"
+                )
+            }
+        }
+    }
+}
+
+impl Error for LimitExceeded {}
+
+/// Tracks how much a single submission extracted so far
+///
+/// Nested archives share the budget of their submission
+#[derive(Debug)]
+pub struct Budget {
+    limits: Limits,
+    /// Size of the submission archive, to calculate the compression ratio
+    archive_bytes: u64,
+    written: AtomicU64,
+    entries: AtomicU64,
+    /// Shared between all submissions of a run
+    total_written: Arc<AtomicU64>,
+}
+
+impl Budget {
+    pub const fn new(limits: Limits, archive_bytes: u64, total_written: Arc<AtomicU64>) -> Self {
+        Self {
+            limits,
+            archive_bytes,
+            written: AtomicU64::new(0),
+            entries: AtomicU64::new(0),
+            total_written,
+        }
+    }
+
+    /// For trusted archives, e.g. the source zip
+    pub fn unlimited() -> Self {
+        Self::new(Limits::UNLIMITED, 0, Arc::default())
+    }
+
+    fn add_entry(&self) -> Result<(), LimitExceeded> {
+        let entries = self.entries.fetch_add(1, Ordering::Relaxed) + 1;
+        if entries > self.limits.max_entries {
+            return Err(LimitExceeded::Entries(self.limits.max_entries));
+        }
+
+        Ok(())
+    }
+
+    /// Fails early, if an archive already announces an entry, which is too big
+    pub fn check_declared(&self, size: u64) -> Result<(), LimitExceeded> {
+        let written = self.written.load(Ordering::Relaxed);
+        if written.saturating_add(size) > self.limits.max_submission_bytes {
+            return Err(LimitExceeded::SubmissionBytes(
+                self.limits.max_submission_bytes,
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn add_bytes(&self, bytes: u64) -> Result<(), LimitExceeded> {
+        let written = self.written.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        let total_written = self.total_written.fetch_add(bytes, Ordering::Relaxed) + bytes;
+
+        if written > self.limits.max_submission_bytes {
+            return Err(LimitExceeded::SubmissionBytes(
+                self.limits.max_submission_bytes,
+            ));
+        }
+        if written > RATIO_GRACE_BYTES
+            && self.archive_bytes > 0
+            && written / self.archive_bytes > self.limits.max_compression_ratio
+        {
+            return Err(LimitExceeded::CompressionRatio(
+                self.limits.max_compression_ratio,
+            ));
+        }
+        if total_written > self.limits.max_total_bytes {
+            return Err(LimitExceeded::TotalBytes(self.limits.max_total_bytes));
+        }
+
+        Ok(())
+    }
+}
+
+/// Counts every byte read against the budget, to stop a bomb while streaming
+struct Metered<'a, R: ?Sized> {
+    inner: &'a mut R,
+    budget: &'a Budget,
+}
+
+impl<R> Read for Metered<'_, R>
+where
+    R: Read + ?Sized,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.budget.add_bytes(n as u64).map_err(io::Error::other)?;
+        Ok(n)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkKind {
+    /// Target is relative to the directory of the link
+    Symbolic,
+    /// Target is relative to the root of the archive
+    Hard,
+}
+
+/// What was in the root of a [`SafeDest`] and spent of its budget at some point,
+/// see [`SafeDest::roll_back`]
+#[derive(Debug)]
+pub struct Checkpoint {
+    existing: HashSet<PathBuf>,
+    written: u64,
+    entries: u64,
+}
+
+/// Destination directory of an extraction
+///
+/// Every entry of an untrusted archive goes through here,
+/// so nothing can be written outside of `root`
+///
+/// We never create links, links pointing inside `root` are replaced by a copy of their target,
+/// so a later entry can't use them to escape either
+///
+/// Everything written is counted against the [`Budget`]
+#[derive(Debug)]
+pub struct SafeDest<'a> {
+    root: PathBuf,
+    budget: &'a Budget,
+}
+
+impl<'a> SafeDest<'a> {
+    pub fn new<P>(root: P, budget: &'a Budget) -> Result<Self>
+    where
+        P: AsRef<Path> + Debug,
+    {
+        let root = root.as_ref().to_owned();
+        fs::create_dir_all(&root).with_context(|| format!("unable to create {root:?}"))?;
+
+        Ok(Self { root, budget })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub const fn budget(&self) -> &'a Budget {
+        self.budget
+    }
+
+    /// Remembers what is in `root` and what the budget has spent so far
+    pub fn checkpoint(&self) -> Result<Checkpoint> {
+        let existing = WalkDir::new(&self.root)
+            .into_iter()
+            .map(|entry| entry.map(walkdir::DirEntry::into_path))
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("unable to read {:?}", self.root))?;
+
+        Ok(Checkpoint {
+            existing,
+            written: self.budget.written.load(Ordering::Relaxed),
+            entries: self.budget.entries.load(Ordering::Relaxed),
+        })
+    }
+
+    /// Removes everything written to `root` since the `checkpoint` and refunds it to the budget,
+    /// e.g. after an attempt with a wrong password
+    ///
+    /// Files, which existed before and were overwritten, can't be restored
+    pub fn roll_back(&self, checkpoint: &Checkpoint) -> Result<()> {
+        for entry in WalkDir::new(&self.root).contents_first(true) {
+            let entry = entry.with_context(|| format!("invalid entry in {:?}", self.root))?;
+            let path = entry.path();
+            if checkpoint.existing.contains(path) {
+                continue;
+            }
+            if entry.file_type().is_dir() {
+                fs::remove_dir(path)
+            } else {
+                fs::remove_file(path)
+            }
+            .with_context(|| format!("unable to remove {path:?}"))?;
+            trace!("removed {path:?}");
+        }
+
+        let written = self
+            .budget
+            .written
+            .swap(checkpoint.written, Ordering::Relaxed);
+        self.budget.total_written.fetch_sub(
+            written.saturating_sub(checkpoint.written),
+            Ordering::Relaxed,
+        );
+        self.budget
+            .entries
+            .store(checkpoint.entries, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    /// Maps the name of an archive entry to a path inside `root`
+    ///
+    /// Absolute names are rewritten to be relative to `root`,
+    /// `..` is resolved, but a name escaping `root` is an error
+    pub fn resolve<P>(&self, name: P) -> Result<PathBuf>
+    where
+        P: AsRef<Path> + Debug,
+    {
+        let name = name.as_ref();
+        if name.has_root() {
+            debug!("rewriting absolute entry {name:?} to be relative");
+        }
+
+        let relative = confine(name)
+            .with_context(|| format!("entry {name:?} points outside of {:?}", self.root))?;
+
+        Ok(self.root.join(relative))
+    }
+
+    #[instrument(skip(self))]
+    pub fn create_dir<P>(&self, name: P) -> Result<()>
+    where
+        P: AsRef<Path> + Debug,
+    {
+        let path = self.resolve(&name)?;
+        self.budget.add_entry()?;
+        fs::create_dir_all(&path).with_context(|| format!("unable to create dir {path:?}"))?;
+        trace!("created {path:?}");
+
+        Ok(())
+    }
+
+    #[instrument(skip(self, reader))]
+    pub fn write_file<P, R>(&self, name: P, reader: &mut R) -> Result<()>
+    where
+        P: AsRef<Path> + Debug,
+        R: Read + ?Sized,
+    {
+        let path = self.resolve(&name)?;
+        self.budget.add_entry()?;
+
+        if let Some(parent) = path.parent()
+            && !parent.exists()
+        {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("unable to create parent dir: {parent:?}"))?;
+            trace!("created parent");
+        }
+
+        unlink(&path)?;
+        let mut writer = BufWriter::new(
+            File::create(&path).with_context(|| format!("unable to create {path:?}"))?,
+        );
+        let mut metered = Metered {
+            inner: reader,
+            budget: self.budget,
+        };
+        io::copy(&mut metered, &mut writer).map_err(|e| match e.downcast::<LimitExceeded>() {
+            Ok(limit) => Report::new(limit),
+            Err(e) => Report::new(e).wrap_err(format!("unable to write {path:?}")),
+        })?;
+        writer
+            .flush()
+            .with_context(|| format!("unable to flush {path:?}"))?;
+        trace!("wrote {path:?}");
+
+        Ok(())
+    }
+
+    /// Sets the modification time of an already written entry
+    pub fn set_modified<P>(&self, name: P, modified: SystemTime) -> Result<()>
+    where
+        P: AsRef<Path> + Debug,
+    {
+        let path = self.resolve(&name)?;
+        File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(modified))
+            .with_context(|| format!("unable to set modification time of {path:?}"))
+    }
+
+    /// Checks, that a link stays inside of `root` and replaces it by a copy of its target
+    ///
+    /// If the target wasn't extracted (yet) or is a directory, the link is skipped
+    #[instrument(skip(self))]
+    pub fn link<P, Q>(&self, name: P, target: Q, kind: LinkKind) -> Result<()>
+    where
+        P: AsRef<Path> + Debug,
+        Q: AsRef<Path> + Debug,
+    {
+        let name = name.as_ref();
+        let target = target.as_ref();
+        let link_path = self.resolve(name)?;
+        self.budget.add_entry()?;
+
+        if target.has_root() {
+            bail!("link {name:?} points to absolute path {target:?}");
+        }
+
+        let relative_target = match kind {
+            LinkKind::Symbolic => confine(name)?
+                .parent()
+                .map_or_else(|| target.to_owned(), |parent| parent.join(target)),
+            LinkKind::Hard => target.to_owned(),
+        };
+        let relative_target = confine(&relative_target).with_context(|| {
+            format!(
+                "link {name:?} -> {target:?} points outside of {:?}",
+                self.root
+            )
+        })?;
+        let target_path = self.root.join(relative_target);
+
+        if !target_path.is_file() || target_path == link_path {
+            debug!("target {target_path:?} of link {name:?} is no extracted file, skipping");
+            return Ok(());
+        }
+
+        if let Some(parent) = link_path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("unable to create parent dir: {parent:?}"))?;
+        }
+        unlink(&link_path)?;
+        let copied = fs::copy(&target_path, &link_path)
+            .with_context(|| format!("unable to copy {target_path:?} to {link_path:?}"))?;
+        self.budget.add_bytes(copied)?;
+        trace!("replaced link {link_path:?} by a copy of {target_path:?}");
+
+        Ok(())
+    }
+}
+
+/// Removes the file at `path` before it is written,
+/// it may be a hard link (e.g. into a source dir), which must not be written through
+fn unlink(path: &Path) -> Result<()> {
+    if path.is_file() {
+        fs::remove_file(path).with_context(|| format!("unable to remove {path:?}"))?;
+    }
+
+    Ok(())
+}
+
+/// Normalizes `path` to a relative path without `.` and `..`,
+/// fails if the `..` would leave the starting directory
+fn confine(path: &Path) -> Result<PathBuf> {
+    let mut confined = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => {
+                if !confined.pop() {
+                    bail!("{path:?} escapes its root");
+                }
+            }
+            Component::Normal(part) => confined.push(part),
+        }
+    }
+
+    Ok(confined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helper;
+
+    #[test]
+    fn confine_resolves_inner_parent_dirs() {
+        assert_eq!(
+            confine(Path::new("a/./b/../c.java")).unwrap(),
+            Path::new("a/c.java")
+        );
+        assert_eq!(confine(Path::new("a/..")).unwrap(), Path::new(""));
+    }
+
+    #[test]
+    fn confine_rejects_escapes() {
+        assert!(confine(Path::new("../a.java")).is_err());
+        assert!(confine(Path::new("a/../../a.java")).is_err());
+        assert!(confine(Path::new("/../a.java")).is_err());
+    }
+
+    #[test]
+    fn resolve_rewrites_absolute_names() {
+        let budget = Budget::unlimited();
+        let root = helper::scratch_dir("resolve_absolute");
+        let dest = SafeDest::new(&root, &budget).unwrap();
+
+        assert_eq!(
+            dest.resolve("/etc/passwd").unwrap(),
+            root.join("etc/passwd")
+        );
+        assert!(dest.resolve("../passwd").is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn write_file_stays_inside_root() {
+        let budget = Budget::unlimited();
+        let parent = helper::scratch_dir("write_file_escape");
+        let dest = SafeDest::new(parent.join("root"), &budget).unwrap();
+
+        assert!(
+            dest.write_file("a/../../escaped.java", &mut b"class A {}".as_slice())
+                .is_err()
+        );
+        assert!(!parent.join("escaped.java").exists());
+
+        dest.write_file("a/../kept.java", &mut b"class A {}".as_slice())
+            .unwrap();
+        assert!(parent.join("root/kept.java").is_file());
+        fs::remove_dir_all(&parent).unwrap();
+    }
+
+    #[test]
+    fn link_is_replaced_by_a_copy() {
+        let budget = Budget::unlimited();
+        let parent = helper::scratch_dir("link_copy");
+        fs::write(parent.join("secret.txt"), "secret").unwrap();
+        let dest = SafeDest::new(parent.join("root"), &budget).unwrap();
+        dest.write_file("src/Main.java", &mut b"class Main {}".as_slice())
+            .unwrap();
+
+        dest.link("src/Copy.java", "Main.java", LinkKind::Symbolic)
+            .unwrap();
+        dest.link("Hard.java", "src/Main.java", LinkKind::Hard)
+            .unwrap();
+        for copy in ["src/Copy.java", "Hard.java"] {
+            let path = parent.join("root").join(copy);
+            assert!(!path.is_symlink());
+            assert_eq!(fs::read_to_string(path).unwrap(), "class Main {}");
+        }
+
+        assert!(
+            dest.link("src/secret.txt", "../../secret.txt", LinkKind::Symbolic)
+                .is_err()
+        );
+        assert!(
+            dest.link("secret.txt", "/etc/passwd", LinkKind::Symbolic)
+                .is_err()
+        );
+        assert!(!parent.join("root/src/secret.txt").exists());
+        assert!(!parent.join("root/secret.txt").exists());
+        fs::remove_dir_all(&parent).unwrap();
+    }
+
+    #[test]
+    fn budget_limits_entries() {
+        let budget = Budget::new(
+            Limits {
+                max_entries: 1,
+                ..Limits::UNLIMITED
+            },
+            0,
+            Arc::default(),
+        );
+        let root = helper::scratch_dir("budget_entries");
+        let dest = SafeDest::new(&root, &budget).unwrap();
+
+        dest.create_dir("a").unwrap();
+        let err = dest.create_dir("b").unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<LimitExceeded>(),
+            Some(LimitExceeded::Entries(1))
+        ));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,408 @@
+use crate::archive_handler::Registry;
+use crate::conf::config::{DuplicatePolicy, Layout};
+use crate::git::{self, Checkout};
+use crate::helper;
+use crate::layout::{self, MappedSubmission, Mapping, Streamed};
+use color_eyre::eyre::{Context, bail};
+use color_eyre::{Report, Result};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fmt::Debug;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tracing::{debug, info, instrument, trace};
+use walkdir::WalkDir;
+
+/// Dir in the tmp dir, where each source is extracted to its own numbered dir,
+/// before they are merged
+pub const STAGING_DIR_NAME: &str = ".sources";
+
+/// Appended to the name of the tmp dir, for the root of the old submissions
+const OLD_DIR_SUFFIX: &str = "_old";
+
+/// A source, extracted to its own dir in [`STAGING_DIR_NAME`]
+#[derive(Debug)]
+pub struct Staged {
+    /// The source as it was passed, e.g. `group1.zip`
+    pub origin: String,
+    pub dir: PathBuf,
+    pub streamed: Streamed,
+    /// Whether the submissions are plain files (e.g. checked out git repositories),
+    /// which are kept like loose files
+    pub loose_files: bool,
+    /// Submissions, which failed before they could be prepared (e.g. a broken repository)
+    pub failed: Vec<Report>,
+}
+
+/// Which source each submission came from, by the name of its dir in the tmp dir
+pub type Origins = HashMap<OsString, String>;
+
+/// A submission, which failed before it could be prepared (e.g. a broken repository)
+///
+/// It has no dir in the tmp dir, so its name may be taken by a submission of another source
+#[derive(Debug)]
+pub struct Failed {
+    pub origin: String,
+    pub err: Report,
+}
+
+/// All sources, merged into the tmp dir
+#[derive(Debug)]
+pub struct Merged {
+    pub streamed: Streamed,
+    /// The mappings of the layouts of all sources
+    pub mapping: Option<Mapping>,
+    /// Whether any source or its layout needs loose files to be kept
+    pub loose_files: bool,
+    /// What the layouts removed and how duplicates were resolved, for the summary
+    pub notes: Vec<String>,
+    pub origins: Origins,
+    /// The failed submissions of all sources
+    pub failed: Vec<Failed>,
+}
+
+/// The dir next to `tmp_dir`, which the old submissions are prepared in
+pub fn old_dir<P>(tmp_dir: P) -> Result<PathBuf>
+where
+    P: AsRef<Path> + Debug,
+{
+    helper::sibling_dir(tmp_dir, OLD_DIR_SUFFIX)
+}
+
+/// Extracts each of the `source_files` into its own dir in [`STAGING_DIR_NAME`] in `root`,
+/// see [`helper::extract_source`]
+#[instrument(skip(stream, checkout, registry))]
+pub fn stage<P>(
+    source_files: &[String],
+    root: P,
+    stream: bool,
+    checkout: &Checkout,
+    registry: &Registry,
+) -> Result<Vec<Staged>>
+where
+    P: AsRef<Path> + Debug,
+{
+    let mut staged = vec![];
+    for (i, source_file) in source_files.iter().enumerate() {
+        let dir = root.as_ref().join(STAGING_DIR_NAME).join(i.to_string());
+        debug!("extracting {source_file:?} to {dir:?}");
+        let extracted = helper::extract_source(source_file, &dir, stream, checkout, registry)
+            .with_context(|| format!("unable to extract {source_file:?} to {dir:?}"))?;
+        info!("extracted {source_file:?}");
+        let source_path = Path::new(source_file);
+        staged.push(Staged {
+            origin: source_file.clone(),
+            dir,
+            streamed: extracted.streamed,
+            loose_files: source_path.is_dir() && git::contains_only_repos(source_path)?,
+            failed: extracted.failed,
+        });
+    }
+
+    Ok(staged)
+}
+
+/// Applies the `layout` to every `staged` source
+/// and moves their submissions into `tmp_dir` in the order of the sources
+///
+/// A submission, whose name is already taken (by an earlier source or `origins`,
+/// the additional submissions), is handled according to `policy`
+#[instrument(skip(staged, origins, keep_non_ascii))]
+pub fn merge<P>(
+    tmp_dir: P,
+    staged: Vec<Staged>,
+    mut origins: Origins,
+    layout: Layout,
+    keep_non_ascii: bool,
+    policy: DuplicatePolicy,
+) -> Result<Merged>
+where
+    P: AsRef<Path> + Debug,
+{
+    let tmp_dir = tmp_dir.as_ref();
+
+    let mut streamed: Streamed = vec![];
+    let mut submissions: Vec<MappedSubmission> = vec![];
+    let mut loose_files = false;
+    let mut notes = vec![];
+    let mut failed = vec![];
+    for Staged {
+        origin,
+        dir,
+        streamed: staged_streamed,
+        loose_files: staged_loose_files,
+        failed: staged_failed,
+    } in staged
+    {
+        debug!("merging {origin:?}");
+        let applied = layout::apply(&dir, layout, keep_non_ascii, staged_streamed)
+            .with_context(|| format!("unable to apply the layout to {origin:?}"))?;
+        loose_files |= staged_loose_files || applied.loose_files;
+        notes.extend(
+            applied
+                .notes
+                .into_iter()
+                .map(|note| format!("{origin}: {note}")),
+        );
+        let mut mapped: HashMap<OsString, MappedSubmission> = applied
+            .mapping
+            .map(|mapping| mapping.submissions)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|submission| (OsString::from(&submission.id), submission))
+            .collect();
+        let mut incoming = applied.streamed;
+
+        let mut names = fs::read_dir(&dir)
+            .with_context(|| format!("unable to read {dir:?}"))?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("unable to read an entry in {dir:?}"))?;
+        names.sort();
+
+        for name in names {
+            let path = dir.join(&name);
+            let mut target_name = name.clone();
+
+            if tmp_dir.join(&name).exists() {
+                let previous = origins
+                    .get(&name)
+                    .map_or("an earlier source", String::as_str)
+                    .to_owned();
+                match policy {
+                    DuplicatePolicy::Error => bail!(
+                        "{name:?} is in {previous:?} and {origin:?}, \
+                        set the duplicate_policy to suffix or newest to resolve it"
+                    ),
+                    DuplicatePolicy::Suffix => {
+                        let mut suffix = 1;
+                        while tmp_dir.join(&target_name).exists() {
+                            target_name.clone_from(&name);
+                            target_name.push(format!("_{suffix}"));
+                            suffix += 1;
+                        }
+                        notes.push(format!(
+                            "{name:?} of {origin:?} is also in {previous:?}, \
+                            renamed it to {target_name:?}"
+                        ));
+                    }
+                    DuplicatePolicy::Newest => {
+                        let existing = tmp_dir.join(&name);
+                        if newest_modification(&path, &incoming)
+                            > newest_modification(&existing, &streamed)
+                        {
+                            trace!("replacing {existing:?} by {path:?}");
+                            helper::remove_entry(&existing)?;
+                            streamed.retain(|(archive, _, _)| !archive.starts_with(&existing));
+                            submissions.retain(|submission| name != submission.id.as_str());
+                            notes.push(format!(
+                                "{name:?} is in {previous:?} and {origin:?}, \
+                                kept the newer one of {origin:?}"
+                            ));
+                        } else {
+                            trace!("dropping {path:?}, {existing:?} is newer");
+                            helper::remove_entry(&path)?;
+                            incoming.retain(|(archive, _, _)| !archive.starts_with(&path));
+                            mapped.remove(&name);
+                            notes.push(format!(
+                                "{name:?} is in {previous:?} and {origin:?}, \
+                                kept the newer one of {previous:?}"
+                            ));
+                            continue;
+                        }
+                    }
+                }
+            }
+
+            let target = tmp_dir.join(&target_name);
+            trace!("moving {path:?} to {target:?}");
+            fs::rename(&path, &target)
+                .with_context(|| format!("unable to move {path:?} to {target:?}"))?;
+            for (archive, _, _) in &mut incoming {
+                if let Ok(relative) = archive.strip_prefix(&path) {
+                    *archive = target.join(relative);
+                }
+            }
+
+            if let Some(mut submission) = mapped.remove(&name) {
+                submission.id = target_name.to_string_lossy().into_owned();
+                submission.origin = Some(origin.clone());
+                submissions.push(submission);
+            }
+            origins.insert(target_name, origin.clone());
+        }
+
+        streamed.append(&mut incoming);
+        fs::remove_dir(&dir).with_context(|| format!("unable to remove {dir:?}"))?;
+
+        failed.extend(staged_failed.into_iter().map(|err| Failed {
+            origin: origin.clone(),
+            err,
+        }));
+    }
+
+    let staging_dir = tmp_dir.join(STAGING_DIR_NAME);
+    if staging_dir.exists() {
+        fs::remove_dir(&staging_dir)
+            .with_context(|| format!("unable to remove {staging_dir:?}"))?;
+    }
+    info!("merged all sources into {tmp_dir:?}");
+
+    submissions.sort_by(|a, b| a.id.cmp(&b.id));
+    let mapping = (!submissions.is_empty()).then_some(Mapping { submissions });
+
+    Ok(Merged {
+        streamed,
+        mapping,
+        loose_files,
+        notes,
+        origins,
+        failed,
+    })
+}
+
+/// The modification time of the newest file of the submission at `path`,
+/// including its archives, which are still in the source zip
+pub fn newest_modification(path: &Path, streamed: &Streamed) -> Option<SystemTime> {
+    let on_disk = WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok()?.modified().ok());
+    let in_source = streamed
+        .iter()
+        .filter(|(archive, _, _)| archive.starts_with(path))
+        .filter_map(|(_, _, entry)| entry.modified);
+
+    on_disk.chain(in_source).max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsStr;
+    use std::fs::File;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// Two sources with a submission `alice` each, the one of `newer` has the newer file
+    fn stage_duplicates(test: &str, newer: usize) -> (PathBuf, Vec<Staged>) {
+        let tmp_dir = helper::scratch_dir(test);
+        let staged = ["group1.zip", "group2.zip"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, origin)| {
+                let dir = tmp_dir.join(STAGING_DIR_NAME).join(i.to_string());
+                fs::create_dir_all(dir.join("alice")).unwrap();
+                fs::create_dir_all(dir.join(format!("bob{i}"))).unwrap();
+                let file_path = dir.join("alice/Main.java");
+                fs::write(&file_path, origin).unwrap();
+                let age = if i == newer { 0 } else { 3600 };
+                File::options()
+                    .write(true)
+                    .open(&file_path)
+                    .unwrap()
+                    .set_modified(UNIX_EPOCH + Duration::from_secs(1_700_000_000 - age))
+                    .unwrap();
+                Staged {
+                    origin: origin.to_owned(),
+                    dir,
+                    streamed: vec![],
+                    loose_files: false,
+                    failed: vec![],
+                }
+            })
+            .collect();
+
+        (tmp_dir, staged)
+    }
+
+    fn merge_with(tmp_dir: &Path, staged: Vec<Staged>, policy: DuplicatePolicy) -> Result<Merged> {
+        merge(
+            tmp_dir,
+            staged,
+            Origins::new(),
+            Layout::Plain,
+            false,
+            policy,
+        )
+    }
+
+    #[test]
+    fn duplicates_are_an_error() {
+        let (tmp_dir, staged) = stage_duplicates("merge_error", 1);
+
+        let err = merge_with(&tmp_dir, staged, DuplicatePolicy::Error).unwrap_err();
+
+        assert!(err.to_string().contains("group1.zip"), "{err:?}");
+        fs::remove_dir_all(&tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn duplicates_get_a_suffix() {
+        let (tmp_dir, staged) = stage_duplicates("merge_suffix", 1);
+
+        let merged = merge_with(&tmp_dir, staged, DuplicatePolicy::Suffix).unwrap();
+
+        let main = |name: &str| fs::read_to_string(tmp_dir.join(name).join("Main.java")).unwrap();
+        assert_eq!(main("alice"), "group1.zip");
+        assert_eq!(main("alice_1"), "group2.zip");
+        assert_eq!(merged.origins[OsStr::new("alice_1")], "group2.zip");
+        assert_eq!(merged.origins[OsStr::new("bob0")], "group1.zip");
+        assert_eq!(merged.notes.len(), 1, "{:?}", merged.notes);
+        assert!(!tmp_dir.join(STAGING_DIR_NAME).exists());
+        fs::remove_dir_all(&tmp_dir).unwrap();
+    }
+
+    #[test]
+    fn duplicates_keep_the_newest() {
+        for newer in [0, 1] {
+            let (tmp_dir, staged) = stage_duplicates("merge_newest", newer);
+
+            let merged = merge_with(&tmp_dir, staged, DuplicatePolicy::Newest).unwrap();
+
+            let expected = ["group1.zip", "group2.zip"][newer];
+            assert_eq!(
+                fs::read_to_string(tmp_dir.join("alice/Main.java")).unwrap(),
+                expected
+            );
+            assert_eq!(merged.origins[OsStr::new("alice")], expected);
+            assert!(!tmp_dir.join("alice_1").exists());
+            assert!(tmp_dir.join("bob0").is_dir() && tmp_dir.join("bob1").is_dir());
+            fs::remove_dir_all(&tmp_dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn old_submissions_are_prepared_next_to_the_tmp_dir() {
+        let dir = helper::scratch_dir("old_submissions");
+        let tmp_dir = dir.join("tmp");
+        let source = dir.join("last_year");
+        fs::create_dir_all(source.join("carol")).unwrap();
+        fs::write(source.join("carol/Main.java"), "class Main {}").unwrap();
+
+        let old_dir = old_dir(&tmp_dir).unwrap();
+        assert_eq!(old_dir, dir.join("tmp_old"));
+        let staged = stage(
+            &[source.to_string_lossy().into_owned()],
+            &old_dir,
+            false,
+            &Checkout::default(),
+            &Registry::builtin(),
+        )
+        .unwrap();
+        let merged = merge_with(&old_dir, staged, DuplicatePolicy::Error).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(old_dir.join("carol/Main.java")).unwrap(),
+            "class Main {}"
+        );
+        assert_eq!(
+            merged.origins[OsStr::new("carol")],
+            source.to_string_lossy()
+        );
+        assert!(!tmp_dir.exists());
+        assert!(source.join("carol/Main.java").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}